@@ -0,0 +1,87 @@
+use actix_web::{
+    error::ResponseError,
+    get,
+    http::{header::ContentType, StatusCode},
+    web::Data,
+    web::{Json, Path, Query},
+    HttpResponse,
+};
+use chrono::Utc;
+use chrono_tz::Europe::Zurich;
+use derive_more::Display;
+use serde::Deserialize;
+
+use crate::{
+    model::{bitfield::Bitfield, calendar::ServiceCalendar, information::Information},
+    repository::{database::RepositoryError, storage::TripRepository},
+};
+
+#[derive(Deserialize)]
+pub struct BitfieldIdentifier {
+    id: String,
+}
+
+#[derive(Deserialize)]
+pub struct BitfieldSelector {
+    // timetable period of the bitfield, the current one by default
+    information: Option<i32>,
+}
+
+#[derive(Debug, Display)]
+pub enum BitfieldError {
+    BitfieldNotFound,
+    DatabaseUnavailable,
+    DatabaseError,
+    BadBitfieldRequest,
+}
+
+impl ResponseError for BitfieldError {
+    fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
+        HttpResponse::build(self.status_code())
+            .insert_header(ContentType::json())
+            .body(self.to_string())
+    }
+
+    fn status_code(&self) -> StatusCode {
+        match self {
+            BitfieldError::BitfieldNotFound => StatusCode::NOT_FOUND,
+            BitfieldError::DatabaseUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            BitfieldError::DatabaseError => StatusCode::INTERNAL_SERVER_ERROR,
+            BitfieldError::BadBitfieldRequest => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+impl From<RepositoryError> for BitfieldError {
+    fn from(error: RepositoryError) -> Self {
+        match error {
+            RepositoryError::NotFound => BitfieldError::BitfieldNotFound,
+            RepositoryError::Unavailable => BitfieldError::DatabaseUnavailable,
+            RepositoryError::Failed => BitfieldError::DatabaseError,
+        }
+    }
+}
+
+#[get("/bitfield/{id}")]
+pub async fn get_bitfield(
+    identifier: Path<BitfieldIdentifier>,
+    repository: Data<dyn TripRepository>,
+    info: Query<BitfieldSelector>,
+) -> Result<Json<ServiceCalendar>, BitfieldError> {
+    let id: Result<i32, std::num::ParseIntError> = identifier.into_inner().id.parse::<i32>();
+    if id.is_err() {
+        return Err(BitfieldError::BadBitfieldRequest);
+    }
+
+    let information: Information = match info.information {
+        Some(information_id) => repository.get_information_by_id(information_id).await?,
+        None => {
+            repository
+                .get_information(Utc::now().with_timezone(&Zurich).date_naive())
+                .await?
+        }
+    };
+    let bitfield: Bitfield = repository.get_bitfield(information.id, id.unwrap()).await?;
+
+    Ok(Json(ServiceCalendar::from_bitfield(&bitfield, &information)))
+}
@@ -0,0 +1,285 @@
+use std::collections::{hash_map::Entry, HashMap};
+
+use actix_web::{
+    error::ResponseError,
+    get,
+    http::{header::ContentType, StatusCode},
+    web::Data,
+    web::Path,
+    web::{Json, Query},
+    HttpResponse,
+};
+use chrono::{NaiveTime, Utc};
+use derive_more::Display;
+use serde::Deserialize;
+
+use crate::{
+    model::{arrival::Arrival, departure::Departure, line::Line, trip::Trip, trip_stop::TripStop},
+    repository::{
+        database::RepositoryError,
+        storage::{BoardFilter, LineRepository, StopRepository, TripRepository},
+    },
+};
+
+use super::trip::{service_day, TripError};
+
+const DEFAULT_LIMIT: i64 = 10;
+const MAX_LIMIT: i64 = 50;
+
+#[derive(Deserialize)]
+pub struct StopIdentifier {
+    id: String,
+}
+
+#[derive(Deserialize)]
+pub struct BoardSelector {
+    // unix timestamp, now when left out
+    time: Option<i64>,
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Display)]
+pub enum BoardError {
+    StopNotFound,
+    LineNotFound,
+    DatabaseUnavailable,
+    DatabaseError,
+    BadBoardRequest,
+    InvalidTimePeriod,
+}
+
+impl ResponseError for BoardError {
+    fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
+        HttpResponse::build(self.status_code())
+            .insert_header(ContentType::json())
+            .body(self.to_string())
+    }
+
+    fn status_code(&self) -> StatusCode {
+        match self {
+            BoardError::StopNotFound => StatusCode::NOT_FOUND,
+            BoardError::DatabaseUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            BoardError::DatabaseError => StatusCode::INTERNAL_SERVER_ERROR,
+            // trips always run on a line, a missing one is broken data
+            BoardError::LineNotFound => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+impl From<RepositoryError> for BoardError {
+    fn from(error: RepositoryError) -> Self {
+        match error {
+            RepositoryError::NotFound => BoardError::StopNotFound,
+            RepositoryError::Unavailable => BoardError::DatabaseUnavailable,
+            RepositoryError::Failed => BoardError::DatabaseError,
+        }
+    }
+}
+
+// lines and stop names, boards repeat the same few of them
+#[derive(Default)]
+struct Labels {
+    lines: HashMap<i32, Line>,
+    stop_names: HashMap<i32, String>,
+}
+
+impl Labels {
+    async fn line(&mut self, repository: &dyn LineRepository, id: i32) -> Result<&Line, BoardError> {
+        if let Entry::Vacant(entry) = self.lines.entry(id) {
+            let line: Line = match repository.get_line(id).await {
+                Ok(line) => line,
+                Err(RepositoryError::NotFound) => return Err(BoardError::LineNotFound),
+                Err(error) => return Err(error.into()),
+            };
+            entry.insert(line);
+        }
+
+        Ok(&self.lines[&id])
+    }
+
+    async fn stop_name(&mut self, repository: &dyn StopRepository, id: i32) -> Result<String, BoardError> {
+        if let Entry::Vacant(entry) = self.stop_names.entry(id) {
+            let name: String = match repository.get_stop(id).await {
+                Ok(stop) => stop.name,
+                Err(RepositoryError::NotFound) => String::new(),
+                Err(error) => return Err(error.into()),
+            };
+            entry.insert(name);
+        }
+
+        Ok(self.stop_names[&id].clone())
+    }
+}
+
+// stop of the board and the filters of the time and limit asked for, on the service day of the time and on
+// the day before, whose trips running past midnight still pass by
+async fn board_filter(
+    identifier: Path<StopIdentifier>,
+    trips: &dyn TripRepository,
+    stops: &dyn StopRepository,
+    info: &BoardSelector,
+) -> Result<(BoardFilter, Option<BoardFilter>), BoardError> {
+    let id: Result<i32, std::num::ParseIntError> = identifier.into_inner().id.parse::<i32>();
+    if id.is_err() {
+        return Err(BoardError::BadBoardRequest);
+    }
+
+    let limit: i64 = info.limit.unwrap_or(DEFAULT_LIMIT);
+    if !(1..=MAX_LIMIT).contains(&limit) {
+        return Err(BoardError::BadBoardRequest);
+    }
+
+    let stop_id: i32 = stops.get_stop(id.unwrap()).await?.id;
+    let timestamp: i64 = info.time.unwrap_or(Utc::now().timestamp());
+    let (information, date, day_index) = service_day(trips, timestamp).await?;
+
+    // times past midnight are stored without their day, 00:30 of the day before is 24:30 of its service day
+    let previous: Option<BoardFilter> = match service_day(trips, timestamp - 86_400).await {
+        Ok((information, _, day_index)) => Some(BoardFilter {
+            information_id: information.id,
+            day_index: day_index as i32,
+            stop_id,
+            from: date.time(),
+            limit,
+            past_midnight: true,
+        }),
+        Err(TripError::InvalidTimePeriod) => None,
+        Err(error) => return Err(error.into()),
+    };
+
+    let filter: BoardFilter = BoardFilter {
+        information_id: information.id,
+        day_index: day_index as i32,
+        stop_id,
+        from: date.time(),
+        limit,
+        past_midnight: false,
+    };
+    Ok((filter, previous))
+}
+
+// trip stops of the day's trips and of the day before's trips past midnight, with their trips, by time of
+// the day
+async fn merge_days(
+    trips: &dyn TripRepository,
+    day: Vec<TripStop>,
+    previous: Vec<TripStop>,
+    time: fn(&TripStop) -> Option<NaiveTime>,
+    limit: i64,
+) -> Result<Vec<(TripStop, Trip)>, BoardError> {
+    let mut board: Vec<(TripStop, Trip)> = Vec::new();
+
+    for trip_stop in day.into_iter().chain(previous) {
+        let trip: Trip = trips.get_trip(trip_stop.trip_id).await?;
+        board.push((trip_stop, trip));
+    }
+
+    board.sort_by_key(|(trip_stop, _)| time(trip_stop));
+    board.truncate(limit as usize);
+    return Ok(board);
+}
+
+impl From<TripError> for BoardError {
+    fn from(error: TripError) -> Self {
+        match error {
+            TripError::DatabaseUnavailable => BoardError::DatabaseUnavailable,
+            TripError::DatabaseError => BoardError::DatabaseError,
+            TripError::InvalidTimePeriod => BoardError::InvalidTimePeriod,
+            _ => BoardError::BadBoardRequest,
+        }
+    }
+}
+
+#[get("/stop/{id}/departures")]
+pub async fn get_stop_departures(
+    identifier: Path<StopIdentifier>,
+    trips: Data<dyn TripRepository>,
+    lines: Data<dyn LineRepository>,
+    stops: Data<dyn StopRepository>,
+    info: Query<BoardSelector>,
+) -> Result<Json<Vec<Departure>>, BoardError> {
+    let (filter, previous) = board_filter(identifier, trips.get_ref(), stops.get_ref(), &info).await?;
+    let limit: i64 = filter.limit;
+    let trip_stops: Vec<TripStop> = trips.get_departures(filter).await?;
+    let previous_trip_stops: Vec<TripStop> = match previous {
+        Some(previous) => trips.get_departures(previous).await?,
+        None => Vec::new(),
+    };
+    let board: Vec<(TripStop, Trip)> = merge_days(
+        trips.get_ref(),
+        trip_stops,
+        previous_trip_stops,
+        |trip_stop| trip_stop.departure_time,
+        limit,
+    )
+    .await?;
+
+    let mut labels: Labels = Labels::default();
+    let mut departures: Vec<Departure> = Vec::new();
+
+    for (trip_stop, trip) in board {
+        let headsign: String = labels.stop_name(stops.get_ref(), trip.destination_id).await?;
+        let line: &Line = labels.line(lines.get_ref(), trip.line_id).await?;
+
+        departures.push(Departure {
+            trip_id: trip.id,
+            line_id: line.id,
+            line_name: line.name.clone(),
+            line_color: line.color.clone(),
+            headsign,
+            destination_id: trip.destination_id,
+            departure_time: trip_stop.departure_time.unwrap(),
+            platform: trip_stop.platform,
+        });
+    }
+
+    Ok(Json(departures))
+}
+
+#[get("/stop/{id}/arrivals")]
+pub async fn get_stop_arrivals(
+    identifier: Path<StopIdentifier>,
+    trips: Data<dyn TripRepository>,
+    lines: Data<dyn LineRepository>,
+    stops: Data<dyn StopRepository>,
+    info: Query<BoardSelector>,
+) -> Result<Json<Vec<Arrival>>, BoardError> {
+    let (filter, previous) = board_filter(identifier, trips.get_ref(), stops.get_ref(), &info).await?;
+    let (stop_id, limit): (i32, i64) = (filter.stop_id, filter.limit);
+    let trip_stops: Vec<TripStop> = trips.get_arrivals(filter).await?;
+    let previous_trip_stops: Vec<TripStop> = match previous {
+        Some(previous) => trips.get_arrivals(previous).await?,
+        None => Vec::new(),
+    };
+    let board: Vec<(TripStop, Trip)> = merge_days(
+        trips.get_ref(),
+        trip_stops,
+        previous_trip_stops,
+        |trip_stop| trip_stop.arrival_time,
+        limit,
+    )
+    .await?;
+
+    let mut labels: Labels = Labels::default();
+    let mut arrivals: Vec<Arrival> = Vec::new();
+
+    for (trip_stop, trip) in board {
+        let origin: String = labels.stop_name(stops.get_ref(), trip.origin_id).await?;
+        let line: &Line = labels.line(lines.get_ref(), trip.line_id).await?;
+
+        arrivals.push(Arrival {
+            trip_id: trip.id,
+            line_id: line.id,
+            line_name: line.name.clone(),
+            line_color: line.color.clone(),
+            origin,
+            origin_id: trip.origin_id,
+            arrival_time: trip_stop.arrival_time.unwrap(),
+            platform: trip_stop.platform,
+            terminates: trip.destination_id == stop_id,
+        });
+    }
+
+    Ok(Json(arrivals))
+}
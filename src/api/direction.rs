@@ -12,7 +12,7 @@ use serde::Deserialize;
 
 use crate::{
     model::{direction::Direction, direction_leg::DirectionLeg, leg_step::LegStep},
-    repository::database::{Database, Table},
+    repository::{database::RepositoryError, storage::DirectionRepository},
 };
 
 #[derive(Deserialize)]
@@ -23,6 +23,8 @@ pub struct DirectionIdentifier {
 #[derive(Debug, Display)]
 pub enum DirectionError {
     DirectionNotFound,
+    DatabaseUnavailable,
+    DatabaseError,
     BadDirectionRequest,
 }
 
@@ -36,94 +38,68 @@ impl ResponseError for DirectionError {
     fn status_code(&self) -> StatusCode {
         match self {
             DirectionError::DirectionNotFound => StatusCode::NOT_FOUND,
+            DirectionError::DatabaseUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            DirectionError::DatabaseError => StatusCode::INTERNAL_SERVER_ERROR,
             _ => StatusCode::BAD_REQUEST,
         }
     }
 }
 
+impl From<RepositoryError> for DirectionError {
+    fn from(error: RepositoryError) -> Self {
+        match error {
+            RepositoryError::NotFound => DirectionError::DirectionNotFound,
+            RepositoryError::Unavailable => DirectionError::DatabaseUnavailable,
+            RepositoryError::Failed => DirectionError::DatabaseError,
+        }
+    }
+}
+
 #[get("/direction/{id}")]
 pub async fn get_direction(
     identifier: Path<DirectionIdentifier>,
-    database: Data<Database>,
+    repository: Data<dyn DirectionRepository>,
 ) -> Result<Json<Direction>, DirectionError> {
-    let id: Result<i32, std::num::ParseIntError> = identifier.into_inner().id.parse::<i32>();
+    let id: Result<i64, std::num::ParseIntError> = identifier.into_inner().id.parse::<i64>();
     if id.is_err() {
         return Err(DirectionError::BadDirectionRequest);
     }
 
-    let direction: Option<Direction> = database
-        .get_one::<Direction>(
-            sqlx::query_as::<_, Direction>(
-                format!("SELECT * FROM {} WHERE id=$1", Direction::TABLE_NAME).as_str(),
-            )
-            .bind(id.unwrap()),
-        )
-        .await;
-
-    match direction {
-        Some(direction) => Ok(Json(direction)),
-        None => Err(DirectionError::DirectionNotFound),
-    }
+    let direction: Direction = repository.get_direction(id.unwrap()).await?;
+
+    Ok(Json(direction))
 }
 
 #[get("/direction/{id}/legs")]
 pub async fn get_direction_legs(
     identifier: Path<DirectionIdentifier>,
-    database: Data<Database>,
+    repository: Data<dyn DirectionRepository>,
 ) -> Result<Json<Vec<DirectionLeg>>, DirectionError> {
-    let id: Result<i32, std::num::ParseIntError> = identifier.into_inner().id.parse::<i32>();
+    let id: Result<i64, std::num::ParseIntError> = identifier.into_inner().id.parse::<i64>();
     if id.is_err() {
         return Err(DirectionError::BadDirectionRequest);
     }
 
-    let direction_legs: Option<Vec<DirectionLeg>> = database
-        .get_many::<DirectionLeg>(
-            sqlx::query_as::<_, DirectionLeg>(
-                format!("SELECT * FROM {} WHERE direction_id=$1", DirectionLeg::TABLE_NAME).as_str(),
-            )
-            .bind(id.unwrap()),
-        )
-        .await;
-
-    match direction_legs {
-        Some(direction_legs) => Ok(Json(direction_legs)),
-        None => Err(DirectionError::DirectionNotFound),
-    }
+    let direction_legs: Vec<DirectionLeg> = repository.get_direction_legs(id.unwrap()).await?;
+
+    Ok(Json(direction_legs))
 }
 
 #[get("/direction/{id}/legs/steps")]
 pub async fn get_direction_leg_steps(
     identifier: Path<DirectionIdentifier>,
-    database: Data<Database>,
+    repository: Data<dyn DirectionRepository>,
 ) -> Result<Json<Vec<LegStep>>, DirectionError> {
-    let id: Result<i32, std::num::ParseIntError> = identifier.into_inner().id.parse::<i32>();
+    let id: Result<i64, std::num::ParseIntError> = identifier.into_inner().id.parse::<i64>();
     if id.is_err() {
         return Err(DirectionError::BadDirectionRequest);
     }
 
-    let direction_legs: Option<Vec<DirectionLeg>> = database
-        .get_many::<DirectionLeg>(
-            sqlx::query_as::<_, DirectionLeg>(
-                format!("SELECT * FROM {} WHERE direction_id=$1", DirectionLeg::TABLE_NAME).as_str(),
-            )
-            .bind(id.unwrap()),
-        )
-        .await;
-
-    if direction_legs.is_none() {
-        return Err(DirectionError::DirectionNotFound);
-    }
+    let direction_legs: Vec<DirectionLeg> = repository.get_direction_legs(id.unwrap()).await?;
 
     let mut leg_steps: Vec<LegStep> = Vec::new();
-    for leg in direction_legs.unwrap() {
-        let lsteps: Vec<LegStep> = database
-            .get_many::<LegStep>(
-                sqlx::query_as::<_, LegStep>(
-                    format!("SELECT * FROM {} WHERE leg_id=$1", LegStep::TABLE_NAME).as_str(),
-                )
-                .bind(leg.id),
-            )
-            .await.unwrap();
+    for leg in direction_legs {
+        let lsteps: Vec<LegStep> = repository.get_leg_steps(leg.id).await?;
         leg_steps.extend(lsteps);
     }
 
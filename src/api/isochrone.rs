@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+
+use actix_web::{
+    error::ResponseError,
+    get,
+    http::{header::ContentType, StatusCode},
+    web::Data,
+    web::{Json, Query},
+    Either, HttpResponse,
+};
+use chrono::{Duration, Timelike, Utc};
+use derive_more::Display;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{
+    model::isochrone::{to_geojson, ReachableStop},
+    repository::{
+        calibration::seconds_to_time,
+        database::RepositoryError,
+        storage::{StopRepository, TripRepository},
+    },
+};
+
+use super::journey::{day_planner, JourneyError};
+
+const DEFAULT_MINUTES: i32 = 30;
+const MAX_MINUTES: i32 = 180;
+const MAX_BANDS: i32 = 12;
+
+#[derive(Deserialize)]
+pub struct IsochroneSelector {
+    from: i32,
+    // unix timestamp, now when left out
+    time: Option<i64>,
+    minutes: Option<i32>,
+    // geojson for polygons, a stop list otherwise
+    format: Option<String>,
+    // nested areas of the polygons
+    bands: Option<i32>,
+}
+
+#[derive(Debug, Display)]
+pub enum IsochroneError {
+    StopNotFound,
+    DatabaseUnavailable,
+    DatabaseError,
+    BadIsochroneRequest,
+    InvalidTimePeriod,
+}
+
+impl ResponseError for IsochroneError {
+    fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
+        HttpResponse::build(self.status_code())
+            .insert_header(ContentType::json())
+            .body(self.to_string())
+    }
+
+    fn status_code(&self) -> StatusCode {
+        match self {
+            IsochroneError::StopNotFound => StatusCode::NOT_FOUND,
+            IsochroneError::DatabaseUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            IsochroneError::DatabaseError => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+impl From<RepositoryError> for IsochroneError {
+    fn from(error: RepositoryError) -> Self {
+        match error {
+            RepositoryError::NotFound => IsochroneError::StopNotFound,
+            RepositoryError::Unavailable => IsochroneError::DatabaseUnavailable,
+            RepositoryError::Failed => IsochroneError::DatabaseError,
+        }
+    }
+}
+
+impl From<JourneyError> for IsochroneError {
+    fn from(error: JourneyError) -> Self {
+        match error {
+            JourneyError::StopNotFound => IsochroneError::StopNotFound,
+            JourneyError::DatabaseUnavailable => IsochroneError::DatabaseUnavailable,
+            JourneyError::DatabaseError => IsochroneError::DatabaseError,
+            JourneyError::InvalidTimePeriod => IsochroneError::InvalidTimePeriod,
+            JourneyError::BadJourneyRequest => IsochroneError::BadIsochroneRequest,
+        }
+    }
+}
+
+#[get("/isochrone")]
+pub async fn get_isochrone(
+    trips: Data<dyn TripRepository>,
+    stops: Data<dyn StopRepository>,
+    info: Query<IsochroneSelector>,
+) -> Result<Either<Json<Vec<ReachableStop>>, Json<Value>>, IsochroneError> {
+    let minutes: i32 = info.minutes.unwrap_or(DEFAULT_MINUTES);
+    let bands: i32 = info.bands.unwrap_or(1);
+    let geojson: bool = match info.format.as_deref() {
+        None | Some("json") => false,
+        Some("geojson") => true,
+        Some(_) => return Err(IsochroneError::BadIsochroneRequest),
+    };
+    if !(1..=MAX_MINUTES).contains(&minutes) || !(1..=MAX_BANDS).contains(&bands) {
+        return Err(IsochroneError::BadIsochroneRequest);
+    }
+
+    stops.get_stop(info.from).await?;
+
+    let duration: i32 = minutes * 60;
+    let (planner, all_stops, time) = day_planner(
+        trips.get_ref(),
+        stops.get_ref(),
+        info.time.unwrap_or(Utc::now().timestamp()),
+        Some(Duration::seconds(duration as i64)),
+    )
+    .await?;
+
+    let durations: HashMap<i32, i32> = planner.reachable(info.from, time, duration);
+    let start: i32 = time.num_seconds_from_midnight() as i32;
+    let mut reachable: Vec<ReachableStop> = all_stops
+        .into_iter()
+        .filter_map(|stop| {
+            let duration: i32 = *durations.get(&stop.id)?;
+            Some(ReachableStop {
+                stop_id: stop.id,
+                name: stop.name,
+                latitude: stop.latitude,
+                longitude: stop.longitude,
+                arrival_time: seconds_to_time(start + duration),
+                duration,
+            })
+        })
+        .collect();
+    reachable.sort_by_key(|stop| (stop.duration, stop.stop_id));
+
+    if geojson {
+        return Ok(Either::Right(Json(to_geojson(&reachable, duration, bands))));
+    }
+
+    Ok(Either::Left(Json(reachable)))
+}
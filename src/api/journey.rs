@@ -0,0 +1,324 @@
+use actix_web::{
+    error::ResponseError,
+    get,
+    http::{header::ContentType, StatusCode},
+    web::Data,
+    web::{Json, Query},
+    HttpResponse,
+};
+use chrono::{Duration, NaiveTime, Utc};
+use derive_more::Display;
+use serde::Deserialize;
+
+use crate::{
+    model::{journey::Journey, stop::Stop, trip::Trip, trip_stop::TripStop},
+    repository::{
+        database::RepositoryError,
+        planner::Planner,
+        storage::{StopRepository, TripFilter, TripRepository},
+    },
+};
+
+use super::trip::{previous_day_filter, service_day, TripError};
+
+const DEFAULT_LIMIT: usize = 3;
+const MAX_LIMIT: usize = 5;
+
+#[derive(Deserialize)]
+pub struct JourneySelector {
+    from: i32,
+    to: i32,
+    // unix timestamp, now when left out
+    time: Option<i64>,
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Display)]
+pub enum JourneyError {
+    StopNotFound,
+    DatabaseUnavailable,
+    DatabaseError,
+    BadJourneyRequest,
+    InvalidTimePeriod,
+}
+
+impl ResponseError for JourneyError {
+    fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
+        HttpResponse::build(self.status_code())
+            .insert_header(ContentType::json())
+            .body(self.to_string())
+    }
+
+    fn status_code(&self) -> StatusCode {
+        match self {
+            JourneyError::StopNotFound => StatusCode::NOT_FOUND,
+            JourneyError::DatabaseUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            JourneyError::DatabaseError => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+impl From<RepositoryError> for JourneyError {
+    fn from(error: RepositoryError) -> Self {
+        match error {
+            RepositoryError::NotFound => JourneyError::StopNotFound,
+            RepositoryError::Unavailable => JourneyError::DatabaseUnavailable,
+            RepositoryError::Failed => JourneyError::DatabaseError,
+        }
+    }
+}
+
+impl From<TripError> for JourneyError {
+    fn from(error: TripError) -> Self {
+        match error {
+            TripError::DatabaseUnavailable => JourneyError::DatabaseUnavailable,
+            TripError::DatabaseError => JourneyError::DatabaseError,
+            TripError::InvalidTimePeriod => JourneyError::InvalidTimePeriod,
+            _ => JourneyError::BadJourneyRequest,
+        }
+    }
+}
+
+// planner over the trips of the day running between the times, with every stop for the walks
+pub async fn day_planner(
+    trips: &dyn TripRepository,
+    stops: &dyn StopRepository,
+    timestamp: i64,
+    until: Option<Duration>,
+) -> Result<(Planner, Vec<Stop>, NaiveTime), JourneyError> {
+    let (information, date, day_index) = service_day(trips, timestamp).await?;
+
+    // trips of the day still running at the time, the ones already on their way included
+    let last: NaiveTime = NaiveTime::from_hms_opt(23, 59, 59).unwrap();
+    let departure_until: NaiveTime = match until {
+        Some(until) if (date.time() + until) > date.time() => date.time() + until,
+        _ => last,
+    };
+    let filter: TripFilter = TripFilter {
+        information_id: information.id,
+        day_index: day_index as i32,
+        departure_from: NaiveTime::MIN,
+        departure_until,
+        arrival_from: date.time(),
+        past_midnight: false,
+    };
+    // and the ones of the day before still running past midnight
+    let previous: Option<TripFilter> = previous_day_filter(trips, timestamp, &filter).await?;
+
+    let mut day_trips: Vec<Trip> = trips.get_trips(filter.clone()).await?;
+    let trip_stops: Vec<TripStop> = trips.get_filtered_trip_stops(filter).await?;
+    let mut previous_trip_stops: Vec<TripStop> = Vec::new();
+    if let Some(previous) = previous {
+        day_trips.extend(trips.get_trips(previous.clone()).await?);
+        previous_trip_stops = trips.get_filtered_trip_stops(previous).await?;
+    }
+    let all_stops: Vec<Stop> = stops.get_stops().await?;
+
+    let planner: Planner = Planner::new(&day_trips, &trip_stops, &previous_trip_stops, &all_stops);
+
+    Ok((planner, all_stops, date.time()))
+}
+
+#[get("/journeys")]
+pub async fn get_journeys(
+    trips: Data<dyn TripRepository>,
+    stops: Data<dyn StopRepository>,
+    info: Query<JourneySelector>,
+) -> Result<Json<Vec<Journey>>, JourneyError> {
+    let limit: usize = info.limit.unwrap_or(DEFAULT_LIMIT);
+    if !(1..=MAX_LIMIT).contains(&limit) || info.from == info.to {
+        return Err(JourneyError::BadJourneyRequest);
+    }
+
+    stops.get_stop(info.from).await?;
+    stops.get_stop(info.to).await?;
+
+    let (planner, _, time) = day_planner(
+        trips.get_ref(),
+        stops.get_ref(),
+        info.time.unwrap_or(Utc::now().timestamp()),
+        None,
+    )
+    .await?;
+
+    Ok(Json(planner.plan(info.from, info.to, time, limit)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use async_trait::async_trait;
+    use chrono::NaiveDate;
+
+    use crate::{
+        model::{
+            bitfield::Bitfield, information::Information, journey::JourneyLeg, line::TransportMode,
+            types::Direction,
+        },
+        repository::storage::{BoardFilter, Bounds},
+    };
+
+    use super::*;
+
+    const A: i32 = 1;
+    const B: i32 = 2;
+    const C: i32 = 3;
+    // 2023-12-12 00:05 in Zurich
+    const AFTER_MIDNIGHT: i64 = 1_702_335_900;
+
+    fn time(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    // a week of timetable whose only trip leaves a on monday evening and reaches c past midnight
+    struct Timetable {
+        information: Information,
+        trip: Trip,
+        trip_stops: Vec<TripStop>,
+    }
+
+    impl Timetable {
+        fn new() -> Timetable {
+            let information: Information = Information {
+                id: 1,
+                start_date: NaiveDate::from_ymd_opt(2023, 12, 11).unwrap(),
+                end_date: NaiveDate::from_ymd_opt(2023, 12, 17).unwrap(),
+            };
+            let trip: Trip = Trip {
+                id: 1,
+                journey_number: 1,
+                option_count: 0,
+                shape_id: None,
+                direction_id: None,
+                transport_mode: TransportMode::Bus,
+                origin_id: A,
+                destination_id: C,
+                information_id: 1,
+                bitfield_id: 1,
+                line_id: 1,
+                direction: Direction::Outward,
+                departure_time: time(23, 50),
+                arrival_time: time(0, 20),
+            };
+            let trip_stops: Vec<TripStop> = [(A, time(23, 50)), (B, time(0, 10)), (C, time(0, 20))]
+                .iter()
+                .enumerate()
+                .map(|(k, (stop_id, time))| TripStop {
+                    id: k as i64,
+                    stop_id: *stop_id,
+                    trip_id: 1,
+                    sequence: k as i16 + 1,
+                    arrival_time: Some(*time),
+                    departure_time: Some(*time),
+                    platform: None,
+                })
+                .collect();
+
+            Timetable { information, trip, trip_stops }
+        }
+
+        fn runs(&self, filter: &TripFilter) -> bool {
+            let monday: NaiveDate = self.information.start_date;
+            let past_midnight: bool = self.trip.arrival_time < self.trip.departure_time;
+
+            return Bitfield::day_index(&self.information, monday) == Some(filter.day_index as usize)
+                && (filter.departure_from..=filter.departure_until).contains(&self.trip.departure_time)
+                && ((past_midnight == filter.past_midnight && self.trip.arrival_time >= filter.arrival_from)
+                    || (!filter.past_midnight && past_midnight));
+        }
+    }
+
+    #[async_trait]
+    impl TripRepository for Timetable {
+        async fn get_information(&self, _: NaiveDate) -> Result<Information, RepositoryError> {
+            Ok(self.information.clone())
+        }
+
+        async fn get_information_by_id(&self, _: i32) -> Result<Information, RepositoryError> {
+            Ok(self.information.clone())
+        }
+
+        async fn get_bitfield(&self, _: i32, _: i32) -> Result<Bitfield, RepositoryError> {
+            Err(RepositoryError::NotFound)
+        }
+
+        async fn get_trips(&self, filter: TripFilter) -> Result<Vec<Trip>, RepositoryError> {
+            Ok(if self.runs(&filter) { vec![self.trip.clone()] } else { Vec::new() })
+        }
+
+        async fn get_trip(&self, _: i64) -> Result<Trip, RepositoryError> {
+            Ok(self.trip.clone())
+        }
+
+        async fn get_trip_stops(&self, _: i64) -> Result<Vec<TripStop>, RepositoryError> {
+            Ok(self.trip_stops.clone())
+        }
+
+        async fn get_filtered_trip_stops(&self, filter: TripFilter) -> Result<Vec<TripStop>, RepositoryError> {
+            Ok(if self.runs(&filter) { self.trip_stops.clone() } else { Vec::new() })
+        }
+
+        async fn get_departures(&self, _: BoardFilter) -> Result<Vec<TripStop>, RepositoryError> {
+            Ok(Vec::new())
+        }
+
+        async fn get_arrivals(&self, _: BoardFilter) -> Result<Vec<TripStop>, RepositoryError> {
+            Ok(Vec::new())
+        }
+    }
+
+    #[async_trait]
+    impl StopRepository for Timetable {
+        async fn get_stops(&self) -> Result<Vec<Stop>, RepositoryError> {
+            Ok([A, B, C]
+                .iter()
+                .map(|id| Stop { id: *id, latitude: 46.0 + *id as f64 * 0.1, longitude: 6.0, name: id.to_string() })
+                .collect())
+        }
+
+        async fn get_stop(&self, _: i32) -> Result<Stop, RepositoryError> {
+            Err(RepositoryError::NotFound)
+        }
+
+        async fn get_stops_within(&self, _: Bounds) -> Result<Vec<Stop>, RepositoryError> {
+            Ok(Vec::new())
+        }
+
+        async fn get_nearest_stops(&self, _: f64, _: f64, _: i64) -> Result<Vec<Stop>, RepositoryError> {
+            Ok(Vec::new())
+        }
+    }
+
+    #[actix_web::test]
+    async fn trips_of_the_day_before_run_past_midnight() {
+        let timetable: Timetable = Timetable::new();
+        let (planner, _, now) = day_planner(&timetable, &timetable, AFTER_MIDNIGHT, None).await.unwrap();
+
+        let journeys: Vec<Journey> = planner.plan(B, C, now, 1);
+        assert_eq!(journeys.len(), 1);
+        assert!(matches!(journeys[0].legs[..], [JourneyLeg::Ride { trip_id: 1, .. }]));
+        assert_eq!((journeys[0].departure_time, journeys[0].arrival_time), (time(0, 10), time(0, 20)));
+    }
+
+    // the isochrones plan up to their duration
+    #[actix_web::test]
+    async fn stops_are_reached_past_midnight_within_the_duration() {
+        let timetable: Timetable = Timetable::new();
+        let until: Option<Duration> = Some(Duration::minutes(30));
+        let (planner, _, now) = day_planner(&timetable, &timetable, AFTER_MIDNIGHT, until).await.unwrap();
+
+        let reachable: HashMap<i32, i32> = planner.reachable(B, now, 30 * 60);
+        assert_eq!(reachable.get(&C), Some(&(15 * 60)));
+        assert!(!reachable.contains_key(&A));
+    }
+
+    #[actix_web::test]
+    async fn trips_of_the_day_before_are_gone_once_arrived() {
+        let timetable: Timetable = Timetable::new();
+        let (planner, _, now) = day_planner(&timetable, &timetable, AFTER_MIDNIGHT + 20 * 60, None).await.unwrap();
+
+        assert!(planner.plan(B, C, now, 1).is_empty());
+    }
+}
@@ -12,7 +12,7 @@ use serde::Deserialize;
 
 use crate::{
     model::{leg_step::LegStep, direction_leg::DirectionLeg},
-    repository::database::{Database, Table},
+    repository::{database::RepositoryError, storage::DirectionRepository},
 };
 
 #[derive(Deserialize)]
@@ -23,6 +23,8 @@ pub struct LegIdentifier {
 #[derive(Debug, Display)]
 pub enum LegError {
     LegNotFound,
+    DatabaseUnavailable,
+    DatabaseError,
     BadLegRequest,
 }
 
@@ -36,57 +38,49 @@ impl ResponseError for LegError {
     fn status_code(&self) -> StatusCode {
         match self {
             LegError::LegNotFound => StatusCode::NOT_FOUND,
+            LegError::DatabaseUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            LegError::DatabaseError => StatusCode::INTERNAL_SERVER_ERROR,
             _ => StatusCode::BAD_REQUEST,
         }
     }
 }
 
+impl From<RepositoryError> for LegError {
+    fn from(error: RepositoryError) -> Self {
+        match error {
+            RepositoryError::NotFound => LegError::LegNotFound,
+            RepositoryError::Unavailable => LegError::DatabaseUnavailable,
+            RepositoryError::Failed => LegError::DatabaseError,
+        }
+    }
+}
+
 #[get("/leg/{id}")]
 pub async fn get_leg(
     identifier: Path<LegIdentifier>,
-    database: Data<Database>,
+    repository: Data<dyn DirectionRepository>,
 ) -> Result<Json<DirectionLeg>, LegError> {
-    let id: Result<i32, std::num::ParseIntError> = identifier.into_inner().id.parse::<i32>();
+    let id: Result<i64, std::num::ParseIntError> = identifier.into_inner().id.parse::<i64>();
     if id.is_err() {
         return Err(LegError::BadLegRequest);
     }
 
-    let leg: Option<DirectionLeg> = database
-        .get_one::<DirectionLeg>(
-            sqlx::query_as::<_, DirectionLeg>(
-                format!("SELECT * FROM {} WHERE id=$1", DirectionLeg::TABLE_NAME).as_str(),
-            )
-            .bind(id.unwrap()),
-        )
-        .await;
+    let leg: DirectionLeg = repository.get_leg(id.unwrap()).await?;
 
-    match leg {
-        Some(leg) => Ok(Json(leg)),
-        None => Err(LegError::LegNotFound),
-    }
+    Ok(Json(leg))
 }
 
 #[get("/leg/{id}/steps")]
 pub async fn get_leg_steps(
     identifier: Path<LegIdentifier>,
-    database: Data<Database>,
+    repository: Data<dyn DirectionRepository>,
 ) -> Result<Json<Vec<LegStep>>, LegError> {
-    let id: Result<i32, std::num::ParseIntError> = identifier.into_inner().id.parse::<i32>();
+    let id: Result<i64, std::num::ParseIntError> = identifier.into_inner().id.parse::<i64>();
     if id.is_err() {
         return Err(LegError::BadLegRequest);
     }
 
-    let leg_steps: Option<Vec<LegStep>> = database
-        .get_many::<LegStep>(
-            sqlx::query_as::<_, LegStep>(
-                format!("SELECT * FROM {} WHERE leg_id=$1", LegStep::TABLE_NAME).as_str(),
-            )
-            .bind(id.unwrap()),
-        )
-        .await;
+    let leg_steps: Vec<LegStep> = repository.get_leg_steps(id.unwrap()).await?;
 
-    match leg_steps {
-        Some(leg_steps) => Ok(Json(leg_steps)),
-        None => Err(LegError::LegNotFound),
-    }
+    Ok(Json(leg_steps))
 }
\ No newline at end of file
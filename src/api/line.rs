@@ -12,7 +12,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     model::line::Line,
-    repository::database::{Database, Table},
+    repository::{database::RepositoryError, storage::LineRepository},
 };
 
 #[derive(Deserialize, Serialize)]
@@ -23,6 +23,8 @@ pub struct LineIdentifier {
 #[derive(Debug, Display)]
 pub enum LineError {
     LineNotFound,
+    DatabaseUnavailable,
+    DatabaseError,
     BadLineRequest,
 }
 
@@ -36,42 +38,41 @@ impl ResponseError for LineError {
     fn status_code(&self) -> StatusCode {
         match self {
             LineError::LineNotFound => StatusCode::NOT_FOUND,
+            LineError::DatabaseUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            LineError::DatabaseError => StatusCode::INTERNAL_SERVER_ERROR,
             LineError::BadLineRequest => StatusCode::BAD_REQUEST,
         }
     }
 }
 
+impl From<RepositoryError> for LineError {
+    fn from(error: RepositoryError) -> Self {
+        match error {
+            RepositoryError::NotFound => LineError::LineNotFound,
+            RepositoryError::Unavailable => LineError::DatabaseUnavailable,
+            RepositoryError::Failed => LineError::DatabaseError,
+        }
+    }
+}
+
 #[get("/lines")]
-pub async fn get_lines(database: Data<Database>) -> Result<Json<Vec<Line>>, LineError> {
-    let lines: Option<Vec<Line>> = database.get_all::<Line>(Line::TABLE_NAME).await;
+pub async fn get_lines(repository: Data<dyn LineRepository>) -> Result<Json<Vec<Line>>, LineError> {
+    let lines: Vec<Line> = repository.get_lines().await?;
 
-    match lines {
-        Some(lines) => Ok(Json(lines)),
-        None => Err(LineError::LineNotFound),
-    }
+    Ok(Json(lines))
 }
 
 #[get("/line/{id}")]
 pub async fn get_line(
     identifier: Path<LineIdentifier>,
-    database: Data<Database>,
+    repository: Data<dyn LineRepository>,
 ) -> Result<Json<Line>, LineError> {
     let id: Result<i32, std::num::ParseIntError> = identifier.into_inner().id.parse::<i32>();
     if id.is_err() {
         return Err(LineError::BadLineRequest);
     }
 
-    let line: Option<Line> = database
-        .get_one::<Line>(
-            sqlx::query_as::<_, Line>(
-                format!("SELECT * FROM {} WHERE id=$1", Line::TABLE_NAME).as_str(),
-            )
-            .bind(id.unwrap()),
-        )
-        .await;
+    let line: Line = repository.get_line(id.unwrap()).await?;
 
-    match line {
-        Some(line) => Ok(Json(line)),
-        None => Err(LineError::LineNotFound),
-    }
+    Ok(Json(line))
 }
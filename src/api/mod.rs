@@ -3,4 +3,9 @@ pub mod stop;
 pub mod trip;
 pub mod shape;
 pub mod direction;
-pub mod leg;
\ No newline at end of file
+pub mod leg;
+pub mod bitfield;
+pub mod vehicle;
+pub mod board;
+pub mod journey;
+pub mod isochrone;
\ No newline at end of file
@@ -12,7 +12,7 @@ use serde::Deserialize;
 
 use crate::{
     model::{shape::Shape, shape_point::ShapePoint, shape_stop::ShapeStop},
-    repository::database::{Database, Table},
+    repository::{database::RepositoryError, storage::ShapeRepository},
 };
 
 #[derive(Deserialize)]
@@ -23,6 +23,8 @@ pub struct ShapeIdentifier {
 #[derive(Debug, Display)]
 pub enum ShapeError {
     ShapeNotFound,
+    DatabaseUnavailable,
+    DatabaseError,
     BadShapeRequest,
 }
 
@@ -36,82 +38,64 @@ impl ResponseError for ShapeError {
     fn status_code(&self) -> StatusCode {
         match self {
             ShapeError::ShapeNotFound => StatusCode::NOT_FOUND,
+            ShapeError::DatabaseUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            ShapeError::DatabaseError => StatusCode::INTERNAL_SERVER_ERROR,
             _ => StatusCode::BAD_REQUEST,
         }
     }
 }
 
+impl From<RepositoryError> for ShapeError {
+    fn from(error: RepositoryError) -> Self {
+        match error {
+            RepositoryError::NotFound => ShapeError::ShapeNotFound,
+            RepositoryError::Unavailable => ShapeError::DatabaseUnavailable,
+            RepositoryError::Failed => ShapeError::DatabaseError,
+        }
+    }
+}
+
 #[get("/shape/{id}")]
 pub async fn get_shape(
     identifier: Path<ShapeIdentifier>,
-    database: Data<Database>,
+    repository: Data<dyn ShapeRepository>,
 ) -> Result<Json<Shape>, ShapeError> {
-    let id: Result<i32, std::num::ParseIntError> = identifier.into_inner().id.parse::<i32>();
+    let id: Result<i64, std::num::ParseIntError> = identifier.into_inner().id.parse::<i64>();
     if id.is_err() {
         return Err(ShapeError::BadShapeRequest);
     }
 
-    let shape: Option<Shape> = database
-        .get_one::<Shape>(
-            sqlx::query_as::<_, Shape>(
-                format!("SELECT * FROM {} WHERE id=$1", Shape::TABLE_NAME).as_str(),
-            )
-            .bind(id.unwrap()),
-        )
-        .await;
+    let shape: Shape = repository.get_shape(id.unwrap()).await?;
 
-    match shape {
-        Some(shape) => Ok(Json(shape)),
-        None => Err(ShapeError::ShapeNotFound),
-    }
+    Ok(Json(shape))
 }
 
 #[get("/shape/{id}/points")]
 pub async fn get_shape_points(
     identifier: Path<ShapeIdentifier>,
-    database: Data<Database>,
+    repository: Data<dyn ShapeRepository>,
 ) -> Result<Json<Vec<ShapePoint>>, ShapeError> {
-    let id: Result<i32, std::num::ParseIntError> = identifier.into_inner().id.parse::<i32>();
+    let id: Result<i64, std::num::ParseIntError> = identifier.into_inner().id.parse::<i64>();
     if id.is_err() {
         return Err(ShapeError::BadShapeRequest);
     }
 
-    let shape_points: Option<Vec<ShapePoint>> = database
-        .get_many::<ShapePoint>(
-            sqlx::query_as::<_, ShapePoint>(
-                format!("SELECT * FROM {} WHERE shape_id=$1", ShapePoint::TABLE_NAME).as_str(),
-            )
-            .bind(id.unwrap()),
-        )
-        .await;
+    let shape_points: Vec<ShapePoint> = repository.get_shape_points(id.unwrap()).await?;
 
-    match shape_points {
-        Some(shape_points) => Ok(Json(shape_points)),
-        None => Err(ShapeError::ShapeNotFound),
-    }
+    Ok(Json(shape_points))
 }
 
 #[get("/shape/{id}/stops")]
 pub async fn get_shape_stops(
     identifier: Path<ShapeIdentifier>,
-    database: Data<Database>,
+    repository: Data<dyn ShapeRepository>,
 ) -> Result<Json<Vec<ShapeStop>>, ShapeError> {
-    let id: Result<i32, std::num::ParseIntError> = identifier.into_inner().id.parse::<i32>();
+    let id: Result<i64, std::num::ParseIntError> = identifier.into_inner().id.parse::<i64>();
     if id.is_err() {
         return Err(ShapeError::BadShapeRequest);
     }
 
-    let shape_stops: Option<Vec<ShapeStop>> = database
-        .get_many::<ShapeStop>(
-            sqlx::query_as::<_, ShapeStop>(
-                format!("SELECT * FROM {} WHERE shape_id=$1", ShapeStop::TABLE_NAME).as_str(),
-            )
-            .bind(id.unwrap()),
-        )
-        .await;
+    let shape_stops: Vec<ShapeStop> = repository.get_shape_stops(id.unwrap()).await?;
 
-    match shape_stops {
-        Some(shape_stops) => Ok(Json(shape_stops)),
-        None => Err(ShapeError::ShapeNotFound),
-    }
+    Ok(Json(shape_stops))
 }
\ No newline at end of file
@@ -3,8 +3,8 @@ use actix_web::{
     get,
     http::{header::ContentType, StatusCode},
     web::Data,
-    web::Json,
     web::Path,
+    web::{Json, Query},
     HttpResponse,
 };
 use derive_more::Display;
@@ -12,7 +12,10 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     model::stop::Stop,
-    repository::database::{Database, Table},
+    repository::{
+        database::RepositoryError,
+        storage::{Bounds, StopRepository},
+    },
 };
 
 #[derive(Deserialize, Serialize)]
@@ -20,9 +23,24 @@ pub struct StopIdentifier {
     id: String,
 }
 
+#[derive(Deserialize)]
+pub struct StopSelector {
+    // min longitude, min latitude, max longitude, max latitude
+    bbox: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct NearestStopSelector {
+    lat: f64,
+    lng: f64,
+    limit: Option<i64>,
+}
+
 #[derive(Debug, Display)]
 pub enum StopError {
     StopNotFound,
+    DatabaseUnavailable,
+    DatabaseError,
     BadStopRequest,
 }
 
@@ -36,42 +54,85 @@ impl ResponseError for StopError {
     fn status_code(&self) -> StatusCode {
         match self {
             StopError::StopNotFound => StatusCode::NOT_FOUND,
+            StopError::DatabaseUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            StopError::DatabaseError => StatusCode::INTERNAL_SERVER_ERROR,
             StopError::BadStopRequest => StatusCode::BAD_REQUEST,
         }
     }
 }
 
+impl From<RepositoryError> for StopError {
+    fn from(error: RepositoryError) -> Self {
+        match error {
+            RepositoryError::NotFound => StopError::StopNotFound,
+            RepositoryError::Unavailable => StopError::DatabaseUnavailable,
+            RepositoryError::Failed => StopError::DatabaseError,
+        }
+    }
+}
+
+pub fn parse_bounds(bbox: &str) -> Option<Bounds> {
+    let values: Vec<f64> = bbox
+        .split(',')
+        .map(|value| value.trim().parse::<f64>())
+        .collect::<Result<Vec<f64>, _>>()
+        .ok()?;
+    if values.len() != 4 || values[0] > values[2] || values[1] > values[3] {
+        return None;
+    }
+
+    Some(Bounds {
+        min_longitude: values[0],
+        min_latitude: values[1],
+        max_longitude: values[2],
+        max_latitude: values[3],
+    })
+}
+
 #[get("/stops")]
-pub async fn get_stops(database: Data<Database>) -> Result<Json<Vec<Stop>>, StopError> {
-    let stops: Option<Vec<Stop>> = database.get_all::<Stop>(Stop::TABLE_NAME).await;
+pub async fn get_stops(
+    repository: Data<dyn StopRepository>,
+    info: Query<StopSelector>,
+) -> Result<Json<Vec<Stop>>, StopError> {
+    let stops: Vec<Stop> = match &info.bbox {
+        Some(bbox) => {
+            let bounds: Bounds = parse_bounds(bbox).ok_or(StopError::BadStopRequest)?;
+            repository.get_stops_within(bounds).await?
+        }
+        None => repository.get_stops().await?,
+    };
+
+    Ok(Json(stops))
+}
 
-    match stops {
-        Some(stops) => Ok(Json(stops)),
-        None => Err(StopError::StopNotFound),
+#[get("/stops/nearest")]
+pub async fn get_nearest_stops(
+    repository: Data<dyn StopRepository>,
+    info: Query<NearestStopSelector>,
+) -> Result<Json<Vec<Stop>>, StopError> {
+    let limit: i64 = info.limit.unwrap_or(5);
+    if !(1..=100).contains(&limit) || info.lat.abs() > 90.0 || info.lng.abs() > 180.0 {
+        return Err(StopError::BadStopRequest);
     }
+
+    let stops: Vec<Stop> = repository
+        .get_nearest_stops(info.lat, info.lng, limit)
+        .await?;
+
+    Ok(Json(stops))
 }
 
 #[get("/stop/{id}")]
 pub async fn get_stop(
     identifier: Path<StopIdentifier>,
-    database: Data<Database>,
+    repository: Data<dyn StopRepository>,
 ) -> Result<Json<Stop>, StopError> {
     let id: Result<i32, std::num::ParseIntError> = identifier.into_inner().id.parse::<i32>();
     if id.is_err() {
         return Err(StopError::BadStopRequest);
     }
 
-    let stop: Option<Stop> = database
-        .get_one::<Stop>(
-            sqlx::query_as::<_, Stop>(
-                format!("SELECT * FROM {} WHERE id=$1", Stop::TABLE_NAME).as_str(),
-            )
-            .bind(id.unwrap()),
-        )
-        .await;
-
-    match stop {
-        Some(stop) => Ok(Json(stop)),
-        None => Err(StopError::StopNotFound),
-    }
+    let stop: Stop = repository.get_stop(id.unwrap()).await?;
+
+    Ok(Json(stop))
 }
@@ -9,15 +9,22 @@ use actix_web::{
     web::{Json, Query},
     HttpResponse,
 };
-use chrono::{Datelike, Duration, NaiveDateTime, NaiveTime, TimeZone};
-use chrono_tz::Europe::Zurich;
+use chrono::{DateTime, Datelike, Duration, NaiveDateTime, NaiveTime, TimeZone};
+use chrono_tz::{Europe::Zurich, Tz};
 use derive_more::Display;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::ops::SubAssign;
 
 use crate::{
-    model::{information::Information, trip::Trip, trip_stop::TripStop},
-    repository::database::{Database, Table},
+    model::{
+        bitfield::Bitfield, calendar::ServiceCalendar, information::Information, trip::Trip,
+        trip_stop::TripStop,
+    },
+    repository::{
+        calibration::DAY,
+        database::RepositoryError,
+        storage::{TripFilter, TripRepository},
+    },
 };
 
 #[derive(Deserialize)]
@@ -25,6 +32,14 @@ pub struct TripIdentifier {
     id: String,
 }
 
+// trip with the days it runs on, as described by its service calendar
+#[derive(Serialize)]
+pub struct TripDetails {
+    #[serde(flatten)]
+    trip: Trip,
+    calendar: Option<String>,
+}
+
 #[derive(Deserialize)]
 pub struct TripSelector {
     timestamp: i64,
@@ -35,6 +50,8 @@ pub struct TripSelector {
 #[derive(Debug, Display)]
 pub enum TripError {
     TripNotFound,
+    DatabaseUnavailable,
+    DatabaseError,
     BadTripRequest,
     InvalidTimePeriod,
     InvalidBounds,
@@ -50,38 +67,25 @@ impl ResponseError for TripError {
     fn status_code(&self) -> StatusCode {
         match self {
             TripError::TripNotFound => StatusCode::NOT_FOUND,
+            TripError::DatabaseUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            TripError::DatabaseError => StatusCode::INTERNAL_SERVER_ERROR,
             _ => StatusCode::BAD_REQUEST,
         }
     }
 }
 
-#[get("/trips")]
-pub async fn get_trips(
-    database: Data<Database>,
-    info: Query<TripSelector>,
-) -> Result<Json<Vec<Trip>>, TripError> {
-    let timestamp: i64 = info.timestamp;
-
-    let naive_date: Option<NaiveDateTime> = NaiveDateTime::from_timestamp_opt(timestamp, 0);
-    if naive_date.is_none() {
-        return Err(TripError::BadTripRequest);
-    }
-
-    let bounds: i16 = info.bounds.unwrap_or(0);
-    if bounds > 24 || bounds < 0 {
-        return Err(TripError::InvalidBounds);
+impl From<RepositoryError> for TripError {
+    fn from(error: RepositoryError) -> Self {
+        match error {
+            RepositoryError::NotFound => TripError::TripNotFound,
+            RepositoryError::Unavailable => TripError::DatabaseUnavailable,
+            RepositoryError::Failed => TripError::DatabaseError,
+        }
     }
+}
 
-    let from: Option<i64> = info.from;
-
-    let date = Zurich.from_utc_datetime(&naive_date.unwrap());
-    let information: Information = database
-        .get_one::<Information>(sqlx::query_as::<_, Information>(
-            format!("SELECT * FROM {}", Information::TABLE_NAME).as_str(),
-        ))
-        .await
-        .unwrap();
-
+// first and last second of a timetable period
+fn period_bounds(information: &Information) -> (DateTime<Tz>, DateTime<Tz>) {
     let start_datetime = Zurich
         .with_ymd_and_hms(
             information.start_date.year(),
@@ -103,10 +107,53 @@ pub async fn get_trips(
         )
         .unwrap();
 
+    return (start_datetime, end_datetime);
+}
+
+// timetable period of the timestamp, with its local date and time and the index of the day in the bitfields
+pub async fn service_day(
+    repository: &dyn TripRepository,
+    timestamp: i64,
+) -> Result<(Information, DateTime<Tz>, usize), TripError> {
+    let naive_date: Option<NaiveDateTime> = NaiveDateTime::from_timestamp_opt(timestamp, 0);
+    if naive_date.is_none() {
+        return Err(TripError::BadTripRequest);
+    }
+
+    let date = Zurich.from_utc_datetime(&naive_date.unwrap());
+    let information: Information = match repository.get_information(date.date_naive()).await {
+        Ok(information) => information,
+        Err(RepositoryError::NotFound) => return Err(TripError::InvalidTimePeriod),
+        Err(error) => return Err(error.into()),
+    };
+
+    let (start_datetime, end_datetime) = period_bounds(&information);
     if date.lt(&start_datetime) || date.gt(&end_datetime) {
         return Err(TripError::InvalidTimePeriod);
     }
 
+    let day_index: Option<usize> = Bitfield::day_index(&information, date.date_naive());
+    if day_index.is_none() {
+        return Err(TripError::InvalidTimePeriod);
+    }
+
+    Ok((information, date, day_index.unwrap()))
+}
+
+// filter of the trips running around the timestamp, with the time of day it falls on
+pub async fn trip_filter(
+    repository: &dyn TripRepository,
+    timestamp: i64,
+    bounds: i16,
+    from: Option<i64>,
+) -> Result<(TripFilter, NaiveTime), TripError> {
+    if bounds > 24 || bounds < 0 {
+        return Err(TripError::InvalidBounds);
+    }
+
+    let (information, date, day_index) = service_day(repository, timestamp).await?;
+    let (start_datetime, end_datetime) = period_bounds(&information);
+
     let mut date_from = start_datetime;
     if from.is_some() {
         let naive_from: Option<NaiveDateTime> = NaiveDateTime::from_timestamp_opt(from.unwrap(), 0);
@@ -125,65 +172,109 @@ pub async fn get_trips(
     let mut lower_time_bound: NaiveTime = date.time();
     lower_time_bound.sub_assign(Duration::minutes(bounds as i64));
 
-    let day_number: i16 = date.signed_duration_since(start_datetime).num_days() as i16;
-    let bitfield_number: i16 = day_number + 2;
-
     // TODO: manage trips that are after 00h
 
-    let trips: Option<Vec<Trip>> = database.get_many::<Trip>(sqlx::query_as::<_, Trip>(format!("SELECT trips.id, trips.journey_number, trips.option_count, trips.shape_id, trips.direction_id, trips.transport_mode, trips.origin_id, trips.destination_id, trips.bitfield_id, trips.line_id, trips.direction, trips.departure_time, trips.arrival_time FROM {} JOIN bitfields ON bitfield_id = bitfields.id WHERE departure_time <= $1 AND departure_time >= $4 AND arrival_time >= $2 AND SUBSTRING(days,$3,1) = '1'", Trip::TABLE_NAME).as_str()).bind(upper_time_bound).bind(lower_time_bound).bind(bitfield_number+1).bind(date_from.time())).await;
+    let filter: TripFilter = TripFilter {
+        information_id: information.id,
+        day_index: day_index as i32,
+        departure_from: date_from.time(),
+        departure_until: upper_time_bound,
+        arrival_from: lower_time_bound,
+        past_midnight: false,
+    };
 
-    match trips {
-        Some(trips) => Ok(Json(trips)),
-        None => Err(TripError::TripNotFound),
-    }
+    Ok((filter, date.time()))
+}
+
+// filter of the trips of the day before still running at the time of the filter, past midnight, none when
+// the day before is out of the timetable periods
+pub async fn previous_day_filter(
+    repository: &dyn TripRepository,
+    timestamp: i64,
+    filter: &TripFilter,
+) -> Result<Option<TripFilter>, TripError> {
+    let (information, _, day_index) = match service_day(repository, timestamp - DAY as i64).await {
+        Ok(service_day) => service_day,
+        Err(TripError::InvalidTimePeriod) => return Ok(None),
+        Err(error) => return Err(error),
+    };
+
+    let filter: TripFilter = TripFilter {
+        information_id: information.id,
+        day_index: day_index as i32,
+        departure_from: NaiveTime::MIN,
+        departure_until: NaiveTime::from_hms_opt(23, 59, 59).unwrap(),
+        arrival_from: filter.arrival_from,
+        past_midnight: true,
+    };
+
+    Ok(Some(filter))
+}
+
+pub async fn find_trips(
+    repository: &dyn TripRepository,
+    timestamp: i64,
+    bounds: i16,
+    from: Option<i64>,
+) -> Result<(Vec<Trip>, NaiveTime), TripError> {
+    let (filter, time) = trip_filter(repository, timestamp, bounds, from).await?;
+    let trips: Vec<Trip> = repository.get_trips(filter).await?;
+
+    Ok((trips, time))
+}
+
+#[get("/trips")]
+pub async fn get_trips(
+    repository: Data<dyn TripRepository>,
+    info: Query<TripSelector>,
+) -> Result<Json<Vec<Trip>>, TripError> {
+    let (trips, _) = find_trips(
+        repository.get_ref(),
+        info.timestamp,
+        info.bounds.unwrap_or(0),
+        info.from,
+    )
+    .await?;
+
+    Ok(Json(trips))
 }
 
 #[get("/trip/{id}")]
 pub async fn get_trip(
     identifier: Path<TripIdentifier>,
-    database: Data<Database>,
-) -> Result<Json<Trip>, TripError> {
-    let id: Result<i32, std::num::ParseIntError> = identifier.into_inner().id.parse::<i32>();
+    repository: Data<dyn TripRepository>,
+) -> Result<Json<TripDetails>, TripError> {
+    let id: Result<i64, std::num::ParseIntError> = identifier.into_inner().id.parse::<i64>();
     if id.is_err() {
         return Err(TripError::BadTripRequest);
     }
 
-    let trip: Option<Trip> = database
-        .get_one::<Trip>(
-            sqlx::query_as::<_, Trip>(
-                format!("SELECT * FROM {} WHERE id=$1", Trip::TABLE_NAME).as_str(),
-            )
-            .bind(id.unwrap()),
-        )
-        .await;
+    let trip: Trip = repository.get_trip(id.unwrap()).await?;
 
-    match trip {
-        Some(trip) => Ok(Json(trip)),
-        None => Err(TripError::TripNotFound),
-    }
+    let information: Information = repository.get_information_by_id(trip.information_id).await?;
+    let calendar: Option<String> = match repository
+        .get_bitfield(trip.information_id, trip.bitfield_id)
+        .await
+    {
+        Ok(bitfield) => Some(ServiceCalendar::from_bitfield(&bitfield, &information).description),
+        Err(RepositoryError::NotFound) => None,
+        Err(error) => return Err(error.into()),
+    };
+
+    Ok(Json(TripDetails { trip, calendar }))
 }
 
 #[get("/trip/{id}/stops")]
 pub async fn get_trip_stops(
     identifier: Path<TripIdentifier>,
-    database: Data<Database>,
+    repository: Data<dyn TripRepository>,
 ) -> Result<Json<Vec<TripStop>>, TripError> {
-    let id: Result<i32, std::num::ParseIntError> = identifier.into_inner().id.parse::<i32>();
+    let id: Result<i64, std::num::ParseIntError> = identifier.into_inner().id.parse::<i64>();
     if id.is_err() {
         return Err(TripError::BadTripRequest);
     }
 
-    let trip_stops: Option<Vec<TripStop>> = database
-        .get_many::<TripStop>(
-            sqlx::query_as::<_, TripStop>(
-                format!("SELECT * FROM {} WHERE trip_id=$1", TripStop::TABLE_NAME).as_str(),
-            )
-            .bind(id.unwrap()),
-        )
-        .await;
+    let trip_stops: Vec<TripStop> = repository.get_trip_stops(id.unwrap()).await?;
 
-    match trip_stops {
-        Some(trip_stops) => Ok(Json(trip_stops)),
-        None => Err(TripError::TripNotFound),
-    }
+    Ok(Json(trip_stops))
 }
@@ -0,0 +1,374 @@
+use std::collections::{hash_map::Entry, HashMap};
+
+use std::{sync::Arc, time::Duration};
+
+use actix_web::{
+    get,
+    http::header::{CacheControl, CacheDirective, CONTENT_TYPE},
+    rt::time::sleep,
+    web::Data,
+    web::{Bytes, Json, Query},
+    HttpResponse,
+};
+use chrono::{NaiveTime, Utc};
+use futures_util::{lock::Mutex, stream};
+use log::warn;
+use serde::Deserialize;
+
+use crate::{
+    model::{
+        direction_leg::DirectionLeg, leg_step::LegStep, shape_point::ShapePoint,
+        shape_stop::ShapeStop, trip::Trip, trip_stop::TripStop, vehicle::Vehicle,
+    },
+    repository::{
+        database::RepositoryError,
+        routing::{decode_polyline, Location},
+        storage::{Bounds, DirectionRepository, ShapeRepository, StopRepository, TripFilter, TripRepository},
+    },
+};
+
+use super::{
+    stop::parse_bounds,
+    trip::{previous_day_filter, trip_filter, TripError},
+};
+
+// seconds between two updates of a stream
+const DEFAULT_INTERVAL: u64 = 5;
+const MAX_INTERVAL: u64 = 60;
+
+#[derive(Deserialize)]
+pub struct VehicleSelector {
+    timestamp: i64,
+}
+
+#[derive(Deserialize)]
+pub struct VehicleSubscription {
+    // min longitude, min latitude, max longitude, max latitude
+    bbox: Option<String>,
+    // comma separated line ids
+    lines: Option<String>,
+    interval: Option<u64>,
+}
+
+// everything a stream needs between two updates
+struct StreamState {
+    trips: Data<dyn TripRepository>,
+    directions: Data<dyn DirectionRepository>,
+    shapes: Data<dyn ShapeRepository>,
+    stops: Data<dyn StopRepository>,
+    broadcast: Data<VehicleBroadcast>,
+    bounds: Option<Bounds>,
+    lines: Option<Vec<i32>>,
+    interval: Duration,
+    started: bool,
+}
+
+// paths between consecutive stops along the routed legs of the directions, keyed by direction
+async fn direction_paths(
+    repository: &dyn DirectionRepository,
+    direction_ids: &[i64],
+) -> Result<HashMap<i64, HashMap<(i32, i32), Vec<Location>>>, RepositoryError> {
+    let mut paths: HashMap<i64, HashMap<(i32, i32), Vec<Location>>> = HashMap::new();
+
+    let legs: Vec<DirectionLeg> = repository.get_directions_legs(direction_ids).await?;
+    let leg_ids: Vec<i64> = legs.iter().map(|leg| leg.id).collect();
+    let mut steps: HashMap<i64, Vec<LegStep>> = HashMap::new();
+    if !leg_ids.is_empty() {
+        for step in repository.get_legs_steps(&leg_ids).await? {
+            steps.entry(step.leg_id).or_default().push(step);
+        }
+    }
+
+    for leg in legs {
+        let mut leg_steps: Vec<LegStep> = steps.remove(&leg.id).unwrap_or_default();
+        leg_steps.sort_by_key(|step| step.sequence);
+
+        let mut path: Vec<Location> = Vec::new();
+        for step in leg_steps {
+            let mut points: Vec<Location> = decode_polyline(&step.polyline);
+            if points.is_empty() {
+                points = vec![
+                    Location { latitude: step.start_lat, longitude: step.start_lng },
+                    Location { latitude: step.end_lat, longitude: step.end_lng },
+                ];
+            }
+            path.extend(points);
+        }
+        paths.entry(leg.direction_id).or_default().insert((leg.origin_id, leg.destination_id), path);
+    }
+
+    return Ok(paths);
+}
+
+// paths between consecutive stops along the snapped points of a shape
+async fn shape_paths(
+    repository: &dyn ShapeRepository,
+    shape_id: i64,
+) -> Result<HashMap<(i32, i32), Vec<Location>>, RepositoryError> {
+    let mut paths: HashMap<(i32, i32), Vec<Location>> = HashMap::new();
+
+    let mut shape_stops: Vec<ShapeStop> = repository.get_shape_stops(shape_id).await?;
+    shape_stops.sort_by_key(|shape_stop| shape_stop.sequence);
+    let mut points: Vec<ShapePoint> = repository.get_shape_points(shape_id).await?;
+    points.sort_by_key(|point| point.sequence);
+
+    let position = |shape_stop: &ShapeStop| points.iter().position(|point| point.shape_stop_id == Some(shape_stop.id));
+    for pair in shape_stops.windows(2) {
+        if let (Some(start), Some(end)) = (position(&pair[0]), position(&pair[1])) {
+            if start < end {
+                let path: Vec<Location> = points[start..=end]
+                    .iter()
+                    .map(|point| Location { latitude: point.latitude, longitude: point.longitude })
+                    .collect();
+                paths.insert((pair[0].stop_id, pair[1].stop_id), path);
+            }
+        }
+    }
+
+    return Ok(paths);
+}
+
+// geometry missing from the database falls back to straight lines between the stops
+fn or_empty<T: Default>(result: Result<T, RepositoryError>) -> Result<T, TripError> {
+    match result {
+        Ok(value) => Ok(value),
+        Err(RepositoryError::NotFound) => Ok(T::default()),
+        Err(error) => Err(error.into()),
+    }
+}
+
+// paths and stop locations, kept across the updates of a stream
+#[derive(Default)]
+pub struct VehicleLocator {
+    direction_paths: HashMap<i64, HashMap<(i32, i32), Vec<Location>>>,
+    shape_paths: HashMap<i64, HashMap<(i32, i32), Vec<Location>>>,
+    locations: HashMap<i32, Location>,
+}
+
+impl VehicleLocator {
+    pub async fn locate(
+        &mut self,
+        trips: &dyn TripRepository,
+        directions: &dyn DirectionRepository,
+        shapes: &dyn ShapeRepository,
+        stops: &dyn StopRepository,
+        timestamp: i64,
+    ) -> Result<Vec<Vehicle>, TripError> {
+        let (filter, time): (TripFilter, NaiveTime) = trip_filter(trips, timestamp, 0, None).await?;
+        // trips of the day before are still on their way past midnight
+        let previous: Option<TripFilter> = previous_day_filter(trips, timestamp, &filter).await?;
+
+        let mut active_trips: Vec<Trip> = Vec::new();
+        let mut trip_stops: HashMap<i64, Vec<TripStop>> = HashMap::new();
+        for filter in std::iter::once(filter).chain(previous) {
+            active_trips.extend(trips.get_trips(filter.clone()).await?);
+            for trip_stop in trips.get_filtered_trip_stops(filter).await? {
+                trip_stops.entry(trip_stop.trip_id).or_default().push(trip_stop);
+            }
+        }
+
+        if self.locations.is_empty() {
+            self.locations = stops
+                .get_stops()
+                .await?
+                .into_iter()
+                .map(|stop| (stop.id, Location { latitude: stop.latitude, longitude: stop.longitude }))
+                .collect();
+        }
+
+        // directions seen for the first time are loaded together, the ones without legs are kept empty
+        let mut direction_ids: Vec<i64> = active_trips
+            .iter()
+            .filter_map(|trip| trip.direction_id)
+            .filter(|direction_id| !self.direction_paths.contains_key(direction_id))
+            .collect();
+        direction_ids.sort_unstable();
+        direction_ids.dedup();
+        if !direction_ids.is_empty() {
+            let mut found = or_empty(direction_paths(directions, &direction_ids).await)?;
+            for direction_id in direction_ids {
+                self.direction_paths.insert(direction_id, found.remove(&direction_id).unwrap_or_default());
+            }
+        }
+
+        let mut vehicles: Vec<Vehicle> = Vec::new();
+        for trip in active_trips {
+            // trips of the same direction or shape share their paths
+            let mut paths: HashMap<(i32, i32), Vec<Location>> = HashMap::new();
+            if let Some(shape_id) = trip.shape_id {
+                if let Entry::Vacant(entry) = self.shape_paths.entry(shape_id) {
+                    entry.insert(or_empty(shape_paths(shapes, shape_id).await)?);
+                }
+                paths.extend(self.shape_paths[&shape_id].clone());
+            }
+            // snapped shapes follow the road closer than the routed legs, they win where both exist
+            if let Some(direction_paths) = trip.direction_id.and_then(|id| self.direction_paths.get(&id)) {
+                for (stops, path) in direction_paths {
+                    paths.entry(*stops).or_insert_with(|| path.clone());
+                }
+            }
+
+            let trip_stops: &Vec<TripStop> = match trip_stops.get(&trip.id) {
+                Some(trip_stops) => trip_stops,
+                None => continue,
+            };
+            if let Some(vehicle) = Vehicle::locate(&trip, trip_stops, time, &paths, &self.locations) {
+                vehicles.push(vehicle);
+            }
+        }
+
+        return Ok(vehicles);
+    }
+}
+
+// last vehicles located for the streams, with the dataset the paths of the locator come from
+#[derive(Default)]
+struct Broadcast {
+    locator: VehicleLocator,
+    dataset: Option<String>,
+    timestamp: i64,
+    vehicles: Option<Arc<Vec<Vehicle>>>,
+}
+
+// vehicles shared by every stream, located once for all the streams updating on the same second
+#[derive(Default)]
+pub struct VehicleBroadcast {
+    broadcast: Mutex<Broadcast>,
+}
+
+impl VehicleBroadcast {
+    pub async fn vehicles(
+        &self,
+        trips: &dyn TripRepository,
+        directions: &dyn DirectionRepository,
+        shapes: &dyn ShapeRepository,
+        stops: &dyn StopRepository,
+        timestamp: i64,
+    ) -> Result<Arc<Vec<Vehicle>>, TripError> {
+        // the streams of the same second wait for the first one to locate the vehicles
+        let mut broadcast = self.broadcast.lock().await;
+        if let Some(vehicles) = broadcast.vehicles.as_ref().filter(|_| broadcast.timestamp == timestamp) {
+            return Ok(vehicles.clone());
+        }
+
+        let dataset: Option<String> = trips.get_dataset().await?;
+        if dataset != broadcast.dataset {
+            broadcast.locator = VehicleLocator::default();
+            broadcast.dataset = dataset;
+        }
+
+        let vehicles: Arc<Vec<Vehicle>> =
+            Arc::new(broadcast.locator.locate(trips, directions, shapes, stops, timestamp).await?);
+        broadcast.timestamp = timestamp;
+        broadcast.vehicles = Some(vehicles.clone());
+
+        return Ok(vehicles);
+    }
+}
+
+#[get("/vehicles")]
+pub async fn get_vehicles(
+    trips: Data<dyn TripRepository>,
+    directions: Data<dyn DirectionRepository>,
+    shapes: Data<dyn ShapeRepository>,
+    stops: Data<dyn StopRepository>,
+    info: Query<VehicleSelector>,
+) -> Result<Json<Vec<Vehicle>>, TripError> {
+    let vehicles: Vec<Vehicle> = VehicleLocator::default()
+        .locate(trips.get_ref(), directions.get_ref(), shapes.get_ref(), stops.get_ref(), info.timestamp)
+        .await?;
+
+    Ok(Json(vehicles))
+}
+
+// server-sent events with the vehicles of the subscription, estimated from the timetable, every interval
+#[get("/vehicles/stream")]
+pub async fn stream_vehicles(
+    trips: Data<dyn TripRepository>,
+    directions: Data<dyn DirectionRepository>,
+    shapes: Data<dyn ShapeRepository>,
+    stops: Data<dyn StopRepository>,
+    broadcast: Data<VehicleBroadcast>,
+    info: Query<VehicleSubscription>,
+) -> Result<HttpResponse, TripError> {
+    let bounds: Option<Bounds> = match &info.bbox {
+        Some(bbox) => Some(parse_bounds(bbox).ok_or(TripError::BadTripRequest)?),
+        None => None,
+    };
+    let lines: Option<Vec<i32>> = match &info.lines {
+        Some(lines) => Some(
+            lines
+                .split(',')
+                .map(|line| line.trim().parse::<i32>())
+                .collect::<Result<Vec<i32>, _>>()
+                .map_err(|_| TripError::BadTripRequest)?,
+        ),
+        None => None,
+    };
+    let interval: u64 = info.interval.unwrap_or(DEFAULT_INTERVAL);
+    if !(1..=MAX_INTERVAL).contains(&interval) {
+        return Err(TripError::BadTripRequest);
+    }
+
+    let state: StreamState = StreamState {
+        trips,
+        directions,
+        shapes,
+        stops,
+        broadcast,
+        bounds,
+        lines,
+        interval: Duration::from_secs(interval),
+        started: false,
+    };
+
+    // the stream ends when the client goes away
+    let events = stream::unfold(state, |mut state| async move {
+        // streams update on the multiples of their interval, together with the other streams
+        if state.started {
+            let interval: i64 = state.interval.as_millis() as i64;
+            let elapsed: i64 = Utc::now().timestamp_millis().rem_euclid(interval);
+            sleep(Duration::from_millis((interval - elapsed) as u64)).await;
+        }
+        state.started = true;
+
+        let result: Result<Arc<Vec<Vehicle>>, TripError> = state
+            .broadcast
+            .vehicles(
+                state.trips.get_ref(),
+                state.directions.get_ref(),
+                state.shapes.get_ref(),
+                state.stops.get_ref(),
+                Utc::now().timestamp(),
+            )
+            .await;
+
+        // failed updates are reported to the client, the next one is tried anyway
+        let event: String = match result {
+            Ok(vehicles) => {
+                let vehicles: Vec<&Vehicle> = vehicles
+                    .iter()
+                    .filter(|vehicle| state.lines.as_ref().is_none_or(|lines| lines.contains(&vehicle.line_id)))
+                    .filter(|vehicle| {
+                        state
+                            .bounds
+                            .as_ref()
+                            .is_none_or(|bounds| bounds.contains(vehicle.latitude, vehicle.longitude))
+                    })
+                    .collect();
+                format!("event: vehicles\ndata: {}\n\n", serde_json::to_string(&vehicles).unwrap())
+            }
+            Err(error) => {
+                warn!("Vehicle stream update failed: {}", error);
+                format!("event: error\ndata: {}\n\n", error)
+            }
+        };
+
+        Some((Ok::<Bytes, actix_web::Error>(Bytes::from(event)), state))
+    });
+
+    Ok(HttpResponse::Ok()
+        .insert_header((CONTENT_TYPE, "text/event-stream"))
+        .insert_header(CacheControl(vec![CacheDirective::NoCache]))
+        .streaming(events))
+}
@@ -2,37 +2,213 @@ mod api;
 mod model;
 mod repository;
 
-use crate::repository::database::Table;
-
 use std::{env, path::Path, str::FromStr};
 
 use api::{
+    bitfield::get_bitfield,
+    board::{get_stop_arrivals, get_stop_departures},
     direction::{get_direction, get_direction_leg_steps, get_direction_legs},
+    isochrone::get_isochrone,
+    journey::get_journeys,
     leg::{get_leg, get_leg_steps},
     line::{get_line, get_lines},
     shape::{get_shape, get_shape_points, get_shape_stops},
-    stop::{get_stop, get_stops},
+    stop::{get_nearest_stops, get_stop, get_stops},
     trip::{get_trip, get_trip_stops, get_trips},
+    vehicle::{get_vehicles, stream_vehicles, VehicleBroadcast},
 };
 
 use actix_cors::Cors;
 use actix_web::{middleware::Logger, web::Data, App, HttpServer};
 use dotenv::dotenv;
+use log::error;
 use model::{
-    bitfield::Bitfield, direction::Direction, direction_leg::DirectionLeg,
-    information::Information, leg_step::LegStep, line::Line, shape::Shape, shape_point::ShapePoint,
-    shape_stop::ShapeStop, stop::Stop, trip::Trip, trip_stop::TripStop,
+    bitfield::Bitfield, dataset::Dataset, direction::Direction, direction_leg::DirectionLeg,
+    information::Information, leg_step::LegStep, line::{Line, TransportMode}, shape::Shape,
+    shape_point::ShapePoint, shape_stop::ShapeStop, stop::Stop, trip::Trip, trip_stop::TripStop,
 };
 use repository::{
-    database::Database,
+    database::{Database, Table},
+    dataset::{reads_active_dataset, use_active_dataset, DatasetError, Datasets},
     hrdf::{CornerDates, Fahrplan, HRDF},
-    maps::Maps,
+    identifier::StableIds,
+    import::{ChangeReport, Import, Upsert},
+    maps::{DirectionFailure, Maps},
+    memory::MemoryDatabase,
+    migration::{Migrator, SqliteMigrator},
+    osm::OsmProvider,
+    quota::RequestQuota,
+    routing::{GoogleProvider, OsrmProvider, RoutingProvider, StraightLineProvider},
+    routing_cache::RoutingCache,
+    sqlite::SqliteDatabase,
+    storage::{
+        DirectionRepository, LineRepository, ShapeRepository, StopRepository, Storage,
+        TripRepository,
+    },
 };
 use sqlx::{
     postgres::{PgConnectOptions, PgPoolOptions},
     ConnectOptions,
 };
 
+fn postgres_options() -> PgConnectOptions {
+    PgConnectOptions::from_str(env::var("DATABASE_URL").unwrap().as_str())
+        .unwrap()
+        .disable_statement_logging()
+}
+
+async fn init_postgres() -> std::io::Result<Database> {
+    // every connection reads from the active dataset, the ones left behind by a switch are replaced
+    let database: Database = Database::init(
+        PgPoolOptions::new()
+            .after_connect(|connection, _| Box::pin(use_active_dataset(connection)))
+            .before_acquire(|connection, _| Box::pin(reads_active_dataset(connection))),
+        postgres_options(),
+    )
+    .await
+    .unwrap();
+
+    // apply pending migrations and check the schema version
+    let migrator: Migrator = Migrator {
+        database: &database,
+    };
+    let auto_migrate: bool = env::var("AUTO_MIGRATE")
+        .map(|value| value != "false")
+        .unwrap_or(true);
+    if auto_migrate {
+        match migrator.migrate().await {
+            Ok(applied) => println!("Applied migrations: {:?}", applied),
+            Err(error) => {
+                error!("Migration failed: {}", error);
+                return Err(std::io::Error::other(format!("Migration failed: {}", error)));
+            }
+        }
+    }
+    if let Err(error) = migrator.check().await {
+        return Err(std::io::Error::other(format!(
+            "Schema mismatch, refusing to serve: {}",
+            error
+        )));
+    }
+
+    // optional postgis geometries for the spatial queries
+    let postgis: bool = env::var("POSTGIS")
+        .map(|value| value == "true")
+        .unwrap_or(false);
+    if postgis {
+        if let Err(error) = migrator.enable_geometries().await {
+            error!("Enabling the geometries failed: {}", error);
+            return Err(std::io::Error::other(format!("Enabling the geometries failed: {}", error)));
+        }
+    }
+
+    Ok(database.with_postgis(postgis))
+}
+
+async fn init_sqlite() -> std::io::Result<SqliteDatabase> {
+    let database: SqliteDatabase = SqliteDatabase::init(
+        env::var("SQLITE_PATH")
+            .unwrap_or("timetable.sqlite".to_string())
+            .as_str(),
+    )
+    .await
+    .unwrap();
+
+    // bring the file to the schema of this build
+    let migrator: SqliteMigrator = SqliteMigrator {
+        database: &database,
+    };
+    match migrator.migrate().await {
+        Ok(applied) => println!("Applied migrations: {:?}", applied),
+        Err(error) => {
+            error!("Migration failed: {}", error);
+            return Err(std::io::Error::other(format!("Migration failed: {}", error)));
+        }
+    }
+
+    Ok(database)
+}
+
+// one hrdf directory per timetable period, two of them cannot start on the same day
+fn open_hrdfs() -> std::io::Result<Vec<HRDF>> {
+    let mut hrdfs: Vec<HRDF> = Vec::new();
+    for path in env::var("HRDF_PATH").unwrap().split(',') {
+        let hrdf: HRDF = HRDF::open(Path::new(path.trim()).to_path_buf(), env::var("AGENCY_ID").unwrap())
+            .map_err(|error| std::io::Error::other(format!("Cannot read the period of {}: {}", path.trim(), error)))?;
+        if hrdfs.iter().any(|other| other.information_id == hrdf.information_id) {
+            return Err(std::io::Error::other(format!(
+                "Two periods of HRDF_PATH start on the same day: {}",
+                hrdf.information_id
+            )));
+        }
+        hrdfs.push(hrdf);
+    }
+
+    Ok(hrdfs)
+}
+
+// empty settings are left out, the ones that do not parse stop the import
+fn optional<T: FromStr>(name: &str) -> std::io::Result<Option<T>> {
+    match env::var(name) {
+        Ok(value) if !value.is_empty() => value
+            .parse::<T>()
+            .map(Some)
+            .map_err(|_| std::io::Error::other(format!("{} is not valid: {}", name, value))),
+        _ => Ok(None),
+    }
+}
+
+// google by default, osrm, a local osm extract or straight lines when the import has no access to google
+async fn init_maps() -> std::io::Result<Maps> {
+    let required = |name: &str| {
+        env::var(name).map_err(|_| {
+            std::io::Error::other(format!("{} is required to route the import", name))
+        })
+    };
+
+    let provider: Box<dyn RoutingProvider> = match env::var("ROUTING_PROVIDER")
+        .unwrap_or("google".to_string())
+        .as_str()
+    {
+        "google" => Box::new(GoogleProvider {
+            api_key: required("MAPS_API_KEY")?,
+        }),
+        "osrm" => Box::new(OsrmProvider {
+            url: required("ROUTING_URL")?,
+            profile: env::var("ROUTING_PROFILE").unwrap_or("driving".to_string()),
+        }),
+        "osm" => Box::new(OsmProvider::new(required("OSM_PATH")?.into())),
+        "straight" => Box::new(StraightLineProvider::default()),
+        other => {
+            return Err(std::io::Error::other(format!(
+                "Unknown routing provider: {}",
+                other
+            )))
+        }
+    };
+    // routed legs are kept per stop pair across imports when a cache file is configured
+    let cache: Option<RoutingCache> = match env::var("ROUTING_CACHE_PATH") {
+        Ok(path) if !path.is_empty() => Some(
+            RoutingCache::open(&path)
+                .await
+                .map_err(|error| std::io::Error::other(format!("Routing cache: {}", error)))?,
+        ),
+        _ => None,
+    };
+    // spacing, retries and budget of the routing requests, unlimited unless configured
+    let quota: RequestQuota = RequestQuota::new(
+        optional::<f64>("ROUTING_RATE_LIMIT")?,
+        optional::<u32>("ROUTING_MAX_RETRIES")?.unwrap_or(5),
+        optional::<u64>("ROUTING_REQUEST_BUDGET")?,
+    );
+
+    Ok(Maps {
+        provider,
+        cache,
+        quota,
+    })
+}
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     dotenv().ok();
@@ -41,15 +217,12 @@ async fn main() -> std::io::Result<()> {
     std::env::set_var("RUST_BACKTRACE", "1");
     env_logger::init();
 
-    // init database
-    let database: Database = Database::init(
-        PgPoolOptions::new(),
-        PgConnectOptions::from_str(env::var("DATABASE_URL").unwrap().as_str())
-            .unwrap()
-            .disable_statement_logging(),
-    )
-    .await
-    .unwrap();
+    // init storage: postgres by default, a single sqlite file or memory for offline deployments
+    let storage: Storage = match env::var("STORAGE_BACKEND").as_deref() {
+        Ok("sqlite") => Storage::Sqlite(init_sqlite().await?),
+        Ok("memory") => Storage::Memory(MemoryDatabase::load(&open_hrdfs()?)?),
+        _ => Storage::Postgres(init_postgres().await?),
+    };
 
     // retreive info from gtfs
     /*let gtfs: GTFS = GTFS {
@@ -72,31 +245,6 @@ async fn main() -> std::io::Result<()> {
     let stops: Vec<Stop> = gtfs.get_stops_from_haltestellen(haltestellen, &all_stops);
     println!("{:#?}", stops);*/
 
-    // init database: create tables
-    let _ = Bitfield::create_table(&database).await;
-    let _ = Line::create_table(&database).await;
-    let _ = Stop::create_table(&database).await;
-    let _ = Shape::create_table(&database).await;
-    let _ = Trip::create_table(&database).await;
-    let _ = TripStop::create_table(&database).await;
-    let _ = Information::create_table(&database).await;
-    let _ = ShapeStop::create_table(&database).await;
-    let _ = ShapePoint::create_table(&database).await;
-    let _ = Direction::create_table(&database).await;
-    let _ = DirectionLeg::create_table(&database).await;
-    let _ = LegStep::create_table(&database).await;
-
-    // retrieve data from hrdf and insert into database
-    let hrdf: HRDF = HRDF {
-        directory: Path::new(&env::var("HRDF_PATH").unwrap().parse::<String>().unwrap())
-            .to_path_buf(),
-        agency_id: env::var("AGENCY_ID").unwrap().parse::<String>().unwrap(),
-    };
-
-    let maps = Maps {
-        api_key: env::var("MAPS_API_KEY").unwrap().parse::<String>().unwrap(),
-    };
-
     let insert_bitfields = false;
     let insert_lines = false;
     let insert_stops = false;
@@ -107,168 +255,382 @@ async fn main() -> std::io::Result<()> {
     let insert_directions = false;
 
     let insert_shapes = false; // UNSTABLE
-    let insert_shape_points = false; // UNSTABLE
+    let insert_shape_points = false;
 
     let insert_information = false;
 
-    let mut fahrplans: Vec<Fahrplan> = Vec::new();
-    let mut stops: Vec<Stop> = Vec::new();
-
-    if insert_lines {
-        println!("Getting lines...");
-        let lines: Vec<Line> = hrdf.get_lines().unwrap();
-        println!("Got lines: {}", lines.len());
-
-        println!("Inserting lines...");
-        let _l = Database::insert_many::<Line>(&database, &lines).await;
-        println!("Inserted lines");
-    }
-
-    if insert_information {
-        println!("Getting information...");
-        let corner_dates: CornerDates = hrdf.get_corner_dates().unwrap();
-        println!("Got corner dates");
-
-        println!("Inserting information...");
-        let _i = Database::insert_many::<Information>(
-            &database,
-            &vec![Information {
-                id: 1,
-                start_date: corner_dates.start_date,
-                end_date: corner_dates.end_date,
-            }],
-        )
-        .await;
-        println!("Inserted information");
-    }
-
-    if insert_trips
-        || insert_trip_stops
+    // trips are imported with their shapes or with their directions
+    let import_shapes: bool = insert_trips && (insert_shapes || insert_shape_points);
+    let imported_tables: Vec<&str> = [
+        (insert_bitfields, Bitfield::TABLE_NAME),
+        (insert_lines, Line::TABLE_NAME),
+        (insert_stops || insert_shape_points || insert_directions, Stop::TABLE_NAME),
+        (insert_information, Information::TABLE_NAME),
+        (import_shapes, Shape::TABLE_NAME),
+        (insert_trips, Trip::TABLE_NAME),
+        (insert_trip_stops && (!insert_directions || !import_shapes), TripStop::TABLE_NAME),
+        (import_shapes, ShapeStop::TABLE_NAME),
+        (import_shapes && insert_shape_points, ShapePoint::TABLE_NAME),
+        (insert_directions && !import_shapes, Direction::TABLE_NAME),
+        (insert_directions && !import_shapes, DirectionLeg::TABLE_NAME),
+        (insert_directions && !import_shapes, LegStep::TABLE_NAME),
+    ]
+    .into_iter()
+    .filter(|(imported, _)| *imported)
+    .map(|(_, table_name)| table_name)
+    .collect();
+
+    let rollback_dataset: bool = env::var("ROLLBACK_DATASET")
+        .map(|value| value == "true")
+        .unwrap_or(false);
+
+    let import: bool = insert_bitfields
+        || insert_lines
         || insert_stops
-        || insert_bitfields
-        || insert_shapes
+        || insert_trips
+        || insert_trip_stops
         || insert_directions
-    {
-        println!("Getting fahrplans...");
-        let res = hrdf.get_fahrplans();
-        if res.is_err() {
-            panic!("Error: {:?}", res.err().unwrap());
+        || insert_shapes
+        || insert_shape_points
+        || insert_information;
+    let hrdfs: Vec<HRDF> = if import { open_hrdfs()? } else { Vec::new() };
+
+    // count the routing requests the import would send, without sending them nor importing anything
+    let dry_run: bool = env::var("ROUTING_DRY_RUN")
+        .map(|value| value == "true")
+        .unwrap_or(false);
+    // routing is only set up for the imports that route
+    let maps: Option<Maps> = if import && (insert_directions || insert_shape_points) {
+        Some(init_maps().await?)
+    } else {
+        None
+    };
+    if let (Some(maps), true) = (&maps, dry_run) {
+        let mut requests: usize = 0;
+        for hrdf in hrdfs.iter() {
+            let fahrplans: Vec<Fahrplan> = hrdf.get_fahrplans().unwrap();
+            if insert_shape_points && insert_trips {
+                let (_, shapes, shape_stops) = hrdf.to_trips_and_shapes_and_shape_stops(&fahrplans);
+                let period_requests: usize = shapes
+                    .iter()
+                    .map(|shape| {
+                        maps.count_snap_requests(
+                            shape_stops.iter().filter(|shape_stop| shape_stop.shape_id == shape.id).count(),
+                        )
+                    })
+                    .sum();
+                println!(
+                    "Period {}: {} shapes, {} snap requests",
+                    hrdf.information_id,
+                    shapes.len(),
+                    period_requests
+                );
+                requests += period_requests;
+            } else if insert_directions {
+                let stops: Vec<Stop> = hrdf.retrieve_stops(hrdf.extract_stop_ids(&fahrplans)).unwrap();
+                let (_, directions) = hrdf.to_trips_and_directions(&fahrplans);
+                let period_requests: usize = hrdf
+                    .estimate_direction_requests(&fahrplans, &directions, &stops, maps)
+                    .await
+                    .unwrap();
+                println!(
+                    "Period {}: {} directions, {} routing requests",
+                    hrdf.information_id,
+                    directions.len(),
+                    period_requests
+                );
+                requests += period_requests;
+            }
         }
-        fahrplans = res.unwrap();
-        println!("Got fahrplans: {}", fahrplans.len());
-    }
 
-    if insert_bitfields {
-        println!("Getting bitfields...");
-        let bitfield_ids: Vec<i32> = hrdf.extract_bitfield_ids(&fahrplans);
-        let bitfields: Vec<Bitfield> = hrdf.retrieve_bitfields(bitfield_ids).unwrap();
-        println!("Got bitfields: {}", bitfields.len());
-
-        println!("Inserting bitfields...");
-        let _b = Database::insert_many::<Bitfield>(&database, &bitfields).await;
-        println!("Inserted bitfields");
+        println!("Dry run: {} routing requests with {}", requests, maps.provider.name());
+        if let Some(cache) = &maps.cache {
+            println!("Routing cache: {}", cache.stats());
+        }
+        if let Some(budget) = maps.quota.budget() {
+            if requests as u64 > budget {
+                println!("Over the request budget of {}", budget);
+            }
+        }
+        return Ok(());
     }
 
-    if insert_stops || insert_shape_points || insert_directions {
-        println!("Getting stops...");
-        let stops_id: Vec<i32> = hrdf.extract_stop_ids(&fahrplans);
-        stops = hrdf.retrieve_stops(stops_id).unwrap();
-        println!("Got stops: {}", stops.len());
-
-        println!("Inserting stops...");
-        let _s = Database::insert_many::<Stop>(&database, &stops).await;
-        println!("Inserted stops");
+    // upsert updates the served timetable in place, otherwise postgres imports into a new dataset
+    // switched to only once validated
+    let upsert: bool = env::var("IMPORT_MODE")
+        .map(|value| value == "upsert")
+        .unwrap_or(false);
+    let mut dataset: Option<Dataset> = None;
+    let mut importer: Import = Import::Insert(storage.clone());
+    if import && upsert {
+        match Upsert::begin(&storage).await {
+            Ok(begun) => importer = Import::Upsert(begun),
+            Err(error) => {
+                error!("Upsert failed to start: {}", error);
+                return Err(std::io::Error::other(format!("Upsert failed to start: {}", error)));
+            }
+        }
+    } else if let (true, Storage::Postgres(database)) = (import, &storage) {
+        let datasets: Datasets = Datasets { database };
+        let (created, created_database) = datasets.create(postgres_options()).await.unwrap();
+        println!("Importing into dataset {}", created.id);
+        if let Err(error) = datasets.copy_before_import(&created, &imported_tables).await {
+            println!("Invalid dataset {}: {}", created.id, error);
+            datasets.fail(&created).await.unwrap();
+            return Err(std::io::Error::other(error.to_string()));
+        }
+        dataset = Some(created);
+        importer = Import::Insert(Storage::Postgres(created_database));
     }
 
-    if insert_trip_stops && !insert_directions {
-        println!("Getting trip stops...");
-        let trip_stops = hrdf.to_trip_stops(&fahrplans);
-        println!("Got trip stops: {}", trip_stops.len());
+    let mut failures: Vec<DirectionFailure> = Vec::new();
+    let mut failed_shapes: Vec<String> = Vec::new();
+    for hrdf in hrdfs.iter() {
+        println!("Importing period {}...", hrdf.information_id);
 
-        println!("Inserting trip stops...");
-        let _ts = Database::insert_many::<TripStop>(&database, &trip_stops).await;
-        println!("Inserted trip stops");
-    }
+        let mut fahrplans: Vec<Fahrplan> = Vec::new();
+        let mut stops: Vec<Stop> = Vec::new();
 
-    if insert_trips && (insert_shapes || insert_shape_points) { // UNSTABLE
-        println!("Getting trips and shapes...");
-        let result = hrdf.to_trips_and_shapes_and_shape_stops(&fahrplans);
-        let trips: Vec<Trip> = result.0;
-        let shapes: Vec<Shape> = result.1;
-        let shape_stops: Vec<ShapeStop> = result.2;
-        println!("Got trips: {}", trips.len());
-        println!("Got shapes: {}", shapes.len());
-        println!("Got shape stops: {}", shape_stops.len());
-
-        if insert_shapes {
-            println!("Inserting shapes...");
-            let _s = Database::insert_many::<Shape>(&database, &shapes).await;
-            println!("Inserted shapes");
-
-            println!("Inserting shape stops...");
-            let _ss = Database::insert_many::<ShapeStop>(&database, &shape_stops).await;
-            println!("Inserted shape stops");
-        }
-        if insert_trips {
-            println!("Inserting trips...");
-            let _t = Database::insert_many::<Trip>(&database, &trips).await;
-            println!("Inserted trips");
-        }
-        if insert_shape_points {
-            // TO REDO (use maps.get_shape_points_from_shape_stops)
-            /*println!("Getting shape points...");
-            let shape_points: Vec<ShapePoint> =
-                hrdf.fetch_shape_points(&shapes, &shape_stops, &stops).await;
-            println!("Got shape points: {}", shape_points.len());
-
-            println!("Inserting shape points...");
-            let _sp = Database::insert_many::<ShapePoint>(&database, &shape_points).await;
-            println!("Inserted shape points");*/
+        if insert_lines {
+            println!("Getting lines...");
+            let lines: Vec<Line> = hrdf.get_lines().unwrap();
+            println!("Got lines: {}", lines.len());
+
+            println!("Inserting lines...");
+            let _l = importer.insert_many::<Line>(&lines).await.unwrap();
+            println!("Inserted lines");
         }
-    } else if insert_trips || insert_directions { // STABLE
-        println!("Getting trips and directions...");
-        let result = hrdf.to_trips_and_directions(&fahrplans);
-        let trips: Vec<Trip> = result.0;
-        let directions: Vec<Direction> = result.1;
-        println!("Got trips: {}", trips.len());
-        println!("Got directions: {}", directions.len());
-
-        let mut trip_stops: Vec<TripStop> = Vec::new();	
-        if insert_directions {
-            println!("Getting trip stops, direction legs and steps...");
-            let (_trip_stops, direction_legs, leg_steps) = hrdf
-                .get_trip_stops_with_directions(&fahrplans, &directions, &stops, maps)
+
+        if insert_information {
+            println!("Getting information...");
+            let corner_dates: CornerDates = hrdf.get_corner_dates().unwrap();
+            println!("Got corner dates");
+
+            println!("Inserting information...");
+            let _i = importer
+                .insert_many::<Information>(&vec![Information {
+                    id: hrdf.information_id,
+                    start_date: corner_dates.start_date,
+                    end_date: corner_dates.end_date,
+                }])
                 .await
                 .unwrap();
+            println!("Inserted information");
+        }
 
-            trip_stops = _trip_stops;
-            println!("Got trip stops: {}", trip_stops.len());
-            println!("Got direction legs: {}", direction_legs.len());
-            println!("Got leg steps: {}", leg_steps.len());
-
-            println!("Inserting direction, steps and legs...");
-            let _d = Database::insert_many::<Direction>(&database, &directions).await;
-            let _dl = Database::insert_many::<DirectionLeg>(&database, &direction_legs).await;
-            let _ls = Database::insert_many::<LegStep>(&database, &leg_steps).await;
-            println!("Inserted direction, steps and legs");
+        if insert_trips
+            || insert_trip_stops
+            || insert_stops
+            || insert_bitfields
+            || insert_shapes
+            || insert_shape_points
+            || insert_directions
+        {
+            println!("Getting fahrplans...");
+            let res = hrdf.get_fahrplans();
+            if res.is_err() {
+                panic!("Error: {:?}", res.err().unwrap());
+            }
+            fahrplans = res.unwrap();
+            println!("Got fahrplans: {}", fahrplans.len());
         }
 
-        if insert_trips {
-            println!("Inserting trips...");
-            let _t = Database::insert_many::<Trip>(&database, &trips).await;
-            println!("Inserted trips");
+        if insert_bitfields {
+            println!("Getting bitfields...");
+            let bitfield_ids: Vec<i32> = hrdf.extract_bitfield_ids(&fahrplans);
+            let bitfields: Vec<Bitfield> = hrdf.retrieve_bitfields(bitfield_ids).unwrap();
+            println!("Got bitfields: {}", bitfields.len());
+
+            println!("Inserting bitfields...");
+            let _b = importer.insert_many::<Bitfield>(&bitfields).await.unwrap();
+            println!("Inserted bitfields");
         }
 
-        if insert_trip_stops {
+        if insert_stops || insert_shape_points || insert_directions {
+            println!("Getting stops...");
+            let stops_id: Vec<i32> = hrdf.extract_stop_ids(&fahrplans);
+            stops = hrdf.retrieve_stops(stops_id).unwrap();
+            println!("Got stops: {}", stops.len());
+
+            println!("Inserting stops...");
+            let _s = importer.insert_many::<Stop>(&stops).await.unwrap();
+            println!("Inserted stops");
+        }
+
+        if insert_trip_stops && !insert_directions {
+            println!("Getting trip stops...");
+            let platforms = hrdf.get_platforms().unwrap();
+            let trip_stops = hrdf.to_trip_stops(&fahrplans, &platforms);
+            println!("Got trip stops: {}", trip_stops.len());
+
             println!("Inserting trip stops...");
-            let _ts = Database::insert_many::<TripStop>(&database, &trip_stops).await;
+            let _ts = importer.insert_many::<TripStop>(&trip_stops).await.unwrap();
             println!("Inserted trip stops");
         }
+
+        if import_shapes { // UNSTABLE
+            println!("Getting trips and shapes...");
+            let result = hrdf.to_trips_and_shapes_and_shape_stops(&fahrplans);
+            let trips: Vec<Trip> = result.0;
+            let shapes: Vec<Shape> = result.1;
+            let shape_stops: Vec<ShapeStop> = result.2;
+            println!("Got trips: {}", trips.len());
+            println!("Got shapes: {}", shapes.len());
+            println!("Got shape stops: {}", shape_stops.len());
+
+            // shape points reference the shapes and their stops
+            if insert_shapes || insert_shape_points {
+                println!("Inserting shapes...");
+                let _s = importer.insert_many::<Shape>(&shapes).await.unwrap();
+                println!("Inserted shapes");
+
+                println!("Inserting shape stops...");
+                let _ss = importer.insert_many::<ShapeStop>(&shape_stops).await.unwrap();
+                println!("Inserted shape stops");
+            }
+            if insert_trips {
+                println!("Inserting trips...");
+                let _t = importer.insert_many::<Trip>(&trips).await.unwrap();
+                println!("Inserted trips");
+            }
+            if let (true, Some(maps)) = (insert_shape_points, &maps) {
+                println!("Getting shape points...");
+                let mut shape_points: Vec<ShapePoint> = Vec::new();
+                let mut shape_point_ids: StableIds = StableIds::new();
+                for shape in &shapes {
+                    let sstops: Vec<ShapeStop> = shape_stops
+                        .iter()
+                        .filter(|shape_stop| shape_stop.shape_id == shape.id)
+                        .cloned()
+                        .collect();
+                    let mode: TransportMode = trips
+                        .iter()
+                        .find(|trip| trip.shape_id == Some(shape.id))
+                        .unwrap()
+                        .transport_mode;
+
+                    match maps
+                        .get_shape_points_from_shape_stops(shape.id, mode, &sstops, &stops, &mut shape_point_ids)
+                        .await
+                    {
+                        Ok(points) => shape_points.extend(points),
+                        Err(error) => failed_shapes.push(format!("{} ({}): {}", shape.identifier, shape.id, error)),
+                    }
+                }
+                println!("Got shape points: {}", shape_points.len());
+
+                println!("Inserting shape points...");
+                let _sp = importer.insert_many::<ShapePoint>(&shape_points).await.unwrap();
+                println!("Inserted shape points");
+            }
+        } else if insert_trips || insert_directions { // STABLE
+            println!("Getting trips and directions...");
+            let result = hrdf.to_trips_and_directions(&fahrplans);
+            let trips: Vec<Trip> = result.0;
+            let directions: Vec<Direction> = result.1;
+            println!("Got trips: {}", trips.len());
+            println!("Got directions: {}", directions.len());
+
+            let mut trip_stops: Vec<TripStop> = Vec::new();	
+            if let (true, Some(maps)) = (insert_directions, &maps) {
+                println!("Getting trip stops, direction legs and steps...");
+                let platforms = hrdf.get_platforms().unwrap();
+                let (_trip_stops, direction_legs, leg_steps, _failures) = hrdf
+                    .get_trip_stops_with_directions(&fahrplans, &directions, &stops, &platforms, maps)
+                    .await
+                    .unwrap();
+                failures.extend(_failures);
+
+                trip_stops = _trip_stops;
+                println!("Got trip stops: {}", trip_stops.len());
+                println!("Got direction legs: {}", direction_legs.len());
+                println!("Got leg steps: {}", leg_steps.len());
+
+                println!("Inserting direction, steps and legs...");
+                let _d = importer.insert_many::<Direction>(&directions).await.unwrap();
+                let _dl = importer.insert_many::<DirectionLeg>(&direction_legs).await.unwrap();
+                let _ls = importer.insert_many::<LegStep>(&leg_steps).await.unwrap();
+                println!("Inserted direction, steps and legs");
+            }
+
+            if insert_trips {
+                println!("Inserting trips...");
+                let _t = importer.insert_many::<Trip>(&trips).await.unwrap();
+                println!("Inserted trips");
+            }
+
+            if insert_trip_stops {
+                println!("Inserting trip stops...");
+                let _ts = importer.insert_many::<TripStop>(&trip_stops).await.unwrap();
+                println!("Inserted trip stops");
+            }
+        }
+    }
+
+    if let Some(maps) = &maps {
+        if let (true, Some(cache)) = (insert_directions, &maps.cache) {
+            println!("Routing cache: {}", cache.stats());
+        }
+        println!("Routing requests: {}", maps.quota.stats());
+    }
+    if !failures.is_empty() {
+        println!("Failed directions: {}", failures.len());
+        for failure in &failures {
+            println!("  {}", failure);
+        }
+    }
+    if !failed_shapes.is_empty() {
+        println!("Failed shapes: {}", failed_shapes.len());
+        for failure in &failed_shapes {
+            println!("  {}", failure);
+        }
+    }
+
+    if let Import::Upsert(upsert) = importer {
+        let report: ChangeReport = match upsert.finish().await {
+            Ok(report) => report,
+            Err(error) => {
+                error!("Upsert failed to finish: {}", error);
+                return Err(std::io::Error::other(format!("Upsert failed to finish: {}", error)));
+            }
+        };
+        print!("Import changes:\n{}", report);
+    }
+
+    if let Storage::Postgres(database) = &storage {
+        let datasets: Datasets = Datasets { database };
+        if let Some(dataset) = dataset {
+            println!("Validating dataset {}...", dataset.id);
+            let valid: Result<(), DatasetError> =
+                match datasets.copy_after_import(&dataset, &imported_tables).await {
+                    Ok(()) => datasets.validate(&dataset).await,
+                    Err(error) => Err(error),
+                };
+            match valid {
+                Ok(()) => {
+                    datasets.activate(&dataset).await.unwrap();
+                    println!("Activated dataset {}", dataset.id);
+                }
+                Err(error) => {
+                    println!("Invalid dataset {}: {}", dataset.id, error);
+                    datasets.fail(&dataset).await.unwrap();
+                }
+            }
+        } else if rollback_dataset {
+            let previous: Dataset = datasets.rollback().await.unwrap();
+            println!("Rolled back to dataset {}", previous.id);
+        }
     }
 
     // init http server
+    let lines: Data<dyn LineRepository> = Data::from(storage.lines());
+    let stops: Data<dyn StopRepository> = Data::from(storage.stops());
+    let trips: Data<dyn TripRepository> = Data::from(storage.trips());
+    let directions: Data<dyn DirectionRepository> = Data::from(storage.directions());
+    let shapes: Data<dyn ShapeRepository> = Data::from(storage.shapes());
+    let vehicles: Data<VehicleBroadcast> = Data::new(VehicleBroadcast::default());
+
     HttpServer::new(move || {
-        let db_data: Data<Database> = Data::new(database.clone());
 
         let logger: Logger = Logger::default();
 
@@ -277,16 +639,29 @@ async fn main() -> std::io::Result<()> {
             .allowed_methods(vec!["GET"]);
 
         App::new()
-            .app_data(db_data)
+            .app_data(lines.clone())
+            .app_data(stops.clone())
+            .app_data(trips.clone())
+            .app_data(directions.clone())
+            .app_data(shapes.clone())
+            .app_data(vehicles.clone())
             .wrap(cors)
             .wrap(logger)
             .service(get_line)
             .service(get_lines)
             .service(get_stop)
             .service(get_stops)
+            .service(get_nearest_stops)
+            .service(get_stop_departures)
+            .service(get_stop_arrivals)
             .service(get_trip)
             .service(get_trip_stops)
             .service(get_trips)
+            .service(get_vehicles)
+            .service(get_journeys)
+            .service(get_isochrone)
+            .service(stream_vehicles)
+            .service(get_bitfield)
             .service(get_shape)
             .service(get_shape_points)
             .service(get_shape_stops)
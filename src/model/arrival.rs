@@ -0,0 +1,18 @@
+use chrono::NaiveTime;
+use serde::Serialize;
+
+// trip reaching a stop, as shown on an arrival board
+#[derive(Serialize, Debug)]
+pub struct Arrival {
+    pub trip_id: i64,
+    pub line_id: i32,
+    pub line_name: String,
+    pub line_color: String,
+    // name of the first stop
+    pub origin: String,
+    pub origin_id: i32,
+    pub arrival_time: NaiveTime,
+    pub platform: Option<String>,
+    // the trip ends at the stop
+    pub terminates: bool,
+}
@@ -1,12 +1,18 @@
-use async_trait::async_trait;
+use chrono::NaiveDate;
 use serde::Serialize;
-use sqlx::{FromRow, Error, postgres::PgQueryResult};
+use sqlx::FromRow;
 
-use crate::repository::database::{Database, Table};
+use crate::repository::database::{Table, Value};
 
-#[derive(Serialize, FromRow, Debug)]
+use super::information::Information;
+
+// the hrdf bitfields start two days before the timetable period and end two days after it
+const PADDING_DAYS: usize = 2;
+
+#[derive(Serialize, FromRow, Debug, Clone)]
 pub struct Bitfield {
     pub id: i32,
+    pub information_id: i32,
     pub days: String,
 }
 
@@ -22,27 +28,95 @@ impl Bitfield {
         }
         return bits;
     }
+
+    // index of date in the days of the period, None outside of it
+    pub fn day_index(information: &Information, date: NaiveDate) -> Option<usize> {
+        if date < information.start_date || date > information.end_date {
+            return None;
+        }
+
+        Some((date - information.start_date).num_days() as usize + PADDING_DAYS)
+    }
+
+    pub fn is_active(&self, information: &Information, date: NaiveDate) -> bool {
+        Bitfield::day_index(information, date)
+            .and_then(|i| self.days.as_bytes().get(i))
+            .map(|day| *day == b'1')
+            .unwrap_or(false)
+    }
 }
 
-#[async_trait]
 impl Table for Bitfield {
     const TABLE_NAME: &'static str = "bitfields";
+    const PRIMARY_KEY: &'static [&'static str] = &["information_id", "id"];
 
-    fn values(&self) -> Vec<Box<dyn std::any::Any>> {
+    fn values(&self) -> Vec<Value> {
         vec![
-            Box::new(self.id),
-            Box::new(self.days.to_string()),
+            self.id.into(),
+            self.information_id.into(),
+            Value::Bits(Some(self.days.to_string())),
         ]
     }
 
     fn keys() -> String {
-        return "(id,days)".to_string();
+        return "(id,information_id,days)".to_string();
+    }
+}
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    fn information() -> Information {
+        Information {
+            id: 1,
+            start_date: NaiveDate::from_ymd_opt(2023, 12, 10).unwrap(),
+            end_date: NaiveDate::from_ymd_opt(2023, 12, 16).unwrap(),
+        }
     }
 
-    async fn create_table(database: &Database) -> Result<PgQueryResult, Error> {
-        database.query(format!("CREATE TABLE IF NOT EXISTS {} (
-            id INTEGER PRIMARY KEY,
-            days VARCHAR(400) NOT NULL
-        )", Self::TABLE_NAME).as_str()).await
+    #[test]
+    fn day_index_skips_the_padding() {
+        let information: Information = information();
+
+        assert_eq!(Bitfield::day_index(&information, information.start_date), Some(2));
+        assert_eq!(Bitfield::day_index(&information, NaiveDate::from_ymd_opt(2023, 12, 11).unwrap()), Some(3));
+        assert_eq!(Bitfield::day_index(&information, information.end_date), Some(8));
+    }
+
+    #[test]
+    fn day_index_is_none_outside_the_period() {
+        let information: Information = information();
+
+        assert_eq!(Bitfield::day_index(&information, NaiveDate::from_ymd_opt(2023, 12, 9).unwrap()), None);
+        assert_eq!(Bitfield::day_index(&information, NaiveDate::from_ymd_opt(2023, 12, 17).unwrap()), None);
+    }
+
+    #[test]
+    fn is_active_reads_the_bit_of_the_day() {
+        let information: Information = information();
+        // padding, then the 10th to the 16th with the 11th and the 16th off
+        let bitfield: Bitfield = Bitfield { id: 1, information_id: 1, days: "111011110".to_string() };
+
+        assert!(bitfield.is_active(&information, NaiveDate::from_ymd_opt(2023, 12, 10).unwrap()));
+        assert!(!bitfield.is_active(&information, NaiveDate::from_ymd_opt(2023, 12, 11).unwrap()));
+        assert!(bitfield.is_active(&information, NaiveDate::from_ymd_opt(2023, 12, 15).unwrap()));
+        assert!(!bitfield.is_active(&information, NaiveDate::from_ymd_opt(2023, 12, 16).unwrap()));
+        assert!(!bitfield.is_active(&information, NaiveDate::from_ymd_opt(2023, 12, 17).unwrap()));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn is_active_is_false_past_the_bits() {
+        let information: Information = information();
+        let bitfield: Bitfield = Bitfield { id: 1, information_id: 1, days: "111".to_string() };
+
+        assert!(bitfield.is_active(&information, information.start_date));
+        assert!(!bitfield.is_active(&information, NaiveDate::from_ymd_opt(2023, 12, 11).unwrap()));
+    }
+
+    #[test]
+    fn hex_digits_become_four_bits() {
+        assert_eq!(Bitfield::convert_hex_to_bits("F0a1"), "1111000010100001");
+    }
+}
@@ -0,0 +1,306 @@
+use chrono::{Datelike, Duration, NaiveDate, Weekday};
+use serde::Serialize;
+
+use super::{bitfield::Bitfield, information::Information};
+
+const WEEKDAYS: [Weekday; 7] = [
+    Weekday::Mon,
+    Weekday::Tue,
+    Weekday::Wed,
+    Weekday::Thu,
+    Weekday::Fri,
+    Weekday::Sat,
+    Weekday::Sun,
+];
+
+// longer lists of exceptions are cut, the operating dates stay complete
+const MAX_LISTED_DATES: usize = 10;
+
+// days a bitfield runs on, described for riders
+#[derive(Serialize, Debug)]
+pub struct ServiceCalendar {
+    pub bitfield_id: i32,
+    pub information_id: i32,
+    pub description: String,
+    pub dates: Vec<NaiveDate>,
+}
+
+// anonymous gregorian algorithm
+fn easter_sunday(year: i32) -> NaiveDate {
+    let a: i32 = year % 19;
+    let b: i32 = year / 100;
+    let c: i32 = year % 100;
+    let d: i32 = b / 4;
+    let e: i32 = b % 4;
+    let f: i32 = (b + 8) / 25;
+    let g: i32 = (b - f + 1) / 3;
+    let h: i32 = (19 * a + b - d - g + 15) % 30;
+    let i: i32 = c / 4;
+    let k: i32 = c % 4;
+    let l: i32 = (32 + 2 * e + 2 * i - h - k) % 7;
+    let m: i32 = (a + 11 * h + 22 * l) / 451;
+    let month: i32 = (h + l - 7 * m + 114) / 31;
+    let day: i32 = (h + l - 7 * m + 114) % 31 + 1;
+
+    NaiveDate::from_ymd_opt(year, month as u32, day as u32).unwrap()
+}
+
+// public holidays of the canton of geneva
+fn holidays(year: i32) -> Vec<NaiveDate> {
+    let easter: NaiveDate = easter_sunday(year);
+    // jeune genevois, thursday after the first sunday of september
+    let first_sunday: NaiveDate = NaiveDate::from_weekday_of_month_opt(year, 9, Weekday::Sun, 1).unwrap();
+
+    vec![
+        NaiveDate::from_ymd_opt(year, 1, 1).unwrap(),
+        easter - Duration::days(2),
+        easter + Duration::days(1),
+        easter + Duration::days(39),
+        easter + Duration::days(50),
+        NaiveDate::from_ymd_opt(year, 8, 1).unwrap(),
+        first_sunday + Duration::days(4),
+        NaiveDate::from_ymd_opt(year, 12, 25).unwrap(),
+        NaiveDate::from_ymd_opt(year, 12, 31).unwrap(),
+    ]
+}
+
+fn weekday_name(weekday: Weekday) -> &'static str {
+    match weekday {
+        Weekday::Mon => "Mon",
+        Weekday::Tue => "Tue",
+        Weekday::Wed => "Wed",
+        Weekday::Thu => "Thu",
+        Weekday::Fri => "Fri",
+        Weekday::Sat => "Sat",
+        Weekday::Sun => "Sun",
+    }
+}
+
+// runs of consecutive weekdays, "Mon–Fri, Sun"
+fn describe_weekdays(included: &[bool; 7]) -> String {
+    if included.iter().all(|day| *day) {
+        return "daily".to_string();
+    }
+
+    let mut runs: Vec<String> = Vec::new();
+    let mut i: usize = 0;
+    while i < 7 {
+        if !included[i] {
+            i += 1;
+            continue;
+        }
+        let start: usize = i;
+        while i + 1 < 7 && included[i + 1] {
+            i += 1;
+        }
+        runs.push(match i - start {
+            0 => weekday_name(WEEKDAYS[start]).to_string(),
+            1 => format!("{}, {}", weekday_name(WEEKDAYS[start]), weekday_name(WEEKDAYS[i])),
+            _ => format!("{}–{}", weekday_name(WEEKDAYS[start]), weekday_name(WEEKDAYS[i])),
+        });
+        i += 1;
+    }
+
+    return runs.join(", ");
+}
+
+impl ServiceCalendar {
+    pub fn from_bitfield(bitfield: &Bitfield, information: &Information) -> ServiceCalendar {
+        let period: Vec<NaiveDate> = information
+            .start_date
+            .iter_days()
+            .take_while(|date| *date <= information.end_date)
+            .collect();
+        let dates: Vec<NaiveDate> = period
+            .iter()
+            .copied()
+            .filter(|date| bitfield.is_active(information, *date))
+            .collect();
+
+        let holidays: Vec<NaiveDate> = (information.start_date.year()..=information.end_date.year())
+            .flat_map(holidays)
+            .collect();
+
+        // a weekday is part of the pattern when the service runs on most of its ordinary dates,
+        // holidays often follow the sunday service and would blur the pattern
+        let mut included: [bool; 7] = [false; 7];
+        for (i, weekday) in WEEKDAYS.iter().enumerate() {
+            let ordinary: Vec<NaiveDate> = period
+                .iter()
+                .copied()
+                .filter(|date| date.weekday() == *weekday && !holidays.contains(date))
+                .collect();
+            let active: usize = ordinary
+                .iter()
+                .filter(|date| bitfield.is_active(information, **date))
+                .count();
+            included[i] = !ordinary.is_empty() && active * 2 > ordinary.len();
+        }
+
+        let is_included = |date: &NaiveDate| included[date.weekday().num_days_from_monday() as usize];
+        let exceptions: Vec<NaiveDate> = period
+            .iter()
+            .copied()
+            .filter(|date| is_included(date) && !bitfield.is_active(information, *date))
+            .collect();
+        let additions: Vec<NaiveDate> = dates.iter().copied().filter(|date| !is_included(date)).collect();
+
+        let description: String = if dates.is_empty() {
+            "no service".to_string()
+        } else if included.iter().all(|day| !*day) {
+            format!("only {}", ServiceCalendar::describe_dates(&additions, information))
+        } else {
+            let mut description: String = describe_weekdays(&included);
+            if !exceptions.is_empty() {
+                description.push_str(
+                    format!(" except {}", ServiceCalendar::describe_dates(&exceptions, information)).as_str(),
+                );
+            }
+            if !additions.is_empty() {
+                description.push_str(
+                    format!(", also {}", ServiceCalendar::describe_dates(&additions, information)).as_str(),
+                );
+            }
+            description
+        };
+
+        ServiceCalendar {
+            bitfield_id: bitfield.id,
+            information_id: information.id,
+            description,
+            dates,
+        }
+    }
+
+    // "25.12, 01.01", the year is only written when the day occurs twice in the period
+    fn describe_dates(dates: &[NaiveDate], information: &Information) -> String {
+        let in_period = |date: Option<NaiveDate>| {
+            date.map(|date| date >= information.start_date && date <= information.end_date)
+                .unwrap_or(false)
+        };
+
+        let mut described: Vec<String> = dates
+            .iter()
+            .take(MAX_LISTED_DATES)
+            .map(|date| {
+                if in_period(date.with_year(date.year() - 1)) || in_period(date.with_year(date.year() + 1)) {
+                    date.format("%d.%m.%Y").to_string()
+                } else {
+                    date.format("%d.%m").to_string()
+                }
+            })
+            .collect();
+        if dates.len() > MAX_LISTED_DATES {
+            described.push(format!("and {} more", dates.len() - MAX_LISTED_DATES));
+        }
+
+        return described.join(", ");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 04.12.2023 to 07.01.2024, monday to sunday, with christmas and new year on mondays
+    fn information() -> Information {
+        Information {
+            id: 1,
+            start_date: NaiveDate::from_ymd_opt(2023, 12, 4).unwrap(),
+            end_date: NaiveDate::from_ymd_opt(2024, 1, 7).unwrap(),
+        }
+    }
+
+    // bits of the period with the padding of the hrdf around them
+    fn bitfield(information: &Information, runs: impl Fn(NaiveDate) -> bool) -> Bitfield {
+        let days: String = information
+            .start_date
+            .iter_days()
+            .take_while(|date| *date <= information.end_date)
+            .map(|date| if runs(date) { '1' } else { '0' })
+            .collect();
+
+        Bitfield { id: 7, information_id: information.id, days: format!("00{}00", days) }
+    }
+
+    fn date(day: u32, month: u32, year: i32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    fn describe(runs: impl Fn(NaiveDate) -> bool) -> String {
+        let information: Information = information();
+        return ServiceCalendar::from_bitfield(&bitfield(&information, runs), &information).description;
+    }
+
+    #[test]
+    fn every_day_is_daily() {
+        assert_eq!(describe(|_| true), "daily");
+    }
+
+    #[test]
+    fn no_day_is_no_service() {
+        assert_eq!(describe(|_| false), "no service");
+    }
+
+    #[test]
+    fn workdays_list_the_holidays_as_exceptions() {
+        let holidays: Vec<NaiveDate> = vec![date(25, 12, 2023), date(1, 1, 2024)];
+        let description: String =
+            describe(|day| day.weekday().num_days_from_monday() < 5 && !holidays.contains(&day));
+
+        assert_eq!(description, "Mon–Fri except 25.12, 01.01");
+    }
+
+    #[test]
+    fn weekends_list_the_holidays_as_additions() {
+        let holidays: Vec<NaiveDate> = vec![date(25, 12, 2023), date(1, 1, 2024)];
+        let description: String =
+            describe(|day| day.weekday().num_days_from_monday() >= 5 || holidays.contains(&day));
+
+        assert_eq!(description, "Sat, Sun, also 25.12, 01.01");
+    }
+
+    #[test]
+    fn separate_weekdays_are_listed_apart() {
+        let description: String =
+            describe(|day| day.weekday() != Weekday::Tue && day.weekday().num_days_from_monday() < 5);
+
+        assert_eq!(description, "Mon, Wed–Fri");
+    }
+
+    #[test]
+    fn scattered_dates_are_listed() {
+        assert_eq!(describe(|day| day == date(20, 12, 2023) || day == date(3, 1, 2024)), "only 20.12, 03.01");
+    }
+
+    #[test]
+    fn long_lists_of_dates_are_cut() {
+        // tuesday to sunday of the first two weeks, two of five of each weekday
+        let description: String =
+            describe(|day| day >= date(5, 12, 2023) && day <= date(17, 12, 2023) && day != date(11, 12, 2023));
+
+        assert_eq!(
+            description,
+            "only 05.12, 06.12, 07.12, 08.12, 09.12, 10.12, 12.12, 13.12, 14.12, 15.12, and 2 more"
+        );
+    }
+
+    #[test]
+    fn dates_recurring_in_the_period_carry_their_year() {
+        let information: Information = Information {
+            id: 1,
+            start_date: date(1, 12, 2023),
+            end_date: date(31, 12, 2024),
+        };
+        let dates: Vec<NaiveDate> = vec![date(25, 12, 2023), date(15, 6, 2024)];
+
+        assert_eq!(ServiceCalendar::describe_dates(&dates, &information), "25.12.2023, 15.06");
+    }
+
+    #[test]
+    fn easter_follows_the_gregorian_computus() {
+        assert_eq!(easter_sunday(2023), date(9, 4, 2023));
+        assert_eq!(easter_sunday(2024), date(31, 3, 2024));
+        assert_eq!(easter_sunday(2025), date(20, 4, 2025));
+    }
+}
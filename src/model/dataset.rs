@@ -0,0 +1,67 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::FromRow;
+
+use crate::repository::database::{Table, Value};
+
+#[derive(Serialize, Debug, PartialEq, Clone, Copy)]
+pub enum DatasetStatus {
+    Loading,
+    Ready,
+    Active,
+    Retired,
+    Failed,
+}
+
+impl TryFrom<String> for DatasetStatus {
+    type Error = ();
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        return Self::from_str(value.as_str());
+    }
+}
+
+impl FromStr for DatasetStatus {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let t: Self = match s {
+            "Loading" => Self::Loading,
+            "Ready" => Self::Ready,
+            "Active" => Self::Active,
+            "Retired" => Self::Retired,
+            "Failed" => Self::Failed,
+            _ => return Err(()),
+        };
+        Ok(t)
+    }
+}
+
+#[derive(Serialize, FromRow, Debug)]
+pub struct Dataset {
+    pub id: i32,
+    #[sqlx(try_from = "String")]
+    pub status: DatasetStatus,
+    pub created_at: DateTime<Utc>,
+    pub activated_at: Option<DateTime<Utc>>,
+}
+
+impl Dataset {
+    pub fn schema_name(&self) -> String {
+        return format!("dataset_{}", self.id);
+    }
+}
+
+impl Table for Dataset {
+    const TABLE_NAME: &'static str = "datasets";
+
+    fn values(&self) -> Vec<Value> {
+        vec![self.id.into(), format!("{:?}", self.status).into()]
+    }
+
+    fn keys() -> String {
+        return "(id,status)".to_string();
+    }
+}
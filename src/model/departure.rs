@@ -0,0 +1,16 @@
+use chrono::NaiveTime;
+use serde::Serialize;
+
+// trip leaving a stop, as shown on a departure board
+#[derive(Serialize, Debug)]
+pub struct Departure {
+    pub trip_id: i64,
+    pub line_id: i32,
+    pub line_name: String,
+    pub line_color: String,
+    // name of the terminus
+    pub headsign: String,
+    pub destination_id: i32,
+    pub departure_time: NaiveTime,
+    pub platform: Option<String>,
+}
@@ -1,66 +1,107 @@
-use async_trait::async_trait;
+use chrono::{NaiveTime, Timelike};
 use serde::Serialize;
-use sqlx::{postgres::PgQueryResult, Error, FromRow};
+use sqlx::{
+    error::BoxDynError,
+    postgres::{PgTypeInfo, PgValueRef},
+    sqlite::{SqliteTypeInfo, SqliteValueRef},
+    Decode, FromRow, Postgres, Sqlite, Type,
+};
 
-use crate::repository::database::{Database, Table};
+use crate::repository::database::{Table, Value};
 
-#[derive(Serialize, FromRow, Debug)]
+// first hour of each time-of-day band: night, morning peak, day, evening peak, evening
+pub const TIME_BANDS: [u32; 5] = [0, 6, 9, 16, 19];
+
+// shortest stop at an intermediate stop, in seconds
+pub const MIN_DWELL: i32 = 15;
+
+pub fn time_band(time: NaiveTime) -> usize {
+    return TIME_BANDS.iter().rposition(|hour| time.hour() >= *hour).unwrap();
+}
+
+// seconds per time band, an integer array in postgres and a json array in sqlite
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(transparent)]
+pub struct BandTimes(pub Vec<i32>);
+
+impl Type<Postgres> for BandTimes {
+    fn type_info() -> PgTypeInfo {
+        <Vec<i32> as Type<Postgres>>::type_info()
+    }
+}
+
+impl<'r> Decode<'r, Postgres> for BandTimes {
+    fn decode(value: PgValueRef<'r>) -> Result<Self, BoxDynError> {
+        Ok(BandTimes(<Vec<i32> as Decode<Postgres>>::decode(value)?))
+    }
+}
+
+impl Type<Sqlite> for BandTimes {
+    fn type_info() -> SqliteTypeInfo {
+        <String as Type<Sqlite>>::type_info()
+    }
+}
+
+impl<'r> Decode<'r, Sqlite> for BandTimes {
+    fn decode(value: SqliteValueRef<'r>) -> Result<Self, BoxDynError> {
+        let text: &str = <&str as Decode<Sqlite>>::decode(value)?;
+        Ok(BandTimes(serde_json::from_str(text)?))
+    }
+}
+
+#[derive(Serialize, FromRow, Debug, Clone)]
 pub struct DirectionLeg {
-    pub id: i32,
-    pub direction_id: i32,
+    pub id: i64,
+    pub direction_id: i64,
     pub distance: i32,
     pub duration: i32,
     pub sequence: i16,
     pub origin_id: i32,
     pub destination_id: i32,
+    // running time to the destination and dwell there per time band, fitted to the timetable,
+    // none until calibrated
+    pub running_times: Option<BandTimes>,
+    pub dwell_times: Option<BandTimes>,
+}
+
+impl DirectionLeg {
+    fn band_time(times: &Option<BandTimes>, band: usize) -> Option<i32> {
+        return times.as_ref().and_then(|times| times.0.get(band).copied());
+    }
+
+    // routed duration until calibrated
+    pub fn running_time(&self, band: usize) -> i32 {
+        return DirectionLeg::band_time(&self.running_times, band).unwrap_or(self.duration);
+    }
+
+    pub fn dwell_time(&self, band: usize) -> i32 {
+        return DirectionLeg::band_time(&self.dwell_times, band).unwrap_or(MIN_DWELL);
+    }
+
+    pub fn set_band_times(&mut self, running_times: &[i32], dwell_times: &[i32]) {
+        self.running_times = Some(BandTimes(running_times.to_vec()));
+        self.dwell_times = Some(BandTimes(dwell_times.to_vec()));
+    }
 }
 
-#[async_trait]
 impl Table for DirectionLeg {
     const TABLE_NAME: &'static str = "direction_legs";
 
-    fn values(&self) -> Vec<Box<dyn std::any::Any>> {
+    fn values(&self) -> Vec<Value> {
         vec![
-            Box::new(self.id),
-            Box::new(self.direction_id),
-            Box::new(self.distance),
-            Box::new(self.duration),
-            Box::new(self.sequence),
-            Box::new(self.origin_id),
-            Box::new(self.destination_id),
+            self.id.into(),
+            self.direction_id.into(),
+            self.distance.into(),
+            self.duration.into(),
+            self.sequence.into(),
+            self.origin_id.into(),
+            self.destination_id.into(),
+            self.running_times.clone().map(|times| times.0).into(),
+            self.dwell_times.clone().map(|times| times.0).into(),
         ]
     }
 
     fn keys() -> String {
-        return "(id,direction_id,distance,duration,sequence,origin_id,destination_id)".to_string();
-    }
-
-    async fn create_table(database: &Database) -> Result<PgQueryResult, Error> {
-        database
-            .query(
-                format!(
-                    "CREATE TABLE IF NOT EXISTS {} (
-            id INTEGER PRIMARY KEY,
-            direction_id INTEGER NOT NULL,
-            distance INTEGER NOT NULL,
-            duration INTEGER NOT NULL,
-            sequence SMALLINT NOT NULL,
-            origin_id INTEGER NOT NULL,
-            destination_id INTEGER NOT NULL,
-            CONSTRAINT fk_origin
-                FOREIGN KEY(origin_id)
-                    REFERENCES stops(id),
-            CONSTRAINT fk_destination
-                FOREIGN KEY(destination_id)
-                    REFERENCES stops(id),
-            CONSTRAINT fk_direction
-                FOREIGN KEY(direction_id)
-                    REFERENCES directions(id)
-        )",
-                    Self::TABLE_NAME
-                )
-                .as_str(),
-            )
-            .await
+        return "(id,direction_id,distance,duration,sequence,origin_id,destination_id,running_times,dwell_times)".to_string();
     }
 }
@@ -1,49 +1,28 @@
-use async_trait::async_trait;
 use chrono::NaiveDate;
 use serde::Serialize;
-use sqlx::{
-    postgres::PgQueryResult,
-    Error, FromRow
-};
+use sqlx::FromRow;
 
-use crate::repository::database::{Database, Table};
+use crate::repository::database::{Table, Value};
 
-#[derive(Serialize, Debug, FromRow)]
+#[derive(Serialize, Debug, FromRow, Clone)]
 pub struct Information {
     pub id: i32,
     pub start_date: NaiveDate,
     pub end_date: NaiveDate,
 }
 
-#[async_trait]
 impl Table for Information {
     const TABLE_NAME: &'static str = "information";
 
-    fn values(&self) -> Vec<Box<dyn std::any::Any>> {
+    fn values(&self) -> Vec<Value> {
         vec![
-            Box::new(self.id),
-            Box::new(self.start_date),
-            Box::new(self.end_date),
+            self.id.into(),
+            self.start_date.into(),
+            self.end_date.into(),
         ]
     }
 
     fn keys() -> String {
         return "(id,start_date,end_date)".to_string();
     }
-
-    async fn create_table(database: &Database) -> Result<PgQueryResult, Error> {
-        database
-            .query(
-                format!(
-                    "CREATE TABLE IF NOT EXISTS {} (
-            id SERIAL PRIMARY KEY,
-            start_date DATE NOT NULL,
-            end_date DATE NOT NULL
-        )",
-                    Self::TABLE_NAME
-                )
-                .as_str(),
-            )
-            .await
-    }
 }
@@ -0,0 +1,64 @@
+use std::f64::consts::PI;
+
+use chrono::NaiveTime;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::repository::planner::{MAX_WALK_DISTANCE, WALK_SPEED};
+
+// corners of the circles drawn around the stops
+const CIRCLE_SEGMENTS: usize = 16;
+const METERS_PER_DEGREE: f64 = 111_320.0;
+
+// stop reached from the origin of an isochrone
+#[derive(Serialize, Debug)]
+pub struct ReachableStop {
+    pub stop_id: i32,
+    pub name: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub arrival_time: NaiveTime,
+    // seconds from the departure
+    pub duration: i32,
+}
+
+// closed ring around a point, radius in meters, as [longitude, latitude] pairs
+fn circle(latitude: f64, longitude: f64, radius: f64) -> Vec<[f64; 2]> {
+    let mut ring: Vec<[f64; 2]> = (0..CIRCLE_SEGMENTS)
+        .map(|k| {
+            let angle: f64 = 2.0 * PI * k as f64 / CIRCLE_SEGMENTS as f64;
+            [
+                longitude + radius * angle.sin() / (METERS_PER_DEGREE * latitude.to_radians().cos()),
+                latitude + radius * angle.cos() / METERS_PER_DEGREE,
+            ]
+        })
+        .collect();
+    ring.push(ring[0]);
+
+    return ring;
+}
+
+// feature collection with one area per band, nested, made of the circles the time left after reaching
+// a stop allows to walk around it, kept apart in a geometry collection since they overlap
+pub fn to_geojson(stops: &Vec<ReachableStop>, duration: i32, bands: i32) -> Value {
+    let features: Vec<Value> = (1..=bands)
+        .map(|band| {
+            let limit: i32 = duration * band / bands;
+            let polygons: Vec<Value> = stops
+                .iter()
+                .filter(|stop| stop.duration <= limit)
+                .map(|stop| (stop, ((limit - stop.duration) as f64 * WALK_SPEED).min(MAX_WALK_DISTANCE)))
+                .filter(|(_, radius)| *radius >= 1.0)
+                .map(|(stop, radius)| json!({ "type": "Polygon", "coordinates": [circle(stop.latitude, stop.longitude, radius)] }))
+                .collect();
+
+            json!({
+                "type": "Feature",
+                "properties": { "minutes": limit / 60 },
+                "geometry": { "type": "GeometryCollection", "geometries": polygons },
+            })
+        })
+        .collect();
+
+    return json!({ "type": "FeatureCollection", "features": features });
+}
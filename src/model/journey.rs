@@ -0,0 +1,36 @@
+use chrono::NaiveTime;
+use serde::Serialize;
+
+// part of a journey, on board of a trip or walking between two stops
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum JourneyLeg {
+    Ride {
+        trip_id: i64,
+        line_id: i32,
+        origin_id: i32,
+        destination_id: i32,
+        departure_time: NaiveTime,
+        arrival_time: NaiveTime,
+        // stops passed on board, the destination included
+        stop_count: i32,
+    },
+    Walk {
+        origin_id: i32,
+        destination_id: i32,
+        departure_time: NaiveTime,
+        arrival_time: NaiveTime,
+        // meters, as the crow flies
+        distance: i32,
+    },
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Journey {
+    pub departure_time: NaiveTime,
+    pub arrival_time: NaiveTime,
+    // seconds
+    pub duration: i32,
+    pub transfers: i32,
+    pub legs: Vec<JourneyLeg>,
+}
@@ -1,66 +1,54 @@
-use async_trait::async_trait;
-use serde::Serialize;
-use sqlx::{postgres::PgQueryResult, Error, FromRow};
+use serde::{Serialize, Serializer};
+use sqlx::FromRow;
 
-use crate::repository::database::{Database, Table};
+use crate::repository::{
+    database::{Table, Value},
+    routing::{decode_polyline, Location},
+};
 
-#[derive(Serialize, FromRow, Debug)]
+// the geometry is stored encoded and served as [latitude, longitude] pairs
+fn serialize_points<S: Serializer>(polyline: &str, serializer: S) -> Result<S::Ok, S::Error> {
+    let points: Vec<[f64; 2]> = decode_polyline(polyline)
+        .iter()
+        .map(|location: &Location| [location.latitude, location.longitude])
+        .collect();
+    points.serialize(serializer)
+}
+
+#[derive(Serialize, FromRow, Debug, Clone)]
 pub struct LegStep {
-    pub id: i32,
-    pub leg_id: i32,
+    pub id: i64,
+    pub leg_id: i64,
     pub distance: i32,
     pub duration: i32,
     pub sequence: i16,
     pub start_lat: f64,
     pub start_lng: f64,
     pub end_lat: f64,
-    pub end_lng: f64
+    pub end_lng: f64,
+    #[serde(rename = "points", serialize_with = "serialize_points")]
+    pub polyline: String
 }
 
-#[async_trait]
 impl Table for LegStep {
     const TABLE_NAME: &'static str = "leg_steps";
 
-    fn values(&self) -> Vec<Box<dyn std::any::Any>> {
+    fn values(&self) -> Vec<Value> {
         vec![
-            Box::new(self.id),
-            Box::new(self.leg_id),
-            Box::new(self.distance),
-            Box::new(self.duration),
-            Box::new(self.sequence),
-            Box::new(self.start_lat),
-            Box::new(self.start_lng),
-            Box::new(self.end_lat),
-            Box::new(self.end_lng)
+            self.id.into(),
+            self.leg_id.into(),
+            self.distance.into(),
+            self.duration.into(),
+            self.sequence.into(),
+            self.start_lat.into(),
+            self.start_lng.into(),
+            self.end_lat.into(),
+            self.end_lng.into(),
+            self.polyline.clone().into()
         ]
     }
 
     fn keys() -> String {
-        return "(id,leg_id,distance,duration,sequence,start_lat,start_lng,end_lat,end_lng)".to_string();
-    }
-
-    async fn create_table(database: &Database) -> Result<PgQueryResult, Error> {
-        database
-            .query(
-                format!(
-                    "CREATE TABLE IF NOT EXISTS {} (
-            id INTEGER PRIMARY KEY,
-            leg_id INTEGER NOT NULL,
-            distance INTEGER NOT NULL,
-            duration INTEGER NOT NULL,
-            sequence SMALLINT NOT NULL,
-            start_lat DOUBLE PRECISION NOT NULL,
-            start_lng DOUBLE PRECISION NOT NULL,
-            end_lat DOUBLE PRECISION NOT NULL,
-            end_lng DOUBLE PRECISION NOT NULL,
-            CONSTRAINT fk_leg
-                FOREIGN KEY(leg_id)
-                    REFERENCES direction_legs(id)
-        )",
-                    Self::TABLE_NAME
-                )
-                .as_str(),
-            )
-            .await
+        return "(id,leg_id,distance,duration,sequence,start_lat,start_lng,end_lat,end_lng,polyline)".to_string();
     }
 }
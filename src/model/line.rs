@@ -1,10 +1,9 @@
-use async_trait::async_trait;
 use std::str::FromStr;
 
 use serde::{Deserialize, Deserializer, Serialize};
-use sqlx::{postgres::PgQueryResult, Error, FromRow};
+use sqlx::FromRow;
 
-use crate::repository::database::{Database, Table};
+use crate::repository::database::{Table, Value};
 
 use super::types::ColorType;
 
@@ -351,7 +350,7 @@ impl<'de> Deserialize<'de> for LineDescription {
     }
 }
 
-#[derive(Serialize, FromRow, Debug)]
+#[derive(Serialize, FromRow, Debug, Clone)]
 pub struct Line {
     pub id: i32,
     pub name: String,
@@ -361,41 +360,24 @@ pub struct Line {
 }
 // TODO: Add transport mode (tramway, trolleybus...) ?
 
-#[async_trait]
 impl Table for Line {
     const TABLE_NAME: &'static str = "lines";
 
-    fn values(&self) -> Vec<Box<dyn std::any::Any>> {
+    fn values(&self) -> Vec<Value> {
         vec![
-            Box::new(self.id),
-            Box::new(self.name.to_string()),
-            Box::new(if self.color_type == ColorType::Unknown {
+            self.id.into(),
+            self.name.to_string().into(),
+            if self.color_type == ColorType::Unknown {
                 String::new()
             } else {
                 format!("{:?}", self.color_type)
-            }),
-            Box::new(self.color.to_string()),
+            }
+            .into(),
+            self.color.to_string().into(),
         ]
     }
 
     fn keys() -> String {
         return "(id,name,color_type,color)".to_string();
     }
-
-    async fn create_table(database: &Database) -> Result<PgQueryResult, Error> {
-        database
-            .query(
-                format!(
-                    "CREATE TABLE IF NOT EXISTS {} (
-            id INTEGER PRIMARY KEY,
-            name VARCHAR(10) NOT NULL,
-            color_type VARCHAR(5),
-            color VARCHAR(11)
-        )",
-                    Self::TABLE_NAME
-                )
-                .as_str(),
-            )
-            .await
-    }
 }
@@ -2,6 +2,7 @@ pub mod line;
 pub mod stop;
 pub mod types;
 pub mod bitfield;
+pub mod calendar;
 pub mod trip;
 pub mod trip_stop;
 pub mod information;
@@ -10,4 +11,10 @@ pub mod shape_stop;
 pub mod shape;
 pub mod direction;
 pub mod direction_leg;
-pub mod leg_step;
\ No newline at end of file
+pub mod leg_step;
+pub mod dataset;
+pub mod vehicle;
+pub mod departure;
+pub mod arrival;
+pub mod journey;
+pub mod isochrone;
\ No newline at end of file
@@ -1,42 +1,25 @@
-use async_trait::async_trait;
 use serde::Serialize;
-use sqlx::{postgres::PgQueryResult, Error, FromRow};
+use sqlx::FromRow;
 
-use crate::repository::database::{Database, Table};
+use crate::repository::database::{Table, Value};
 
-#[derive(Serialize, FromRow, Debug)]
+#[derive(Serialize, FromRow, Debug, Clone)]
 pub struct Shape {
-    pub id: i32,
+    pub id: i64,
     pub identifier: String
 }
 
-#[async_trait]
 impl Table for Shape {
     const TABLE_NAME: &'static str = "shapes";
 
-    fn values(&self) -> Vec<Box<dyn std::any::Any>> {
+    fn values(&self) -> Vec<Value> {
         vec![
-            Box::new(self.id),
-            Box::new(self.identifier.to_string()),
+            self.id.into(),
+            self.identifier.to_string().into(),
         ]
     }
 
     fn keys() -> String {
         return "(id,identifier)".to_string();
     }
-
-    async fn create_table(database: &Database) -> Result<PgQueryResult, Error> {
-        database
-            .query(
-                format!(
-                    "CREATE TABLE IF NOT EXISTS {} (
-            id INTEGER PRIMARY KEY,
-            identifier VARCHAR(1024) NOT NULL
-        )",
-                    Self::TABLE_NAME
-                )
-                .as_str(),
-            )
-            .await
-    }
 }
@@ -1,61 +1,33 @@
-use async_trait::async_trait;
 use serde::Serialize;
-use sqlx::{postgres::PgQueryResult, Error, FromRow};
+use sqlx::FromRow;
 
-use crate::repository::database::{Database, Table};
+use crate::repository::database::{Table, Value};
 
-#[derive(Serialize, FromRow, Debug)]
+#[derive(Serialize, FromRow, Debug, Clone)]
 pub struct ShapePoint {
-    pub id: i32,
-    pub shape_id: i32,
+    pub id: i64,
+    pub shape_id: i64,
     pub sequence: i16,
     pub latitude: f64,
     pub longitude: f64,
-    pub shape_stop_id: Option<i32>,
+    pub shape_stop_id: Option<i64>,
 }
 
-#[async_trait]
 impl Table for ShapePoint {
     const TABLE_NAME: &'static str = "shape_points";
 
-    fn values(&self) -> Vec<Box<dyn std::any::Any>> {
+    fn values(&self) -> Vec<Value> {
         vec![
-            Box::new(self.id),
-            Box::new(self.shape_id),
-            Box::new(self.sequence),
-            Box::new(self.latitude),
-            Box::new(self.longitude),
-            Box::new(if self.shape_stop_id.is_some() {
-                self.shape_stop_id.unwrap()
-            } else {
-                0
-            }),
+            self.id.into(),
+            self.shape_id.into(),
+            self.sequence.into(),
+            self.latitude.into(),
+            self.longitude.into(),
+            self.shape_stop_id.into(),
         ]
     }
 
     fn keys() -> String {
         return "(id,shape_id,sequence,latitude,longitude,shape_stop_id)".to_string();
     }
-
-    async fn create_table(database: &Database) -> Result<PgQueryResult, Error> {
-        database
-            .query(
-                format!(
-                    "CREATE TABLE IF NOT EXISTS {} (
-            id INTEGER PRIMARY KEY,
-            shape_id INTEGER NOT NULL,
-            sequence SMALLINT NOT NULL,
-            latitude DOUBLE PRECISION NOT NULL,
-            longitude DOUBLE PRECISION NOT NULL,
-            shape_stop_id INTEGER,
-            CONSTRAINT fk_shape
-                FOREIGN KEY(shape_id)
-                    REFERENCES shapes(id)
-        )",
-                    Self::TABLE_NAME
-                )
-                .as_str(),
-            )
-            .await
-    }
 }
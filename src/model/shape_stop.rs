@@ -1,54 +1,29 @@
-use async_trait::async_trait;
 use serde::Serialize;
-use sqlx::{postgres::PgQueryResult, Error, FromRow};
+use sqlx::FromRow;
 
-use crate::repository::database::{Database, Table};
+use crate::repository::database::{Table, Value};
 
-#[derive(Serialize, FromRow, Debug)]
+#[derive(Serialize, FromRow, Debug, Clone)]
 pub struct ShapeStop {
-    pub id: i32,
-    pub shape_id: i32,
+    pub id: i64,
+    pub shape_id: i64,
     pub stop_id: i32,
     pub sequence: i16,
 }
 
-#[async_trait]
 impl Table for ShapeStop {
     const TABLE_NAME: &'static str = "shape_stops";
 
-    fn values(&self) -> Vec<Box<dyn std::any::Any>> {
+    fn values(&self) -> Vec<Value> {
         vec![
-            Box::new(self.id),
-            Box::new(self.shape_id),
-            Box::new(self.stop_id),
-            Box::new(self.sequence),
+            self.id.into(),
+            self.shape_id.into(),
+            self.stop_id.into(),
+            self.sequence.into(),
         ]
     }
 
     fn keys() -> String {
         return "(id,shape_id,stop_id,sequence)".to_string();
     }
-
-    async fn create_table(database: &Database) -> Result<PgQueryResult, Error> {
-        database
-            .query(
-                format!(
-                    "CREATE TABLE IF NOT EXISTS {} (
-            id INTEGER PRIMARY KEY,
-            shape_id INTEGER NOT NULL,
-            stop_id INTEGER NOT NULL,
-            sequence SMALLINT NOT NULL,
-            CONSTRAINT fk_stop
-                FOREIGN KEY(stop_id)
-                    REFERENCES stops(id),
-            CONSTRAINT fk_shape
-                FOREIGN KEY(shape_id)
-                    REFERENCES shapes(id)
-        )",
-                    Self::TABLE_NAME
-                )
-                .as_str(),
-            )
-            .await
-    }
 }
@@ -1,10 +1,9 @@
-use async_trait::async_trait;
 use serde::Serialize;
-use sqlx::{FromRow, postgres::PgQueryResult, Error};
+use sqlx::FromRow;
 
-use crate::repository::database::{Database, Table};
+use crate::repository::database::{Table, Value};
 
-#[derive(Serialize, FromRow, Debug)]
+#[derive(Serialize, FromRow, Debug, Clone)]
 pub struct Stop {
     pub id: i32,
     pub latitude: f64,
@@ -12,29 +11,19 @@ pub struct Stop {
     pub name: String,
 }
 
-#[async_trait]
 impl Table for  Stop {
     const TABLE_NAME: &'static str = "stops";
 
-    fn values(&self) -> Vec<Box<dyn std::any::Any>> {
+    fn values(&self) -> Vec<Value> {
         vec![
-            Box::new(self.id),
-            Box::new(self.latitude),
-            Box::new(self.longitude),
-            Box::new(self.name.to_string()),
+            self.id.into(),
+            self.latitude.into(),
+            self.longitude.into(),
+            self.name.to_string().into(),
         ]
     }
 
     fn keys() -> String {
         return "(id,latitude,longitude,name)".to_string();
     }
-
-    async fn create_table(database: &Database) -> Result<PgQueryResult, Error> {
-        database.query(format!("CREATE TABLE IF NOT EXISTS {} (
-            id INTEGER PRIMARY KEY,
-            latitude DOUBLE PRECISION NOT NULL,
-            longitude DOUBLE PRECISION NOT NULL,
-            name VARCHAR(60) NOT NULL
-        )", Self::TABLE_NAME).as_str()).await
-    }
 }
\ No newline at end of file
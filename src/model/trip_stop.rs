@@ -1,68 +1,36 @@
-use std::str::FromStr;
-
-use async_trait::async_trait;
 use chrono::NaiveTime;
 use serde::Serialize;
-use sqlx::{postgres::PgQueryResult, Error, FromRow};
+use sqlx::FromRow;
 
-use crate::repository::database::{Database, Table};
+use crate::repository::database::{Table, Value};
 
 #[derive(Serialize, FromRow, Debug, Clone)]
 pub struct TripStop {
-    pub id: i32,
+    pub id: i64,
     pub stop_id: i32,
-    pub trip_id: i32,
+    pub trip_id: i64,
     pub sequence: i16,
     pub arrival_time: Option<NaiveTime>,
     pub departure_time: Option<NaiveTime>,
+    pub platform: Option<String>,
 }
 
-#[async_trait]
 impl Table for TripStop {
     const TABLE_NAME: &'static str = "trip_stops";
 
-    fn values(&self) -> Vec<Box<dyn std::any::Any>> {
+    fn values(&self) -> Vec<Value> {
         vec![
-            Box::new(self.id),
-            Box::new(self.stop_id),
-            Box::new(self.trip_id),
-            Box::new(self.sequence),
-            Box::new(if self.arrival_time.is_some() {
-                self.arrival_time.unwrap()
-            } else {
-                NaiveTime::from_str("00:00:00").unwrap()
-            }),
-            Box::new(if self.departure_time.is_some() {
-                self.departure_time.unwrap()
-            } else {
-                NaiveTime::from_str("00:00:00").unwrap()
-            }),
+            self.id.into(),
+            self.stop_id.into(),
+            self.trip_id.into(),
+            self.sequence.into(),
+            self.arrival_time.into(),
+            self.departure_time.into(),
+            self.platform.clone().into(),
         ]
     }
 
     fn keys() -> String {
-        return "(id,stop_id,trip_id,sequence,arrival_time,departure_time)".to_string();
-    }
-
-    async fn create_table(database: &Database) -> Result<PgQueryResult, Error> {
-        database
-            .query(
-                format!(
-                    "CREATE TABLE IF NOT EXISTS {} (
-            id INTEGER PRIMARY KEY,
-            stop_id INTEGER NOT NULL,
-            trip_id INTEGER NOT NULL,
-            sequence SMALLINT NOT NULL,
-            arrival_time TIME,
-            departure_time TIME,
-            CONSTRAINT fk_trip
-                FOREIGN KEY(trip_id)
-                    REFERENCES trips(id)
-        )",
-                    Self::TABLE_NAME
-                )
-                .as_str(),
-            )
-            .await
+        return "(id,stop_id,trip_id,sequence,arrival_time,departure_time,platform)".to_string();
     }
 }
@@ -0,0 +1,290 @@
+use std::collections::HashMap;
+
+use chrono::{NaiveTime, Timelike};
+use serde::Serialize;
+
+use crate::repository::{
+    calibration::DAY,
+    routing::{bearing, haversine, Location},
+};
+
+use super::{trip::Trip, trip_stop::TripStop};
+
+// estimated position of the vehicle running a trip
+#[derive(Serialize, Debug)]
+pub struct Vehicle {
+    pub trip_id: i64,
+    pub line_id: i32,
+    pub latitude: f64,
+    pub longitude: f64,
+    // degrees clockwise from north
+    pub bearing: f64,
+    // stop the vehicle is at or heading to
+    pub next_stop_id: i32,
+}
+
+// point at a fraction of the length of a path, with the bearing of the path there, none on an empty path
+fn along(path: &[Location], fraction: f64) -> Option<(Location, f64)> {
+    let last: Location = *path.last()?;
+    let lengths: Vec<f64> = path.windows(2).map(|pair| haversine(&pair[0], &pair[1])).collect();
+    let mut remaining: f64 = lengths.iter().sum::<f64>() * fraction.clamp(0.0, 1.0);
+
+    for (pair, length) in path.windows(2).zip(&lengths) {
+        if remaining <= *length && *length > 0.0 {
+            let ratio: f64 = remaining / length;
+            let location: Location = Location {
+                latitude: pair[0].latitude + (pair[1].latitude - pair[0].latitude) * ratio,
+                longitude: pair[0].longitude + (pair[1].longitude - pair[0].longitude) * ratio,
+            };
+            return Some((location, bearing(&pair[0], &pair[1])));
+        }
+        remaining -= length;
+    }
+
+    // paths of a single point
+    let heading: f64 = match path.len() {
+        1 => 0.0,
+        length => bearing(&path[length - 2], &last),
+    };
+    return Some((last, heading));
+}
+
+impl Vehicle {
+    // paths are keyed by the stops they join, stops missing from them are joined in straight lines
+    pub fn locate(
+        trip: &Trip,
+        trip_stops: &Vec<TripStop>,
+        time: NaiveTime,
+        paths: &HashMap<(i32, i32), Vec<Location>>,
+        locations: &HashMap<i32, Location>,
+    ) -> Option<Vehicle> {
+        let mut trip_stops: Vec<&TripStop> = trip_stops.iter().collect();
+        trip_stops.sort_by_key(|trip_stop| trip_stop.sequence);
+
+        // arrival and departure of every stop in seconds, increasing across midnight
+        let mut times: Vec<(i32, i32)> = Vec::new();
+        let mut offset: i32 = 0;
+        let mut previous: i32 = 0;
+        for trip_stop in &trip_stops {
+            let arrival: NaiveTime = trip_stop.arrival_time.or(trip_stop.departure_time)?;
+            let departure: NaiveTime = trip_stop.departure_time.unwrap_or(arrival);
+            let mut seconds = |time: NaiveTime| {
+                let seconds: i32 = time.num_seconds_from_midnight() as i32 + offset;
+                if seconds < previous {
+                    offset += DAY;
+                    previous = seconds + DAY;
+                } else {
+                    previous = seconds;
+                }
+                previous
+            };
+            times.push((seconds(arrival), seconds(departure)));
+        }
+
+        let mut now: i32 = time.num_seconds_from_midnight() as i32;
+        if now < times.first()?.1 {
+            now += DAY;
+        }
+
+        let path = |k: usize| -> Vec<Location> {
+            let (origin, destination) = (trip_stops[k].stop_id, trip_stops[k + 1].stop_id);
+            match paths.get(&(origin, destination)) {
+                Some(path) if !path.is_empty() => path.clone(),
+                _ => vec![locations.get(&origin).copied(), locations.get(&destination).copied()]
+                    .into_iter()
+                    .flatten()
+                    .collect(),
+            }
+        };
+
+        for (k, (arrival, departure)) in times.iter().enumerate() {
+            if now < *arrival && k > 0 {
+                let previous_departure: i32 = times[k - 1].1;
+                let fraction: f64 = (now - previous_departure) as f64 / (*arrival - previous_departure).max(1) as f64;
+                let (location, heading) = along(&path(k - 1), fraction)?;
+
+                return Some(Vehicle {
+                    trip_id: trip.id,
+                    line_id: trip.line_id,
+                    latitude: location.latitude,
+                    longitude: location.longitude,
+                    bearing: heading,
+                    next_stop_id: trip_stops[k].stop_id,
+                });
+            }
+
+            if now <= *departure && now >= *arrival {
+                let location: Location = *locations.get(&trip_stops[k].stop_id)?;
+                let heading: f64 = if k + 1 < trip_stops.len() {
+                    along(&path(k), 0.0)?.1
+                } else if k > 0 {
+                    along(&path(k - 1), 1.0)?.1
+                } else {
+                    0.0
+                };
+
+                return Some(Vehicle {
+                    trip_id: trip.id,
+                    line_id: trip.line_id,
+                    latitude: location.latitude,
+                    longitude: location.longitude,
+                    bearing: heading,
+                    next_stop_id: trip_stops[k].stop_id,
+                });
+            }
+        }
+
+        // before the first departure or after the last arrival
+        return None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::{line::TransportMode, types::Direction};
+
+    use super::*;
+
+    const A: i32 = 1;
+    const B: i32 = 2;
+    const C: i32 = 3;
+
+    fn location(latitude: f64, longitude: f64) -> Location {
+        Location { latitude, longitude }
+    }
+
+    fn time(hour: u32, minute: u32, second: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, second).unwrap()
+    }
+
+    fn trip() -> Trip {
+        Trip {
+            id: 10,
+            journey_number: 1,
+            option_count: 0,
+            shape_id: None,
+            direction_id: None,
+            transport_mode: TransportMode::Bus,
+            origin_id: A,
+            destination_id: C,
+            information_id: 1,
+            bitfield_id: 1,
+            line_id: 5,
+            direction: Direction::Outward,
+            departure_time: time(8, 0, 0),
+            arrival_time: time(8, 20, 0),
+        }
+    }
+
+    // stops given as (stop, arrival, departure)
+    fn trip_stops(stops: &[(i32, Option<NaiveTime>, Option<NaiveTime>)]) -> Vec<TripStop> {
+        return stops
+            .iter()
+            .enumerate()
+            .map(|(k, (stop_id, arrival_time, departure_time))| TripStop {
+                id: k as i64,
+                stop_id: *stop_id,
+                trip_id: 10,
+                sequence: k as i16 + 1,
+                arrival_time: *arrival_time,
+                departure_time: *departure_time,
+                platform: None,
+            })
+            .collect();
+    }
+
+    // a, b and c a little more than a kilometer apart northwards
+    fn locations() -> HashMap<i32, Location> {
+        return HashMap::from([(A, location(46.0, 6.0)), (B, location(46.01, 6.0)), (C, location(46.02, 6.0))]);
+    }
+
+    fn morning_stops() -> Vec<TripStop> {
+        return trip_stops(&[
+            (A, None, Some(time(8, 0, 0))),
+            (B, Some(time(8, 10, 0)), Some(time(8, 11, 0))),
+            (C, Some(time(8, 20, 0)), None),
+        ]);
+    }
+
+    #[test]
+    fn along_is_none_on_an_empty_path() {
+        assert!(along(&[], 0.5).is_none());
+    }
+
+    #[test]
+    fn along_a_single_point_stays_there() {
+        let (point, heading) = along(&[location(46.0, 6.0)], 0.5).unwrap();
+
+        assert_eq!((point.latitude, point.longitude, heading), (46.0, 6.0, 0.0));
+    }
+
+    #[test]
+    fn along_splits_the_path_by_length() {
+        let path: Vec<Location> = vec![location(46.0, 6.0), location(46.01, 6.0), location(46.02, 6.0)];
+
+        let (point, heading) = along(&path, 0.75).unwrap();
+        assert!((point.latitude - 46.015).abs() < 1e-6);
+        assert!(heading.abs() < 1e-6);
+
+        let (point, _) = along(&path, 2.0).unwrap();
+        assert_eq!(point.latitude, 46.02);
+    }
+
+    #[test]
+    fn vehicles_move_between_the_stops() {
+        let vehicle: Vehicle =
+            Vehicle::locate(&trip(), &morning_stops(), time(8, 5, 0), &HashMap::new(), &locations()).unwrap();
+
+        assert!((vehicle.latitude - 46.005).abs() < 1e-6);
+        assert!(vehicle.bearing.abs() < 1e-6);
+        assert_eq!((vehicle.trip_id, vehicle.line_id, vehicle.next_stop_id), (10, 5, B));
+    }
+
+    #[test]
+    fn vehicles_wait_at_the_stops_facing_the_next_one() {
+        let vehicle: Vehicle =
+            Vehicle::locate(&trip(), &morning_stops(), time(8, 10, 30), &HashMap::new(), &locations()).unwrap();
+
+        assert_eq!((vehicle.latitude, vehicle.longitude), (46.01, 6.0));
+        assert!(vehicle.bearing.abs() < 1e-6);
+        assert_eq!(vehicle.next_stop_id, B);
+    }
+
+    #[test]
+    fn vehicles_follow_the_paths_over_straight_lines() {
+        let paths: HashMap<(i32, i32), Vec<Location>> =
+            HashMap::from([((A, B), vec![location(46.0, 6.0), location(46.0, 6.02)])]);
+        let vehicle: Vehicle = Vehicle::locate(&trip(), &morning_stops(), time(8, 5, 0), &paths, &locations()).unwrap();
+
+        assert!((vehicle.longitude - 6.01).abs() < 1e-6);
+        assert!((vehicle.bearing - 90.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn no_vehicle_outside_the_trip() {
+        assert!(Vehicle::locate(&trip(), &morning_stops(), time(7, 59, 0), &HashMap::new(), &locations()).is_none());
+        assert!(Vehicle::locate(&trip(), &morning_stops(), time(8, 21, 0), &HashMap::new(), &locations()).is_none());
+    }
+
+    #[test]
+    fn no_vehicle_without_any_location() {
+        let vehicle: Option<Vehicle> =
+            Vehicle::locate(&trip(), &morning_stops(), time(8, 5, 0), &HashMap::new(), &HashMap::new());
+
+        assert!(vehicle.is_none());
+    }
+
+    #[test]
+    fn trips_keep_running_past_midnight() {
+        let trip_stops: Vec<TripStop> = trip_stops(&[
+            (A, None, Some(time(23, 55, 0))),
+            (B, Some(time(0, 5, 0)), Some(time(0, 5, 0))),
+        ]);
+
+        for now in [time(23, 57, 30), time(0, 2, 30)] {
+            let vehicle: Vehicle = Vehicle::locate(&trip(), &trip_stops, now, &HashMap::new(), &locations()).unwrap();
+            assert_eq!(vehicle.next_stop_id, B);
+            assert!(vehicle.latitude > 46.0 && vehicle.latitude < 46.01);
+        }
+    }
+}
@@ -0,0 +1,232 @@
+use chrono::NaiveTime;
+
+use crate::model::direction_leg::{time_band, DirectionLeg, MIN_DWELL, TIME_BANDS};
+
+// bounds of the fitted running times relative to the routed durations,
+// beyond them the timetable is more likely wrong than the routing
+const MIN_SCALE: f64 = 0.5;
+const MAX_SCALE: f64 = 4.0;
+
+// scheduled passage of a trip at one of its stops, in seconds since the start of its operating day
+#[derive(Clone, Copy, Debug)]
+pub struct ScheduledStop {
+    pub arrival: Option<i32>,
+    pub departure: Option<i32>,
+}
+
+// seconds in a day, scheduled times after midnight keep counting from the operating day
+pub const DAY: i32 = 86_400;
+
+pub fn seconds_to_time(seconds: i32) -> NaiveTime {
+    return NaiveTime::from_num_seconds_from_midnight_opt(seconds.rem_euclid(DAY) as u32, 0).unwrap();
+}
+
+// band of the departure from the first stop
+pub fn trip_band(trip: &[ScheduledStop]) -> Option<usize> {
+    let first: &ScheduledStop = trip.first()?;
+    let departure: i32 = first.departure.or(first.arrival)?;
+
+    return Some(time_band(seconds_to_time(departure)));
+}
+
+fn mean(samples: &[i32]) -> Option<f64> {
+    if samples.is_empty() {
+        return None;
+    }
+
+    return Some(samples.iter().map(|sample| *sample as f64).sum::<f64>() / samples.len() as f64);
+}
+
+// dwell times come from the scheduled stops. running times follow the scheduled times between the stops
+// where the timetable moves on, and the routed durations share them among the stops scheduled on the
+// same minute, so that vehicles keep moving between minute rounded stops
+fn fit(legs: &[&mut DirectionLeg], trips: &[&Vec<ScheduledStop>]) -> Option<(Vec<i32>, Vec<i32>)> {
+    let count: usize = legs.len();
+
+    // no dwell at the terminus
+    let dwell_times: Vec<i32> = (0..count)
+        .map(|k| {
+            if k + 1 == count {
+                return 0;
+            }
+            let samples: Vec<i32> = trips
+                .iter()
+                .filter_map(|trip| Some(trip[k + 1].departure? - trip[k + 1].arrival?))
+                .collect();
+            mean(&samples).map_or(MIN_DWELL, |dwell| (dwell.round() as i32).max(MIN_DWELL))
+        })
+        .collect();
+
+    // running time from the first departure to every stop, dwells left out
+    let mut scheduled: Vec<f64> = vec![0.0; count + 1];
+    for k in 1..=count {
+        let samples: Vec<i32> = trips
+            .iter()
+            .filter_map(|trip| Some(trip[k].arrival.or(trip[k].departure)? - trip[0].departure?))
+            .collect();
+        scheduled[k] = mean(&samples)? - dwell_times[..k - 1].iter().sum::<i32>() as f64;
+    }
+
+    let mut routed: Vec<f64> = vec![0.0; count + 1];
+    for k in 1..=count {
+        routed[k] = routed[k - 1] + legs[k - 1].duration as f64;
+    }
+
+    let mut running_times: Vec<i32> = vec![0; count];
+    let mut anchor: usize = 0;
+    for k in 1..=count {
+        if k < count && scheduled[k] <= scheduled[anchor] {
+            continue;
+        }
+
+        let span_scheduled: f64 = (scheduled[k] - scheduled[anchor]).max(0.0);
+        let span_routed: f64 = routed[k] - routed[anchor];
+        for j in anchor..k {
+            running_times[j] = if span_routed > 0.0 {
+                let scale: f64 = (span_scheduled / span_routed).clamp(MIN_SCALE, MAX_SCALE);
+                (legs[j].duration as f64 * scale).round() as i32
+            } else {
+                (span_scheduled / (k - anchor) as f64).round() as i32
+            };
+        }
+        anchor = k;
+    }
+
+    return Some((running_times, dwell_times));
+}
+
+// fit the legs of a direction to the scheduled times of its trips per time band,
+// bands without trips take the fit of the whole day
+pub fn calibrate(legs: &mut Vec<&mut DirectionLeg>, trips: &[Vec<ScheduledStop>]) {
+    legs.sort_by_key(|leg| leg.sequence);
+
+    let trips: Vec<&Vec<ScheduledStop>> = trips
+        .iter()
+        .filter(|trip| trip.len() == legs.len() + 1)
+        .collect();
+    let day: (Vec<i32>, Vec<i32>) = match fit(legs, &trips) {
+        Some(day) => day,
+        None => return,
+    };
+
+    let mut running_times: Vec<Vec<i32>> = vec![Vec::new(); legs.len()];
+    let mut dwell_times: Vec<Vec<i32>> = vec![Vec::new(); legs.len()];
+    for band in 0..TIME_BANDS.len() {
+        let band_trips: Vec<&Vec<ScheduledStop>> = trips
+            .iter()
+            .copied()
+            .filter(|trip| trip_band(trip) == Some(band))
+            .collect();
+        let (running, dwell) = fit(legs, &band_trips).unwrap_or(day.clone());
+
+        for k in 0..legs.len() {
+            running_times[k].push(running[k]);
+            dwell_times[k].push(dwell[k]);
+        }
+    }
+
+    for (k, leg) in legs.iter_mut().enumerate() {
+        leg.set_band_times(&running_times[k], &dwell_times[k]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::direction_leg::BandTimes;
+
+    use super::*;
+
+    const SEVEN: i32 = 7 * 3600;
+    const FIVE_PM: i32 = 17 * 3600;
+
+    // departure from the first stop, then arrival and departure at the next ones, in seconds after it
+    fn trip(departure: i32, stops: &[(i32, i32)]) -> Vec<ScheduledStop> {
+        let mut trip: Vec<ScheduledStop> = vec![ScheduledStop { arrival: None, departure: Some(departure) }];
+        for (arrival, leave) in stops {
+            trip.push(ScheduledStop { arrival: Some(departure + arrival), departure: Some(departure + leave) });
+        }
+        return trip;
+    }
+
+    fn calibrated(durations: &[i32], trips: &[Vec<ScheduledStop>]) -> Vec<DirectionLeg> {
+        let mut legs: Vec<DirectionLeg> = durations
+            .iter()
+            .enumerate()
+            .map(|(k, duration)| DirectionLeg {
+                id: k as i64,
+                direction_id: 1,
+                distance: 0,
+                duration: *duration,
+                sequence: k as i16 + 1,
+                origin_id: k as i32,
+                destination_id: k as i32 + 1,
+                running_times: None,
+                dwell_times: None,
+            })
+            .collect();
+        calibrate(&mut legs.iter_mut().collect(), trips);
+        return legs;
+    }
+
+    fn band_times(times: &Option<BandTimes>) -> Vec<i32> {
+        return times.as_ref().map(|times| times.0.clone()).unwrap_or_default();
+    }
+
+    #[test]
+    fn running_times_follow_the_timetable_and_dwells_the_stops() {
+        let legs: Vec<DirectionLeg> = calibrated(&[100, 200], &[trip(SEVEN, &[(120, 150), (450, 450)])]);
+
+        // bands without trips take the fit of the whole day
+        assert_eq!(band_times(&legs[0].running_times), vec![120; 5]);
+        assert_eq!(band_times(&legs[1].running_times), vec![300; 5]);
+        assert_eq!(band_times(&legs[0].dwell_times), vec![30; 5]);
+        assert_eq!(band_times(&legs[1].dwell_times), vec![0; 5]);
+    }
+
+    #[test]
+    fn bands_are_fitted_to_their_own_trips() {
+        let trips: Vec<Vec<ScheduledStop>> = vec![
+            trip(SEVEN, &[(120, 150), (450, 450)]),
+            trip(FIVE_PM, &[(180, 210), (630, 630)]),
+        ];
+        let legs: Vec<DirectionLeg> = calibrated(&[100, 200], &trips);
+
+        assert_eq!(band_times(&legs[0].running_times), vec![150, 120, 150, 180, 150]);
+        assert_eq!(band_times(&legs[1].running_times), vec![360, 300, 360, 420, 360]);
+        assert_eq!(legs[1].running_time(3), 420);
+    }
+
+    #[test]
+    fn stops_on_the_same_minute_share_the_routed_durations() {
+        let legs: Vec<DirectionLeg> = calibrated(&[60, 60, 60], &[trip(SEVEN, &[(0, 0), (120, 120), (240, 240)])]);
+
+        let running: Vec<i32> = legs.iter().map(|leg| leg.running_time(1)).collect();
+        assert_eq!(running, vec![53, 53, 105]);
+        assert_eq!(legs[0].dwell_time(1), MIN_DWELL);
+    }
+
+    #[test]
+    fn running_times_stay_within_the_scale_bounds() {
+        let legs: Vec<DirectionLeg> = calibrated(&[100], &[trip(SEVEN, &[(1000, 1000)])]);
+        assert_eq!(legs[0].running_time(1), (100.0 * MAX_SCALE) as i32);
+
+        let legs: Vec<DirectionLeg> = calibrated(&[100], &[trip(SEVEN, &[(10, 10)])]);
+        assert_eq!(legs[0].running_time(1), (100.0 * MIN_SCALE) as i32);
+    }
+
+    #[test]
+    fn legs_stay_uncalibrated_without_matching_trips() {
+        let legs: Vec<DirectionLeg> = calibrated(&[100, 200], &[trip(SEVEN, &[(120, 150)])]);
+
+        assert!(legs[0].running_times.is_none() && legs[0].dwell_times.is_none());
+        assert_eq!(legs[0].running_time(1), 100);
+        assert_eq!(legs[0].dwell_time(1), MIN_DWELL);
+    }
+
+    #[test]
+    fn trips_past_midnight_fall_in_the_night_band() {
+        assert_eq!(seconds_to_time(DAY + 3600), NaiveTime::from_hms_opt(1, 0, 0).unwrap());
+        assert_eq!(trip_band(&trip(DAY + 3600, &[])), Some(0));
+        assert_eq!(trip_band(&trip(SEVEN, &[])), Some(1));
+    }
+}
@@ -1,25 +1,107 @@
-use std::any::TypeId;
-use std::ops::Add;
+use std::cmp;
 
-use async_trait::async_trait;
 use chrono::{NaiveDate, NaiveTime};
+use derive_more::Display;
 use log::error;
 use sqlx::postgres::{PgArguments, PgConnectOptions, PgPool, PgPoolOptions, PgQueryResult, PgRow};
 use sqlx::query::QueryAs;
-use sqlx::{Error, Postgres};
+use sqlx::{Error, Postgres, QueryBuilder};
+
+const MAX_BIND_PARAMETERS: usize = 65535;
+
+// column value bound by insert_many, None is inserted as NULL
+#[derive(Debug, Clone)]
+pub enum Value {
+    Integer(Option<i32>),
+    BigInt(Option<i64>),
+    SmallInt(Option<i16>),
+    Double(Option<f64>),
+    Boolean(Option<bool>),
+    Text(Option<String>),
+    // '0'/'1' characters, stored as a bit string where the backend has one
+    Bits(Option<String>),
+    Date(Option<NaiveDate>),
+    Time(Option<NaiveTime>),
+    // stored as an integer array where the backend has one, as a json array otherwise
+    Integers(Option<Vec<i32>>),
+}
+
+macro_rules! impl_value_from {
+    ($($type:ty => $variant:ident),* $(,)?) => {
+        $(
+            impl From<$type> for Value {
+                fn from(value: $type) -> Self {
+                    Value::$variant(Some(value))
+                }
+            }
+
+            impl From<Option<$type>> for Value {
+                fn from(value: Option<$type>) -> Self {
+                    Value::$variant(value)
+                }
+            }
+        )*
+    };
+}
+
+impl_value_from! {
+    i32 => Integer,
+    i64 => BigInt,
+    i16 => SmallInt,
+    f64 => Double,
+    bool => Boolean,
+    String => Text,
+    NaiveDate => Date,
+    NaiveTime => Time,
+    Vec<i32> => Integers,
+}
+
+#[derive(Debug, Display)]
+pub enum RepositoryError {
+    NotFound,
+    Unavailable,
+    Failed,
+}
+
+impl From<Error> for RepositoryError {
+    fn from(error: Error) -> Self {
+        match error {
+            Error::RowNotFound => RepositoryError::NotFound,
+            Error::PoolTimedOut | Error::PoolClosed | Error::Io(_) | Error::Tls(_) => {
+                error!("Database unavailable: {:?}", error);
+                RepositoryError::Unavailable
+            }
+            // connection exceptions (08) and operator interventions (57P)
+            Error::Database(ref database_error)
+                if database_error
+                    .code()
+                    .map(|code| code.starts_with("08") || code.starts_with("57P"))
+                    .unwrap_or(false) =>
+            {
+                error!("Database unavailable: {:?}", error);
+                RepositoryError::Unavailable
+            }
+            _ => {
+                error!("Error: {:?}", error);
+                RepositoryError::Failed
+            }
+        }
+    }
+}
 
-#[async_trait]
 pub trait Table {
     const TABLE_NAME: &'static str;
+    const PRIMARY_KEY: &'static [&'static str] = &["id"];
 
-    async fn create_table(database: &Database) -> Result<PgQueryResult, Error>;
     fn keys() -> String;
-    fn values(&self) -> Vec<Box<dyn std::any::Any>>;
+    fn values(&self) -> Vec<Value>;
 }
 
 #[derive(Clone)]
 pub struct Database {
     pool: PgPool,
+    // spatial queries use the postgis geometry columns
+    postgis: bool,
 }
 
 impl Database {
@@ -29,126 +111,109 @@ impl Database {
     ) -> Result<Database, Error> {
         Ok(Database {
             pool: config.connect_with(connect_options).await?,
+            postgis: false,
         })
     }
 
+    pub fn with_postgis(mut self, postgis: bool) -> Self {
+        self.postgis = postgis;
+        self
+    }
+
+    pub fn pool(&self) -> &PgPool {
+        &self.pool
+    }
+
+    pub fn postgis(&self) -> bool {
+        self.postgis
+    }
+
     pub async fn query(&self, query: &str) -> Result<PgQueryResult, Error> {
         return sqlx::query(query).execute(&self.pool).await;
     }
 
-    pub async fn get_many<T>(&self, query: QueryAs<'_, Postgres, T, PgArguments>) -> Option<Vec<T>>
+    pub async fn get_many<T>(
+        &self,
+        query: QueryAs<'_, Postgres, T, PgArguments>,
+    ) -> Result<Vec<T>, RepositoryError>
     where
         T: for<'r> sqlx::FromRow<'r, PgRow> + Send + Unpin,
     {
         let res: Result<Vec<T>, Error> = query.fetch_all(&self.pool).await;
 
-        return match res {
-            Ok(output) => match output.len() {
-                0 => None,
-                _ => Some(output),
-            },
-            Err(error) => {
-                error!("Error: {:?}", error);
-                None
-            }
-        };
+        return res.map_err(RepositoryError::from);
     }
 
-    pub async fn get_one<T>(&self, query: QueryAs<'_, Postgres, T, PgArguments>) -> Option<T>
+    pub async fn get_one<T>(
+        &self,
+        query: QueryAs<'_, Postgres, T, PgArguments>,
+    ) -> Result<T, RepositoryError>
     where
         T: for<'r> sqlx::FromRow<'r, PgRow> + Send + Unpin,
     {
         let res: Result<T, Error> = query.fetch_one(&self.pool).await;
 
-        return match res {
-            Ok(output) => Some(output),
-            Err(error) => {
-                error!("Error: {:?}", error);
-                None
-            }
-        };
+        return res.map_err(RepositoryError::from);
     }
 
-    pub async fn get_all<T>(&self, table_name: &str) -> Option<Vec<T>>
+    pub async fn get_all<T>(&self, table_name: &str) -> Result<Vec<T>, RepositoryError>
     where
         T: for<'r> sqlx::FromRow<'r, PgRow> + Send + Unpin,
     {
         let query = format!("SELECT * FROM {}", table_name);
         let res: Result<Vec<T>, Error> = sqlx::query_as::<_, T>(&query).fetch_all(&self.pool).await;
 
-        return match res {
-            Ok(output) => match output.len() {
-                0 => None,
-                _ => Some(output),
-            },
-            Err(error) => {
-                error!("Error: {:?}", error);
-                None
-            }
-        };
+        return res.map_err(RepositoryError::from);
     }
 
     pub async fn insert_many<T>(&self, data: &Vec<T>) -> Result<PgQueryResult, Error>
     where
         T: serde::Serialize + Table,
     {
-        let parts: std::slice::Chunks<'_, T> = data.chunks(10);
-
-        for chunk in parts {
-            let mut i = 1;
-            let mut str: String = String::new();
-            let mut params: Vec<Box<dyn std::any::Any>> = Vec::new();
-            for d in chunk {
-                str = str.add("(");
-                for v in d.values() {
-                    str = str.add(&format!("${},", i));
-                    params.push(v);
-                    i += 1;
-                }
-                str.pop();
-                str = str.add("),");
-            }
-            str.pop();
-
-            let query: String = format!(
-                "INSERT INTO {} {} VALUES {} ON CONFLICT DO NOTHING",
-                T::TABLE_NAME,
-                T::keys(),
-                str
-            );
-
-            let mut final_query = sqlx::query(&query);
-
-            for value in params {
-                if TypeId::of::<i32>() == value.type_id() {
-                    let n: i32 = *value.downcast::<i32>().unwrap();
-                    final_query = final_query.bind(n);
-                } else if TypeId::of::<i16>() == value.type_id() {
-                    let n: i16 = *value.downcast::<i16>().unwrap();
-                    final_query = final_query.bind(n);
-                } else if TypeId::of::<f64>() == value.type_id() {
-                    let n: f64 = *value.downcast::<f64>().unwrap();
-                    final_query = final_query.bind(n);
-                } else if TypeId::of::<String>() == value.type_id() {
-                    let n: String = *value.downcast::<String>().unwrap();
-                    final_query = final_query.bind(n);
-                } else if TypeId::of::<NaiveDate>() == value.type_id() {
-                    let n: NaiveDate = *value.downcast::<NaiveDate>().unwrap();
-                    final_query = final_query.bind(n);
-                } else if TypeId::of::<NaiveTime>() == value.type_id() {
-                    let n: NaiveTime = *value.downcast::<NaiveTime>().unwrap();
-                    final_query = final_query.bind(n);
-                } else if TypeId::of::<Option<i32>>() == value.type_id() {
-                    let n: i32 = value.downcast::<Option<i32>>().unwrap().unwrap_or(0);
-                    final_query = final_query.bind(n);
-                } else {
-                    panic!("Unknown type");
-                }
-            }
+        let mut result: PgQueryResult = PgQueryResult::default();
 
-            final_query.execute(&self.pool).await?;
+        for mut builder in insert_queries(T::TABLE_NAME, data) {
+            builder.push(" ON CONFLICT DO NOTHING");
+            result.extend([builder.build().execute(&self.pool).await?]);
         }
 
-        Ok(PgQueryResult::default())
+        Ok(result)
+    }
+}
+
+// multi-row inserts of data into table_name, split to respect the bind parameter limit
+pub fn insert_queries<T: Table>(table_name: &str, data: &[T]) -> Vec<QueryBuilder<'static, Postgres>> {
+    if data.is_empty() {
+        return Vec::new();
     }
+
+    // postgres accepts at most 65535 bind parameters per statement
+    let columns: usize = data[0].values().len();
+    let rows_per_query: usize = cmp::max(1, MAX_BIND_PARAMETERS / columns);
+
+    data.chunks(rows_per_query)
+        .map(|chunk| {
+            let mut builder: QueryBuilder<Postgres> =
+                QueryBuilder::new(format!("INSERT INTO {} {} ", table_name, T::keys()));
+
+            builder.push_values(chunk, |mut row, d| {
+                for value in d.values() {
+                    match value {
+                        Value::Integer(v) => row.push_bind(v),
+                        Value::BigInt(v) => row.push_bind(v),
+                        Value::SmallInt(v) => row.push_bind(v),
+                        Value::Double(v) => row.push_bind(v),
+                        Value::Boolean(v) => row.push_bind(v),
+                        Value::Text(v) => row.push_bind(v),
+                        Value::Bits(v) => row.push_bind(v).push_unseparated("::BIT VARYING"),
+                        Value::Date(v) => row.push_bind(v),
+                        Value::Time(v) => row.push_bind(v),
+                        Value::Integers(v) => row.push_bind(v),
+                    };
+                }
+            });
+
+            builder
+        })
+        .collect()
 }
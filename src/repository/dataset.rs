@@ -0,0 +1,423 @@
+use derive_more::Display;
+use log::info;
+use sqlx::{
+    postgres::{PgConnectOptions, PgConnection, PgPoolOptions},
+    Postgres, Transaction,
+};
+
+use crate::model::{
+    bitfield::Bitfield,
+    dataset::{Dataset, DatasetStatus},
+    information::Information,
+    line::Line,
+    stop::Stop,
+    trip::Trip,
+    trip_stop::TripStop,
+};
+
+use super::{
+    database::{Database, Table},
+    migration::{MigrationError, Migrator, TimetableTable, TIMETABLE_TABLES},
+};
+
+// number of previously active datasets kept for rollback
+const KEEP_PREVIOUS_DATASETS: i64 = 1;
+
+#[derive(Debug, Display)]
+pub enum DatasetError {
+    #[display(fmt = "database error: {}", _0)]
+    Database(sqlx::Error),
+    #[display(fmt = "migration error: {}", _0)]
+    Migration(MigrationError),
+    #[display(fmt = "table {} is empty", _0)]
+    EmptyTable(&'static str),
+    #[display(fmt = "{} rows with {}", _1, _0)]
+    Inconsistent(&'static str, i64),
+    #[display(fmt = "no previous dataset to roll back to")]
+    NoPreviousDataset,
+    #[display(fmt = "{} cannot be imported without {}", _0, _1)]
+    PartialImport(&'static str, &'static str),
+}
+
+impl From<sqlx::Error> for DatasetError {
+    fn from(error: sqlx::Error) -> Self {
+        DatasetError::Database(error)
+    }
+}
+
+impl From<MigrationError> for DatasetError {
+    fn from(error: MigrationError) -> Self {
+        DatasetError::Migration(error)
+    }
+}
+
+// point the connection at the active dataset, the public schema stays visible behind it
+pub async fn use_active_dataset(connection: &mut PgConnection) -> Result<(), sqlx::Error> {
+    let result = sqlx::query(
+        format!(
+            "SELECT set_config('search_path', COALESCE((SELECT 'dataset_' || id FROM public.{} WHERE status=$1), 'public') || ',public', false)",
+            Dataset::TABLE_NAME
+        )
+        .as_str(),
+    )
+    .bind(format!("{:?}", DatasetStatus::Active))
+    .execute(connection)
+    .await;
+
+    match result {
+        Ok(_) => Ok(()),
+        // datasets table is not migrated yet, keep serving the public schema
+        Err(sqlx::Error::Database(error)) if error.code().as_deref() == Some("42P01") => Ok(()),
+        Err(error) => Err(error),
+    }
+}
+
+// false for the connections still reading a dataset switched away from, by any process, the pool
+// replaces them
+pub async fn reads_active_dataset(connection: &mut PgConnection) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query_scalar::<_, bool>(
+        format!(
+            "SELECT current_schema() = COALESCE((SELECT 'dataset_' || id FROM public.{} WHERE status=$1), 'public')",
+            Dataset::TABLE_NAME
+        )
+        .as_str(),
+    )
+    .bind(format!("{:?}", DatasetStatus::Active))
+    .fetch_one(connection)
+    .await;
+
+    match result {
+        Ok(active) => Ok(active),
+        // datasets table is not migrated yet, keep serving the public schema
+        Err(sqlx::Error::Database(error)) if error.code().as_deref() == Some("42P01") => Ok(true),
+        Err(error) => Err(error),
+    }
+}
+
+pub struct Datasets<'a> {
+    pub database: &'a Database,
+}
+
+impl Datasets<'_> {
+    // register a new dataset and create its schema, returns a database writing into it
+    pub async fn create(
+        &self,
+        connect_options: PgConnectOptions,
+    ) -> Result<(Dataset, Database), DatasetError> {
+        let dataset: Dataset = sqlx::query_as::<_, Dataset>(
+            format!(
+                "INSERT INTO public.{} (status) VALUES ($1) RETURNING *",
+                Dataset::TABLE_NAME
+            )
+            .as_str(),
+        )
+        .bind(format!("{:?}", DatasetStatus::Loading))
+        .fetch_one(self.database.pool())
+        .await?;
+
+        Migrator {
+            database: self.database,
+        }
+        .create_timetable_schema(&dataset.schema_name())
+        .await?;
+
+        let database: Database = Database::init(
+            PgPoolOptions::new(),
+            connect_options.options([("search_path", dataset.schema_name())]),
+        )
+        .await?;
+
+        info!("Created dataset {}", dataset.id);
+
+        Ok((dataset, database))
+    }
+
+    // schema the api reads from, public until a dataset is activated
+    async fn active_schema(&self) -> Result<String, DatasetError> {
+        let schema: String = sqlx::query_scalar(
+            format!(
+                "SELECT COALESCE((SELECT 'dataset_' || id FROM public.{} WHERE status=$1), 'public')",
+                Dataset::TABLE_NAME
+            )
+            .as_str(),
+        )
+        .bind(format!("{:?}", DatasetStatus::Active))
+        .fetch_one(self.database.pool())
+        .await?;
+
+        Ok(schema)
+    }
+
+    // copy a table of the active dataset, keeping the rows whose parents are in the dataset
+    async fn copy(&self, dataset: &Dataset, table: &TimetableTable) -> Result<(), DatasetError> {
+        let source: String = self.active_schema().await?;
+        let target: String = dataset.schema_name();
+
+        // generated geometries are computed again on insert
+        let columns: String = sqlx::query_scalar(
+            "SELECT string_agg(column_name, ',' ORDER BY ordinal_position) FROM information_schema.columns
+            WHERE table_schema=$1 AND table_name=$2 AND is_generated='NEVER'",
+        )
+        .bind(&target)
+        .bind(table.name)
+        .fetch_one(self.database.pool())
+        .await?;
+
+        let mut conditions: Vec<String> = vec!["true".to_string()];
+        for foreign_key in table.foreign_keys {
+            conditions.push(format!(
+                "({}) IN (SELECT {} FROM {}.{})",
+                foreign_key.columns, foreign_key.parent_columns, target, foreign_key.parent
+            ));
+        }
+
+        let copied: u64 = self
+            .database
+            .query(
+                format!(
+                    "INSERT INTO {0}.{2} ({3}) SELECT {3} FROM {1}.{2} WHERE {4}",
+                    target,
+                    source,
+                    table.name,
+                    columns,
+                    conditions.join(" AND ")
+                )
+                .as_str(),
+            )
+            .await?
+            .rows_affected();
+        info!("Copied {} {} from {}", copied, table.name, source);
+
+        Ok(())
+    }
+
+    // partial imports keep the other tables of the active dataset: the ones independent from the
+    // imported tables are copied before the import, the others after it
+    pub async fn copy_before_import(
+        &self,
+        dataset: &Dataset,
+        imported: &[&str],
+    ) -> Result<(), DatasetError> {
+        for table in TIMETABLE_TABLES {
+            if imported.contains(&table.name) {
+                // an imported table cannot wait for a parent copied after the import
+                for foreign_key in table.foreign_keys {
+                    let copied_after: bool = TimetableTable::find(foreign_key.parent).is_some_and(
+                        |parent| !imported.contains(&parent.name) && parent.depends_on(imported),
+                    );
+                    if copied_after {
+                        return Err(DatasetError::PartialImport(table.name, foreign_key.parent));
+                    }
+                }
+            } else if !table.depends_on(imported) {
+                self.copy(dataset, table).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub async fn copy_after_import(
+        &self,
+        dataset: &Dataset,
+        imported: &[&str],
+    ) -> Result<(), DatasetError> {
+        for table in TIMETABLE_TABLES {
+            if !imported.contains(&table.name) && table.depends_on(imported) {
+                self.copy(dataset, table).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn count(&self, query: String) -> Result<i64, DatasetError> {
+        let count: i64 = sqlx::query_scalar(query.as_str())
+            .fetch_one(self.database.pool())
+            .await?;
+
+        Ok(count)
+    }
+
+    pub async fn validate(&self, dataset: &Dataset) -> Result<(), DatasetError> {
+        let schema: String = dataset.schema_name();
+
+        for table_name in [
+            Bitfield::TABLE_NAME,
+            Line::TABLE_NAME,
+            Stop::TABLE_NAME,
+            Trip::TABLE_NAME,
+            TripStop::TABLE_NAME,
+            Information::TABLE_NAME,
+        ] {
+            let count: i64 = self
+                .count(format!("SELECT COUNT(*) FROM {}.{}", schema, table_name))
+                .await?;
+            if count == 0 {
+                return Err(DatasetError::EmptyTable(table_name));
+            }
+        }
+
+        let unknown_stops: i64 = self
+            .count(format!(
+                "SELECT COUNT(*) FROM {0}.{1} LEFT JOIN {0}.{2} ON {2}.id = {1}.stop_id WHERE {2}.id IS NULL",
+                schema,
+                TripStop::TABLE_NAME,
+                Stop::TABLE_NAME
+            ))
+            .await?;
+        if unknown_stops > 0 {
+            return Err(DatasetError::Inconsistent(
+                "trip stops referencing unknown stops",
+                unknown_stops,
+            ));
+        }
+
+        let empty_trips: i64 = self
+            .count(format!(
+                "SELECT COUNT(*) FROM {0}.{1} WHERE NOT EXISTS (SELECT 1 FROM {0}.{2} WHERE {2}.trip_id = {1}.id)",
+                schema,
+                Trip::TABLE_NAME,
+                TripStop::TABLE_NAME
+            ))
+            .await?;
+        if empty_trips > 0 {
+            return Err(DatasetError::Inconsistent("trips without stops", empty_trips));
+        }
+
+        Ok(())
+    }
+
+    async fn set_status(
+        transaction: &mut Transaction<'_, Postgres>,
+        id: i32,
+        status: DatasetStatus,
+    ) -> Result<(), DatasetError> {
+        sqlx::query(
+            format!(
+                "UPDATE public.{} SET status=$1, activated_at=CASE WHEN $1=$2 THEN NOW() ELSE activated_at END WHERE id=$3",
+                Dataset::TABLE_NAME
+            )
+            .as_str(),
+        )
+        .bind(format!("{:?}", status))
+        .bind(format!("{:?}", DatasetStatus::Active))
+        .bind(id)
+        .execute(&mut **transaction)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn active(
+        transaction: &mut Transaction<'_, Postgres>,
+    ) -> Result<Option<Dataset>, DatasetError> {
+        let dataset: Option<Dataset> = sqlx::query_as::<_, Dataset>(
+            format!(
+                "SELECT * FROM public.{} WHERE status=$1 FOR UPDATE",
+                Dataset::TABLE_NAME
+            )
+            .as_str(),
+        )
+        .bind(format!("{:?}", DatasetStatus::Active))
+        .fetch_optional(&mut **transaction)
+        .await?;
+
+        Ok(dataset)
+    }
+
+    // atomically switch the api to the dataset, the previous one is kept for rollback
+    pub async fn activate(&self, dataset: &Dataset) -> Result<(), DatasetError> {
+        let mut transaction: Transaction<'_, Postgres> = self.database.pool().begin().await?;
+
+        if let Some(active) = Self::active(&mut transaction).await? {
+            Self::set_status(&mut transaction, active.id, DatasetStatus::Ready).await?;
+        }
+        Self::set_status(&mut transaction, dataset.id, DatasetStatus::Active).await?;
+
+        transaction.commit().await?;
+        info!("Activated dataset {}", dataset.id);
+
+        self.prune().await
+    }
+
+    pub async fn fail(&self, dataset: &Dataset) -> Result<(), DatasetError> {
+        let mut transaction: Transaction<'_, Postgres> = self.database.pool().begin().await?;
+        Self::set_status(&mut transaction, dataset.id, DatasetStatus::Failed).await?;
+        transaction.commit().await?;
+
+        self.prune().await
+    }
+
+    // switch the api back to the most recently active dataset
+    pub async fn rollback(&self) -> Result<Dataset, DatasetError> {
+        let mut transaction: Transaction<'_, Postgres> = self.database.pool().begin().await?;
+
+        let previous: Option<Dataset> = sqlx::query_as::<_, Dataset>(
+            format!(
+                "SELECT * FROM public.{} WHERE status=$1 AND activated_at IS NOT NULL ORDER BY activated_at DESC LIMIT 1 FOR UPDATE",
+                Dataset::TABLE_NAME
+            )
+            .as_str(),
+        )
+        .bind(format!("{:?}", DatasetStatus::Ready))
+        .fetch_optional(&mut *transaction)
+        .await?;
+
+        let previous: Dataset = match previous {
+            Some(previous) => previous,
+            None => return Err(DatasetError::NoPreviousDataset),
+        };
+
+        if let Some(active) = Self::active(&mut transaction).await? {
+            Self::set_status(&mut transaction, active.id, DatasetStatus::Retired).await?;
+        }
+        Self::set_status(&mut transaction, previous.id, DatasetStatus::Active).await?;
+
+        transaction.commit().await?;
+        info!("Rolled back to dataset {}", previous.id);
+
+        self.prune().await?;
+
+        Ok(previous)
+    }
+
+    // drop the schemas of failed, retired and stale datasets
+    async fn prune(&self) -> Result<(), DatasetError> {
+        let datasets: Vec<Dataset> = sqlx::query_as::<_, Dataset>(
+            format!(
+                "SELECT * FROM public.{0} WHERE status IN ($1,$2)
+                OR (status=$3 AND id NOT IN (SELECT id FROM public.{0} WHERE status=$3 ORDER BY activated_at DESC NULLS LAST LIMIT $4))
+                OR (status=$5 AND id < (SELECT id FROM public.{0} WHERE status=$6))",
+                Dataset::TABLE_NAME
+            )
+            .as_str(),
+        )
+        .bind(format!("{:?}", DatasetStatus::Retired))
+        .bind(format!("{:?}", DatasetStatus::Failed))
+        .bind(format!("{:?}", DatasetStatus::Ready))
+        .bind(KEEP_PREVIOUS_DATASETS)
+        .bind(format!("{:?}", DatasetStatus::Loading))
+        .bind(format!("{:?}", DatasetStatus::Active))
+        .fetch_all(self.database.pool())
+        .await?;
+
+        for dataset in datasets {
+            let mut transaction: Transaction<'_, Postgres> =
+                self.database.pool().begin().await?;
+
+            sqlx::query(format!("DROP SCHEMA IF EXISTS {} CASCADE", dataset.schema_name()).as_str())
+                .execute(&mut *transaction)
+                .await?;
+            sqlx::query(format!("DELETE FROM public.{} WHERE id=$1", Dataset::TABLE_NAME).as_str())
+                .bind(dataset.id)
+                .execute(&mut *transaction)
+                .await?;
+
+            transaction.commit().await?;
+            info!("Dropped dataset {}", dataset.id);
+        }
+
+        Ok(())
+    }
+}
@@ -1,10 +1,10 @@
-use chrono::{NaiveDate, NaiveTime};
+use chrono::{Datelike, NaiveDate, NaiveTime};
 use unicode_segmentation::UnicodeSegmentation;
 
 use crate::model::{
     bitfield::Bitfield,
     direction::Direction as RouteDirection,
-    direction_leg::{self, DirectionLeg},
+    direction_leg::{self, DirectionLeg, MIN_DWELL},
     leg_step::LegStep,
     line::{Line, TransportMode},
     shape::Shape,
@@ -16,17 +16,24 @@ use crate::model::{
 };
 use std::{
     cmp,
+    collections::HashMap,
     fs::File,
-    io::{BufRead, BufReader, Error, Lines},
+    io::{BufRead, BufReader, Error, ErrorKind, Lines},
     panic,
     path::PathBuf,
 };
 
-use super::maps::Maps;
+use super::{
+    calibration::{calibrate, seconds_to_time, trip_band, ScheduledStop},
+    identifier::StableIds,
+    maps::{DirectionFailure, Maps},
+    routing::RoutingError,
+};
 
 pub struct HRDF {
     pub directory: PathBuf,
     pub agency_id: String,
+    pub information_id: i32,
 }
 
 macro_rules! define_record {
@@ -143,6 +150,15 @@ define_record! {
     }
 }
 
+define_record! {
+    RawGleis {
+        stop_id: i32 => 0 => 7,
+        journey_number: i32 => 8 => 14,
+        agency_id: String => 15 => 21,
+        platform: String => 22 => 30,
+    }
+}
+
 define_record! {
     RawStop {
         id: i32 => 0 => 7,
@@ -187,11 +203,45 @@ bit_field_number bit_field
 bitfield: 1 hexa = 4 bits, 4 bits = 4 days (0|1)
 2firsts and 2 lasts are inserted
 
-gleise: platform info
+gleis: platform info
+STOP_ID journey_number TU_CODE #ref (time) (bit_field_number)
+STOP_ID #ref G 'track' A 'section'
+older files give the platform in place of the #ref
 ?: line path information ?
 */
 
 impl HRDF {
+    // stops in order, shared by every trip serving the same stop pattern
+    fn stop_pattern(fahrplan: &Fahrplan) -> String {
+        return fahrplan
+            .stops
+            .iter()
+            .enumerate()
+            .map(|(j, stop)| format!("{}:{}", j + 1, stop.id))
+            .collect::<Vec<String>>()
+            .join(",");
+    }
+
+    // trip ids derived from the journey so that they survive new releases, the period (its first
+    // day, not its position in HRDF_PATH) only tells apart the trips of the periods served together
+    fn trip_ids(&self, fahrplans: &Vec<Fahrplan>) -> Vec<i64> {
+        let mut ids: StableIds = StableIds::new();
+
+        return fahrplans
+            .iter()
+            .map(|fahrplan| {
+                ids.get(&format!(
+                    "{}:{}:{}:{}:{}",
+                    self.information_id,
+                    fahrplan.z.agency_id,
+                    fahrplan.z.journey_number,
+                    fahrplan.z.option_count,
+                    Self::stop_pattern(fahrplan)
+                ))
+            })
+            .collect();
+    }
+
     fn create_reader(&self, filename: &str) -> Result<BufReader<File>, Error> {
         let path: PathBuf = self.directory.join(filename);
         let reader: BufReader<File> = BufReader::new(File::open(path)?);
@@ -223,6 +273,7 @@ impl HRDF {
             if ids.contains(&bf_line.number) {
                 let bitfield: Bitfield = Bitfield {
                     id: bf_line.number,
+                    information_id: self.information_id,
                     days: Bitfield::convert_hex_to_bits(bf_line.days.as_str()),
                 };
 
@@ -233,15 +284,35 @@ impl HRDF {
         return Ok(bitfields);
     }
 
+    // the period is identified by its first day, as yyyymmdd, so that its id does not depend on the other
+    // periods served with it
+    pub fn open(directory: PathBuf, agency_id: String) -> Result<HRDF, Error> {
+        let mut hrdf: HRDF = HRDF {
+            directory,
+            agency_id,
+            information_id: 0,
+        };
+        let start_date: NaiveDate = hrdf.get_corner_dates()?.start_date;
+        hrdf.information_id = start_date.year() * 10_000 + start_date.month() as i32 * 100 + start_date.day() as i32;
+
+        return Ok(hrdf);
+    }
+
     pub fn get_corner_dates(&self) -> Result<CornerDates, Error> {
         let reader: BufReader<File> = self.create_reader("ECKDATEN")?;
         let mut lines: Lines<BufReader<File>> = reader.lines();
 
+        let mut date = || -> Result<NaiveDate, Error> {
+            let line: String = lines
+                .next()
+                .ok_or(Error::new(ErrorKind::InvalidData, "missing date in ECKDATEN"))??;
+            NaiveDate::parse_from_str(line.trim(), "%d.%m.%Y").map_err(|error| {
+                Error::new(ErrorKind::InvalidData, format!("invalid date in ECKDATEN: {}", error))
+            })
+        };
         let corner_dates = CornerDates {
-            start_date: NaiveDate::parse_from_str(lines.next().unwrap()?.as_str(), "%d.%m.%Y")
-                .unwrap(),
-            end_date: NaiveDate::parse_from_str(lines.next().unwrap()?.as_str(), "%d.%m.%Y")
-                .unwrap(),
+            start_date: date()?,
+            end_date: date()?,
         };
 
         return Ok(corner_dates);
@@ -319,6 +390,70 @@ impl HRDF {
         return Ok(stops);
     }
 
+    // platforms keyed by (journey number, agency, stop), the first one listed wins when they depend on the time
+    pub fn get_platforms(&self) -> Result<HashMap<(i32, String, i32), String>, Error> {
+        let mut platforms: HashMap<(i32, String, i32), String> = HashMap::new();
+
+        let reader: BufReader<File> = match self.create_reader("GLEIS").or_else(|_| self.create_reader("GLEISE")) {
+            Ok(reader) => reader,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(platforms),
+            Err(error) => return Err(error),
+        };
+        let lines: Vec<String> = reader.lines().collect::<Result<Vec<String>, Error>>()?;
+
+        // track and section of every reference of a stop
+        let mut definitions: HashMap<(i32, String), String> = HashMap::new();
+        for line in &lines {
+            if line.get(8..9) != Some("#") {
+                continue;
+            }
+            let stop_id: Option<i32> = line.get(0..7).and_then(|id| id.trim().parse::<i32>().ok());
+            let reference: Option<&str> = line.get(8..16);
+            if let (Some(stop_id), Some(reference)) = (stop_id, reference) {
+                let values: Vec<&str> = line[16..].split('\'').collect();
+                // G 'track' A 'section', quoted values at the odd positions
+                let mut track: Option<&str> = None;
+                let mut section: Option<&str> = None;
+                for pair in values.chunks(2) {
+                    match (pair[0].trim(), pair.get(1)) {
+                        ("G", Some(value)) => track = Some(value),
+                        ("A", Some(value)) => section = Some(value),
+                        _ => {}
+                    }
+                }
+                if let Some(platform) = track.or(section) {
+                    definitions.insert((stop_id, reference.trim().to_string()), platform.to_string());
+                }
+            }
+        }
+
+        for line in &lines {
+            if line.get(8..9) == Some("#") {
+                continue;
+            }
+            let gleis: RawGleis = match RawGleis::from_line(line) {
+                Ok(gleis) => gleis,
+                Err(_) => continue,
+            };
+            if gleis.agency_id != self.agency_id {
+                continue;
+            }
+
+            let platform: Option<String> = if gleis.platform.starts_with('#') {
+                definitions.get(&(gleis.stop_id, gleis.platform.clone())).cloned()
+            } else {
+                Some(gleis.platform)
+            };
+            if let Some(platform) = platform {
+                platforms
+                    .entry((gleis.journey_number, gleis.agency_id, gleis.stop_id))
+                    .or_insert(platform);
+            }
+        }
+
+        return Ok(platforms);
+    }
+
     pub fn extract_stop_ids(&self, fahrplans: &Vec<Fahrplan>) -> Vec<i32> {
         let mut stop_ids: Vec<i32> = Vec::new();
 
@@ -338,45 +473,37 @@ impl HRDF {
         fahrplans: &Vec<Fahrplan>,
     ) -> (Vec<Trip>, Vec<Shape>, Vec<ShapeStop>) {
         let mut trips: Vec<Trip> = Vec::new();
-        let mut i: i32 = 1;
+        let trip_ids: Vec<i64> = self.trip_ids(fahrplans);
 
         let mut shapes: Vec<Shape> = Vec::new();
-        let mut si: i32 = 0;
+        let mut shape_ids: StableIds = StableIds::new();
 
         let mut shape_stops: Vec<ShapeStop> = Vec::new();
-        let mut ssi: i32 = 1;
-
-        for fahrplan in fahrplans {
-            let mut j: i16 = 0;
+        let mut shape_stop_ids: StableIds = StableIds::new();
 
-            let identifier = fahrplan
-                .stops
-                .iter()
-                .map(|stop| {
-                    j += 1;
-                    j.to_string() + stop.id.to_string().as_str()
-                })
-                .collect::<Vec<String>>()
-                .join("");
+        for (fahrplan, trip_id) in fahrplans.iter().zip(trip_ids) {
+            let identifier: String = Self::stop_pattern(fahrplan);
 
             let mut shape: Option<&Shape> =
                 shapes.iter().find(|shape| shape.identifier == identifier);
             if shape.is_none() {
-                si += 1;
-                let temp_shape = Shape { id: si, identifier };
+                let shape_id: i64 = shape_ids.get(&identifier);
+                let temp_shape = Shape {
+                    id: shape_id,
+                    identifier,
+                };
 
                 let mut y = 1;
                 for stop in &fahrplan.stops {
                     let shape_stop: ShapeStop = ShapeStop {
-                        id: ssi,
-                        shape_id: si,
+                        id: shape_stop_ids.get(&format!("{}:{}", shape_id, y)),
+                        shape_id,
                         stop_id: stop.id,
                         sequence: y,
                     };
 
                     shape_stops.push(shape_stop);
                     y += 1;
-                    ssi += 1;
                 }
 
                 shapes.push(temp_shape);
@@ -384,7 +511,7 @@ impl HRDF {
             }
 
             let trip: Trip = Trip {
-                id: i,
+                id: trip_id,
                 journey_number: fahrplan.z.journey_number,
                 option_count: fahrplan.z.option_count,
                 shape_id: Some(shape.unwrap().id),
@@ -392,6 +519,7 @@ impl HRDF {
                 transport_mode: fahrplan.g.transport_mode,
                 origin_id: fahrplan.g.origin_id,
                 destination_id: fahrplan.g.destination_id,
+                information_id: self.information_id,
                 bitfield_id: fahrplan.a.bit_field_number,
                 line_id: fahrplan.l.line_number,
                 direction: fahrplan.r.direction,
@@ -418,7 +546,6 @@ impl HRDF {
             };
 
             trips.push(trip);
-            i += 1;
         }
 
         return (trips, shapes, shape_stops);
@@ -429,32 +556,21 @@ impl HRDF {
         fahrplans: &Vec<Fahrplan>,
     ) -> (Vec<Trip>, Vec<RouteDirection>) {
         let mut trips: Vec<Trip> = Vec::new();
-        let mut i: i32 = 1;
+        let trip_ids: Vec<i64> = self.trip_ids(fahrplans);
 
         let mut directions: Vec<RouteDirection> = Vec::new();
-        let mut si: i32 = 0;
-
-        for fahrplan in fahrplans {
-            let mut j: i16 = 0;
+        let mut direction_ids: StableIds = StableIds::new();
 
-            let identifier = fahrplan
-                .stops
-                .iter()
-                .map(|stop| {
-                    j += 1;
-                    j.to_string() + stop.id.to_string().as_str()
-                })
-                .collect::<Vec<String>>()
-                .join("");
+        for (fahrplan, trip_id) in fahrplans.iter().zip(trip_ids) {
+            let identifier: String = Self::stop_pattern(fahrplan);
 
             let mut direction: Option<&RouteDirection> = directions
                 .iter()
                 .find(|direction| direction.identifier == identifier);
 
             if direction.is_none() {
-                si += 1;
                 let temp_dir = RouteDirection {
-                    id: si,
+                    id: direction_ids.get(&identifier),
                     identifier,
                     origin_id: fahrplan.g.origin_id,
                     destination_id: fahrplan.g.destination_id,
@@ -465,7 +581,7 @@ impl HRDF {
             }
 
             let trip: Trip = Trip {
-                id: i,
+                id: trip_id,
                 journey_number: fahrplan.z.journey_number,
                 option_count: fahrplan.z.option_count,
                 shape_id: None,
@@ -473,6 +589,7 @@ impl HRDF {
                 transport_mode: fahrplan.g.transport_mode,
                 origin_id: fahrplan.g.origin_id,
                 destination_id: fahrplan.g.destination_id,
+                information_id: self.information_id,
                 bitfield_id: fahrplan.a.bit_field_number,
                 line_id: fahrplan.l.line_number,
                 direction: fahrplan.r.direction,
@@ -499,46 +616,67 @@ impl HRDF {
             };
 
             trips.push(trip);
-            i += 1;
         }
 
         return (trips, directions);
     }
 
-    // create trip stops taking account of the route duration (from google maps)
+    // seconds since the start of the operating day, "-2512" is 25:12
+    fn scheduled_seconds(time: &str) -> Option<i32> {
+        if time.trim().is_empty() {
+            return None;
+        }
+
+        let hours: i32 = time[1..3].parse().ok()?;
+        let minutes: i32 = time[3..5].parse().ok()?;
+        return Some(hours * 3600 + minutes * 60);
+    }
+
+    fn scheduled_stops(fahrplan: &Fahrplan) -> Vec<ScheduledStop> {
+        return fahrplan
+            .stops
+            .iter()
+            .map(|stop| ScheduledStop {
+                arrival: Self::scheduled_seconds(&stop.arrival_time),
+                departure: Self::scheduled_seconds(&stop.departure_time),
+            })
+            .collect();
+    }
+
+    // create trip stops with the leg running times and dwell times calibrated against the timetable
     pub async fn get_trip_stops_with_directions(
         &self,
         fahrplans: &Vec<Fahrplan>,
         directions: &Vec<RouteDirection>,
         stops: &Vec<Stop>,
-        maps: Maps,
-    ) -> Result<(Vec<TripStop>, Vec<DirectionLeg>, Vec<LegStep>), Error> {
+        platforms: &HashMap<(i32, String, i32), String>,
+        maps: &Maps,
+    ) -> Result<(Vec<TripStop>, Vec<DirectionLeg>, Vec<LegStep>, Vec<DirectionFailure>), Error> {
         let mut trip_stops: Vec<TripStop> = Vec::new();
-        let mut a: i32 = 1;
-        let mut i: i32 = 1;
+        let mut failures: Vec<DirectionFailure> = Vec::new();
+        let trip_ids: Vec<i64> = self.trip_ids(fahrplans);
+        let mut trip_stop_ids: StableIds = StableIds::new();
 
         let mut direction_legs: Vec<DirectionLeg> = Vec::new();
         let mut leg_steps: Vec<LegStep> = Vec::new();
-        let mut leg_id = 1;
-        let mut step_id = 1;
+        let mut leg_ids: StableIds = StableIds::new();
+        let mut step_ids: StableIds = StableIds::new();
 
+        // trips of every direction
+        let mut direction_fahrplans: HashMap<i64, Vec<&Fahrplan>> = HashMap::new();
         for fahrplan in fahrplans {
-            let mut j: i16 = 0;
-
-            let identifier = fahrplan
-                .stops
-                .iter()
-                .map(|stop| {
-                    j += 1;
-                    j.to_string() + stop.id.to_string().as_str()
-                })
-                .collect::<Vec<String>>()
-                .join("");
-            let direction = directions.iter().find(|d| d.identifier == identifier);
-
-            if direction.is_none() {
-                continue;
+            let identifier: String = Self::stop_pattern(fahrplan);
+            if let Some(direction) = directions.iter().find(|d| d.identifier == identifier) {
+                direction_fahrplans.entry(direction.id).or_default().push(fahrplan);
             }
+        }
+
+        // route every direction along its first trip
+        for direction in directions {
+            let fahrplan: &Fahrplan = match direction_fahrplans.get(&direction.id) {
+                Some(dfahrplans) => dfahrplans[0],
+                None => continue,
+            };
 
             let tstops: Vec<&Stop> = fahrplan
                 .stops
@@ -546,142 +684,156 @@ impl HRDF {
                 .map(|stop| stops.iter().find(|s| s.id == stop.id).unwrap())
                 .collect();
 
-            let mut dlegs: Vec<&DirectionLeg> = direction_legs
-                .iter()
-                .filter(|dleg| dleg.direction_id == direction.unwrap().id)
-                .collect::<Vec<&DirectionLeg>>();
+            let result = maps
+                .get_direction_sub_from_direction(
+                    direction,
+                    fahrplan.g.transport_mode,
+                    &tstops,
+                    stops,
+                    &mut leg_ids,
+                    &mut step_ids,
+                )
+                .await;
 
-            if dlegs.len() == 0 {
-                let (dl, ls) = maps
-                    .get_direction_sub_from_direction(
-                        &direction.unwrap(),
-                        &tstops,
-                        stops,
-                        leg_id,
-                        step_id,
-                    )
-                    .await
-                    .unwrap();
-
-                direction_legs.extend(dl);
-                leg_steps.extend(ls);
-
-                dlegs = direction_legs
-                    .iter()
-                    .filter(|dleg| dleg.direction_id == direction.unwrap().id)
-                    .collect::<Vec<&DirectionLeg>>();
-
-                leg_id = direction_legs.len() as i32 + 1;
-                step_id = leg_steps.len() as i32 + 1;
+            match result {
+                Ok((dl, ls)) => {
+                    direction_legs.extend(dl);
+                    leg_steps.extend(ls);
+                }
+                Err(error) => failures.push(DirectionFailure {
+                    direction_id: direction.id,
+                    identifier: direction.identifier.clone(),
+                    error,
+                }),
             }
+        }
 
-            let mut h: i16 = 1;
-            for stop in &fahrplan.stops {
-                let previous_stop: Option<i32> = if trip_stops.last().is_some() {
-                    Some(trip_stops.last().unwrap().stop_id)
-                } else {
-                    None
-                };
-                let previous_departure: Option<NaiveTime> = if trip_stops.last().is_some() {
-                    Some(trip_stops.last().unwrap().departure_time.unwrap())
-                } else {
-                    None
-                };
-                let mut arrival_time: Option<NaiveTime> = if stop.arrival_time.is_empty() {
-                    None
-                } else {
-                    Some(
-                        NaiveTime::from_hms_opt(
-                            stop.arrival_time[1..3].parse::<u32>().unwrap() % 24, // TODO: manage trips that are after 00h
-                            stop.arrival_time[3..5].parse().unwrap(),
-                            0,
-                        )
-                        .unwrap(),
-                    )
-                };
+        for direction in directions {
+            let scheduled: Vec<Vec<ScheduledStop>> = direction_fahrplans
+                .get(&direction.id)
+                .map(|dfahrplans| dfahrplans.iter().map(|fahrplan| Self::scheduled_stops(fahrplan)).collect())
+                .unwrap_or_default();
+            let mut dlegs: Vec<&mut DirectionLeg> = direction_legs
+                .iter_mut()
+                .filter(|dleg| dleg.direction_id == direction.id)
+                .collect();
 
-                let departure_time: Option<NaiveTime> = if stop.departure_time.is_empty() {
-                    None
-                } else {
-                    Some(
-                        NaiveTime::from_hms_opt(
-                            stop.departure_time[1..3].parse::<u32>().unwrap() % 24, // TODO: manage trips that are after 00h
-                            stop.departure_time[3..5].parse().unwrap(),
-                            0,
-                        )
-                        .unwrap(),
-                    )
-                };
+            calibrate(&mut dlegs, &scheduled);
+        }
 
-                let stop_duration: i64 = if arrival_time.is_some() && departure_time.is_some() {
-                    departure_time
-                        .unwrap()
-                        .signed_duration_since(arrival_time.unwrap())
-                        .num_seconds()
-                        + 15
-                } else {
-                    0
-                };
+        for (fahrplan, trip_id) in fahrplans.iter().zip(trip_ids) {
+            let identifier: String = Self::stop_pattern(fahrplan);
+            let direction = directions.iter().find(|d| d.identifier == identifier);
 
-                if arrival_time.is_some() && previous_departure.is_some() && previous_stop.is_some()
-                {
-                    let trip_duration = dlegs
-                        .iter()
-                        .find(|dleg| {
-                            dleg.origin_id == previous_stop.unwrap()
-                                && dleg.destination_id == stop.id
-                        })
-                        .unwrap()
-                        .duration;
-                    let real_arrival_time = previous_departure.unwrap()
-                        + chrono::Duration::seconds(trip_duration as i64);
+            if direction.is_none() {
+                continue;
+            }
 
-                    let difference = arrival_time
-                        .unwrap()
-                        .signed_duration_since(real_arrival_time);
-                    if difference.num_seconds().abs() < 60 {
-                        arrival_time = Some(real_arrival_time);
+            let dlegs: Vec<&DirectionLeg> = direction_legs
+                .iter()
+                .filter(|dleg| dleg.direction_id == direction.unwrap().id)
+                .collect::<Vec<&DirectionLeg>>();
+
+            let scheduled: Vec<ScheduledStop> = Self::scheduled_stops(fahrplan);
+            let band: usize = trip_band(&scheduled).unwrap_or(0);
+
+            // calibrated times follow the legs from the first departure and fall back on the timetable
+            // once they are a minute away from it, directions that failed to route keep the timetable times
+            let mut previous_departure: Option<i32> = None;
+            let mut h: i16 = 1;
+            for (k, (stop, times)) in fahrplan.stops.iter().zip(&scheduled).enumerate() {
+                let dleg: Option<&&DirectionLeg> = dlegs.iter().find(|dleg| {
+                    k > 0 && dleg.origin_id == fahrplan.stops[k - 1].id && dleg.destination_id == stop.id
+                });
+
+                let mut arrival: Option<i32> = times.arrival;
+                if let (Some(scheduled_arrival), Some(dleg), Some(departure)) = (times.arrival, dleg, previous_departure) {
+                    let calibrated_arrival: i32 = departure + dleg.running_time(band);
+                    if (calibrated_arrival - scheduled_arrival).abs() < 60 {
+                        arrival = Some(calibrated_arrival);
                     }
                 }
 
+                let dwell: i32 = dleg.map_or(MIN_DWELL, |dleg| dleg.dwell_time(band));
+                let departure: Option<i32> = match (arrival, times.departure) {
+                    (Some(arrival), Some(scheduled_departure)) => Some(scheduled_departure.max(arrival + dwell)),
+                    (Some(arrival), None) => Some(arrival),
+                    (None, scheduled_departure) => scheduled_departure,
+                };
+
                 let trip_stop: TripStop = TripStop {
-                    id: i,
+                    id: trip_stop_ids.get(&format!("{}:{}", trip_id, h)),
                     stop_id: stop.id,
-                    trip_id: a,
+                    trip_id,
                     sequence: h,
-                    arrival_time,
-                    departure_time: if arrival_time.is_some() {
-                        Some(arrival_time.unwrap() + chrono::Duration::seconds(stop_duration))
-                    } else {
-                        departure_time
-                    },
+                    arrival_time: arrival.map(seconds_to_time),
+                    departure_time: departure.map(seconds_to_time),
+                    platform: platforms
+                        .get(&(fahrplan.z.journey_number, fahrplan.z.agency_id.clone(), stop.id))
+                        .cloned(),
                 };
 
                 trip_stops.push(trip_stop);
+                previous_departure = departure;
 
                 h += 1;
-                i += 1;
             }
+        }
+
+        Ok((trip_stops, direction_legs, leg_steps, failures))
+    }
+
+    // routing requests an import of the directions would send, one trip is routed per direction
+    pub async fn estimate_direction_requests(
+        &self,
+        fahrplans: &Vec<Fahrplan>,
+        directions: &Vec<RouteDirection>,
+        stops: &Vec<Stop>,
+        maps: &Maps,
+    ) -> Result<usize, RoutingError> {
+        let mut routed: Vec<i64> = Vec::new();
+        let mut requests: usize = 0;
+
+        for fahrplan in fahrplans {
+            let identifier: String = Self::stop_pattern(fahrplan);
+            let direction = directions.iter().find(|d| d.identifier == identifier);
+
+            if direction.is_none() || routed.contains(&direction.unwrap().id) {
+                continue;
+            }
+            routed.push(direction.unwrap().id);
+
+            let tstops: Vec<&Stop> = fahrplan
+                .stops
+                .iter()
+                .map(|stop| stops.iter().find(|s| s.id == stop.id).unwrap())
+                .collect();
 
-            a += 1;
+            requests += maps
+                .count_direction_requests(fahrplan.g.transport_mode, &tstops)
+                .await?;
         }
 
-        Ok((trip_stops, direction_legs, leg_steps))
+        Ok(requests)
     }
 
-    pub fn to_trip_stops(&self, fahrplans: &Vec<Fahrplan>) -> Vec<TripStop> {
+    pub fn to_trip_stops(
+        &self,
+        fahrplans: &Vec<Fahrplan>,
+        platforms: &HashMap<(i32, String, i32), String>,
+    ) -> Vec<TripStop> {
         let mut trip_stops: Vec<TripStop> = Vec::new();
-        let mut i: i32 = 1;
-        let mut a: i32 = 1;
+        let trip_ids: Vec<i64> = self.trip_ids(fahrplans);
+        let mut trip_stop_ids: StableIds = StableIds::new();
 
-        for fahrplan in fahrplans {
+        for (fahrplan, trip_id) in fahrplans.iter().zip(trip_ids) {
             let mut j: i16 = 1;
 
             for stop in &fahrplan.stops {
                 let trip_stop: TripStop = TripStop {
-                    id: a,
+                    id: trip_stop_ids.get(&format!("{}:{}", trip_id, j)),
                     stop_id: stop.id,
-                    trip_id: i,
+                    trip_id,
                     sequence: j,
                     arrival_time: if stop.arrival_time.is_empty() {
                         None
@@ -707,14 +859,14 @@ impl HRDF {
                             .unwrap(),
                         )
                     },
+                    platform: platforms
+                        .get(&(fahrplan.z.journey_number, fahrplan.z.agency_id.clone(), stop.id))
+                        .cloned(),
                 };
 
                 trip_stops.push(trip_stop);
                 j += 1;
-                a += 1;
             }
-
-            i += 1;
         }
 
         return trip_stops;
@@ -0,0 +1,74 @@
+use std::collections::HashSet;
+
+// ids stay below 2^53 so that javascript clients read them exactly
+const ID_MASK: u64 = (1 << 53) - 1;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+// fnv-1a, unlike DefaultHasher its output never changes between builds
+fn fnv1a(key: &str) -> u64 {
+    let mut hash: u64 = FNV_OFFSET_BASIS;
+    for byte in key.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    return hash;
+}
+
+// derives ids from the natural key of a record so that they survive re-imports
+pub struct StableIds {
+    used: HashSet<i64>,
+}
+
+impl StableIds {
+    pub fn new() -> Self {
+        StableIds {
+            used: HashSet::new(),
+        }
+    }
+
+    // on a collision the next free id is taken, deterministic as long as keys come in the same order
+    pub fn get(&mut self, key: &str) -> i64 {
+        let mut id: i64 = (fnv1a(key) & ID_MASK).max(1) as i64;
+        while !self.used.insert(id) {
+            id = if id as u64 >= ID_MASK { 1 } else { id + 1 };
+        }
+        return id;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fnv1a_matches_the_reference_values() {
+        assert_eq!(fnv1a(""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a("a"), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn ids_survive_a_new_import() {
+        let keys: Vec<&str> = vec!["1:000881:12:0:1", "1:000881:13:0:1", "1:000881:12:1:1"];
+
+        let mut first: StableIds = StableIds::new();
+        let mut second: StableIds = StableIds::new();
+        for key in keys {
+            let id: i64 = first.get(key);
+            assert_eq!(id, second.get(key));
+            assert!(id >= 1 && id as u64 <= ID_MASK);
+        }
+    }
+
+    #[test]
+    fn colliding_keys_take_the_next_free_id() {
+        let mut ids: StableIds = StableIds::new();
+        let id: i64 = ids.get("trip");
+
+        assert_eq!(ids.get("trip"), id + 1);
+        ids.used.insert(id + 3);
+        assert_eq!(ids.get("trip"), id + 2);
+        assert_eq!(ids.get("trip"), id + 4);
+    }
+}
@@ -0,0 +1,404 @@
+use std::fmt;
+
+use log::info;
+use sqlx::{Error, Postgres, Sqlite, Transaction};
+
+use super::{
+    database::{self, Table},
+    migration::{TimetableTable, TIMETABLE_TABLES},
+    sqlite,
+    storage::Storage,
+};
+
+pub struct TableChanges {
+    pub table_name: &'static str,
+    pub added: i64,
+    pub changed: i64,
+    pub removed: i64,
+}
+
+#[derive(Default)]
+pub struct ChangeReport {
+    pub tables: Vec<TableChanges>,
+}
+
+impl fmt::Display for ChangeReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for table in &self.tables {
+            writeln!(
+                f,
+                "{}: {} added, {} changed, {} removed",
+                table.table_name, table.added, table.changed, table.removed
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// temp tables only live on the connection that created them, the whole upsert keeps one
+enum Connection {
+    Postgres(Box<Transaction<'static, Postgres>>),
+    Sqlite(Box<Transaction<'static, Sqlite>>),
+}
+
+impl Connection {
+    async fn execute(&mut self, statement: &str) -> Result<u64, Error> {
+        let rows_affected: u64 = match self {
+            Connection::Postgres(transaction) => sqlx::query(statement)
+                .execute(&mut ***transaction)
+                .await?
+                .rows_affected(),
+            Connection::Sqlite(transaction) => sqlx::query(statement)
+                .execute(&mut ***transaction)
+                .await?
+                .rows_affected(),
+        };
+
+        Ok(rows_affected)
+    }
+
+    async fn report(&mut self, changes: &TableChanges) -> Result<(), Error> {
+        match self {
+            Connection::Postgres(transaction) => {
+                sqlx::query("INSERT INTO import_reports (table_name,added,changed,removed) VALUES ($1,$2,$3,$4)")
+                    .bind(changes.table_name)
+                    .bind(changes.added)
+                    .bind(changes.changed)
+                    .bind(changes.removed)
+                    .execute(&mut ***transaction)
+                    .await?;
+            }
+            Connection::Sqlite(transaction) => {
+                sqlx::query("INSERT INTO import_reports (table_name,added,changed,removed) VALUES (?1,?2,?3,?4)")
+                    .bind(changes.table_name)
+                    .bind(changes.added)
+                    .bind(changes.changed)
+                    .bind(changes.removed)
+                    .execute(&mut ***transaction)
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn count(&mut self, query: &str) -> Result<i64, Error> {
+        let count: i64 = match self {
+            Connection::Postgres(transaction) => {
+                sqlx::query_scalar(query).fetch_one(&mut ***transaction).await?
+            }
+            Connection::Sqlite(transaction) => {
+                sqlx::query_scalar(query).fetch_one(&mut ***transaction).await?
+            }
+        };
+
+        Ok(count)
+    }
+}
+
+struct StagedTable {
+    name: &'static str,
+    columns: Vec<String>,
+    primary_key: &'static [&'static str],
+}
+
+impl StagedTable {
+    fn staging_name(&self) -> String {
+        format!("staging_{}", self.name)
+    }
+
+    fn key_condition(&self, target: &str, staged: &str) -> String {
+        self.primary_key
+            .iter()
+            .map(|column| format!("{0}.{2} = {1}.{2}", target, staged, column))
+            .collect::<Vec<String>>()
+            .join(" AND ")
+    }
+
+    fn row(&self, alias: &str) -> String {
+        self.columns
+            .iter()
+            .map(|column| format!("{}.{}", alias, column))
+            .collect::<Vec<String>>()
+            .join(",")
+    }
+}
+
+// stages every period of an import, then updates changed rows and deletes the vanished ones at once
+pub struct Upsert {
+    connection: Connection,
+    tables: Vec<StagedTable>,
+}
+
+impl Upsert {
+    pub async fn begin(storage: &Storage) -> Result<Upsert, Error> {
+        let connection: Connection = match storage {
+            Storage::Postgres(database) => {
+                Connection::Postgres(Box::new(database.pool().begin().await?))
+            }
+            Storage::Sqlite(database) => {
+                Connection::Sqlite(Box::new(database.pool().begin().await?))
+            }
+            Storage::Memory(_) => {
+                return Err(Error::Configuration(
+                    "the memory backend cannot be imported into".into(),
+                ))
+            }
+        };
+
+        Ok(Upsert {
+            connection,
+            tables: Vec::new(),
+        })
+    }
+
+    // tables are upserted in the order they are first staged, parents before children
+    pub async fn stage<T>(&mut self, data: &Vec<T>) -> Result<u64, Error>
+    where
+        T: serde::Serialize + Table,
+    {
+        let staging_name: String = format!("staging_{}", T::TABLE_NAME);
+
+        if !self.tables.iter().any(|table| table.name == T::TABLE_NAME) {
+            self.connection
+                .execute(format!("DROP TABLE IF EXISTS {}", staging_name).as_str())
+                .await?;
+            self.connection
+                .execute(
+                    format!(
+                        "CREATE TEMP TABLE {} AS SELECT * FROM {} WHERE 1=0",
+                        staging_name,
+                        T::TABLE_NAME
+                    )
+                    .as_str(),
+                )
+                .await?;
+            // rows repeated by several periods are staged once, the first one wins like on insert
+            self.connection
+                .execute(
+                    format!(
+                        "CREATE UNIQUE INDEX {0}_key ON {0} ({1})",
+                        staging_name,
+                        T::PRIMARY_KEY.join(",")
+                    )
+                    .as_str(),
+                )
+                .await?;
+
+            self.tables.push(StagedTable {
+                name: T::TABLE_NAME,
+                columns: T::keys()
+                    .trim_matches(|c| c == '(' || c == ')')
+                    .split(',')
+                    .map(|column| column.trim().to_string())
+                    .collect(),
+                primary_key: T::PRIMARY_KEY,
+            });
+        }
+
+        let mut rows_affected: u64 = 0;
+        match &mut self.connection {
+            Connection::Postgres(transaction) => {
+                for mut builder in database::insert_queries(&staging_name, data) {
+                    builder.push(" ON CONFLICT DO NOTHING");
+                    rows_affected += builder.build().execute(&mut ***transaction).await?.rows_affected();
+                }
+            }
+            Connection::Sqlite(transaction) => {
+                for mut builder in sqlite::insert_queries(&staging_name, data) {
+                    builder.push(" ON CONFLICT DO NOTHING");
+                    rows_affected += builder.build().execute(&mut ***transaction).await?.rows_affected();
+                }
+            }
+        }
+
+        Ok(rows_affected)
+    }
+
+    // condition on the rows of a table that vanished from the import or reference a vanished row
+    fn vanished(&self, timetable: &TimetableTable, alias: &str, depth: usize) -> String {
+        if let Some(table) = self.tables.iter().find(|table| table.name == timetable.name) {
+            let staged: String = format!("staged{}", depth);
+            return format!(
+                "NOT EXISTS (SELECT 1 FROM {} AS {} WHERE {})",
+                table.staging_name(),
+                staged,
+                table.key_condition(alias, &staged)
+            );
+        }
+
+        let staged_names: Vec<&str> = self.tables.iter().map(|table| table.name).collect();
+        let parent_alias: String = format!("parent{}", depth + 1);
+        let conditions: Vec<String> = timetable
+            .foreign_keys
+            .iter()
+            .filter_map(|foreign_key| {
+                let parent: &TimetableTable = TimetableTable::find(foreign_key.parent)?;
+                if !staged_names.contains(&parent.name) && !parent.depends_on(&staged_names) {
+                    return None;
+                }
+
+                let join: Vec<String> = foreign_key
+                    .columns
+                    .split(',')
+                    .zip(foreign_key.parent_columns.split(','))
+                    .map(|(column, parent_column)| {
+                        format!("{}.{} = {}.{}", parent_alias, parent_column, alias, column)
+                    })
+                    .collect();
+                Some(format!(
+                    "EXISTS (SELECT 1 FROM {} AS {} WHERE {} AND {})",
+                    parent.name,
+                    parent_alias,
+                    join.join(" AND "),
+                    self.vanished(parent, &parent_alias, depth + 1)
+                ))
+            })
+            .collect();
+
+        if conditions.is_empty() {
+            return "false".to_string();
+        }
+        return format!("({})", conditions.join(" OR "));
+    }
+
+    pub async fn finish(mut self) -> Result<ChangeReport, Error> {
+        let mut report: ChangeReport = ChangeReport::default();
+
+        for table in &self.tables {
+            let staging_name: String = table.staging_name();
+
+            let added: i64 = self
+                .connection
+                .count(
+                    format!(
+                        "SELECT COUNT(*) FROM {0} AS staged WHERE NOT EXISTS (SELECT 1 FROM {1} AS target WHERE {2})",
+                        staging_name,
+                        table.name,
+                        table.key_condition("target", "staged")
+                    )
+                    .as_str(),
+                )
+                .await?;
+            let changed: i64 = self
+                .connection
+                .count(
+                    format!(
+                        "SELECT COUNT(*) FROM {0} AS staged JOIN {1} AS target ON {2} WHERE ({3}) IS DISTINCT FROM ({4})",
+                        staging_name,
+                        table.name,
+                        table.key_condition("target", "staged"),
+                        table.row("target"),
+                        table.row("staged")
+                    )
+                    .as_str(),
+                )
+                .await?;
+
+            let updates: Vec<String> = table
+                .columns
+                .iter()
+                .filter(|column| !table.primary_key.contains(&column.as_str()))
+                .map(|column| format!("{0} = excluded.{0}", column))
+                .collect();
+            let on_conflict: String = if updates.is_empty() {
+                "DO NOTHING".to_string()
+            } else {
+                format!(
+                    "DO UPDATE SET {} WHERE ({}) IS DISTINCT FROM ({})",
+                    updates.join(","),
+                    table.row(table.name),
+                    table.row("excluded")
+                )
+            };
+            self.connection
+                .execute(
+                    format!(
+                        "INSERT INTO {0} ({1}) SELECT {1} FROM {2} WHERE true ON CONFLICT ({3}) {4}",
+                        table.name,
+                        table.columns.join(","),
+                        staging_name,
+                        table.primary_key.join(","),
+                        on_conflict
+                    )
+                    .as_str(),
+                )
+                .await?;
+
+            report.tables.push(TableChanges {
+                table_name: table.name,
+                added,
+                changed,
+                removed: 0,
+            });
+        }
+
+        // children are cleaned before their parents to respect the foreign keys, the tables that
+        // were not imported lose the rows referencing vanished ones
+        let staged_names: Vec<&str> = self.tables.iter().map(|table| table.name).collect();
+        for timetable in TIMETABLE_TABLES.iter().rev() {
+            let staged: Option<usize> = staged_names.iter().position(|name| *name == timetable.name);
+            if staged.is_none() && !timetable.depends_on(&staged_names) {
+                continue;
+            }
+
+            let removed: i64 = self
+                .connection
+                .execute(
+                    format!(
+                        "DELETE FROM {} WHERE {}",
+                        timetable.name,
+                        self.vanished(timetable, timetable.name, 0)
+                    )
+                    .as_str(),
+                )
+                .await? as i64;
+            match staged {
+                Some(i) => report.tables[i].removed = removed,
+                None if removed > 0 => report.tables.push(TableChanges {
+                    table_name: timetable.name,
+                    added: 0,
+                    changed: 0,
+                    removed,
+                }),
+                None => {}
+            }
+        }
+
+        for changes in &report.tables {
+            self.connection.report(changes).await?;
+        }
+        for table in &self.tables {
+            self.connection
+                .execute(format!("DROP TABLE {}", table.staging_name()).as_str())
+                .await?;
+        }
+
+        match self.connection {
+            Connection::Postgres(transaction) => transaction.commit().await?,
+            Connection::Sqlite(transaction) => transaction.commit().await?,
+        }
+        info!("Upserted {} tables", report.tables.len());
+
+        Ok(report)
+    }
+}
+
+// destination of an import run
+pub enum Import {
+    // plain inserts, rows that already exist are kept as they are
+    Insert(Storage),
+    Upsert(Upsert),
+}
+
+impl Import {
+    pub async fn insert_many<T>(&mut self, data: &Vec<T>) -> Result<u64, Error>
+    where
+        T: serde::Serialize + Table,
+    {
+        match self {
+            Import::Insert(storage) => storage.insert_many(data).await,
+            Import::Upsert(upsert) => upsert.stage(data).await,
+        }
+    }
+}
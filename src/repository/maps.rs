@@ -1,111 +1,147 @@
-use reqwest::{Error, Response};
-use serde::{Deserialize, Serialize};
+use std::fmt;
 
 use crate::model::{
-    direction::Direction, direction_leg::DirectionLeg, leg_step::LegStep, shape_point::ShapePoint,
-    shape_stop::ShapeStop, stop::Stop, trip_stop::TripStop,
+    direction::Direction, direction_leg::DirectionLeg, leg_step::LegStep, line::TransportMode,
+    shape_point::ShapePoint, shape_stop::ShapeStop, stop::Stop,
 };
 
-#[derive(Debug, Deserialize, Serialize)]
-struct DirectionResponse {
-    routes: Vec<RouteResponse>,
-}
+use super::{
+    identifier::StableIds,
+    quota::RequestQuota,
+    routing::{encode_polyline, Location, RoutedLeg, RoutingError, RoutingProvider, SnappedLocation},
+    routing_cache::RoutingCache,
+};
 
-#[derive(Debug, Deserialize, Serialize)]
-struct RouteResponse {
-    bounds: Bounds,
-    legs: Vec<LegResponse>,
-}
+// input points snapped twice between two chunks of a long path
+const SNAP_OVERLAP: usize = 10;
 
-#[derive(Debug, Deserialize, Serialize)]
-struct LegResponse {
-    distance: Distance,
-    duration: Duration,
-    start_location: Position,
-    end_location: Position,
-    steps: Vec<StepResponse>,
+// builds legs, steps and shape points with the routing provider selected by ROUTING_PROVIDER
+pub struct Maps {
+    pub provider: Box<dyn RoutingProvider>,
+    pub cache: Option<RoutingCache>,
+    pub quota: RequestQuota,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-struct StepResponse {
-    distance: Distance,
-    duration: Duration,
-    start_location: Position,
-    end_location: Position,
+// direction left without legs, its trips keep the timetable times
+pub struct DirectionFailure {
+    pub direction_id: i64,
+    pub identifier: String,
+    pub error: RoutingError,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-struct Distance {
-    text: String,
-    value: i32,
+impl fmt::Display for DirectionFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({}): {}", self.identifier, self.direction_id, self.error)
+    }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-struct Duration {
-    text: String,
-    value: i32,
-}
+impl Maps {
+    // legs already routed for another direction or a previous import are reused
+    async fn cached_legs(
+        &self,
+        mode: TransportMode,
+        trip_stops: &[&Stop],
+    ) -> Result<Vec<Option<RoutedLeg>>, RoutingError> {
+        let provider: String = self.provider.name();
+
+        let mut routed: Vec<Option<RoutedLeg>> = Vec::new();
+        for pair in trip_stops.windows(2) {
+            routed.push(match &self.cache {
+                Some(cache) => cache.get(&provider, mode, pair[0].id, pair[1].id).await?,
+                None => None,
+            });
+        }
 
-#[derive(Debug, Deserialize, Serialize)]
-struct Bounds {
-    northeast: Position,
-    southwest: Position,
-}
+        return Ok(routed);
+    }
 
-#[derive(Debug, Deserialize, Serialize)]
-struct Position {
-    lat: f64,
-    lng: f64,
-}
+    // the missing legs are routed by runs of consecutive stops within the waypoint limit,
+    // one request per run, given as (first leg, last stop)
+    fn missing_runs(&self, routed: &[Option<RoutedLeg>]) -> Vec<(usize, usize)> {
+        let mut runs: Vec<(usize, usize)> = Vec::new();
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct RoadResponse {
-    pub snappedPoints: Vec<SnappedPoint>,
-    pub warningMessage: Option<String>,
-}
+        let mut k: usize = 0;
+        while k < routed.len() {
+            if routed[k].is_some() {
+                k += 1;
+                continue;
+            }
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct SnappedPoint {
-    pub location: Location,
-    pub originalIndex: Option<i32>,
-    pub placeId: String,
-}
+            let start: usize = k;
+            while k < routed.len() && routed[k].is_none() && k - start < self.provider.max_waypoints() - 1 {
+                k += 1;
+            }
+            runs.push((start, k));
+        }
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct Location {
-    pub latitude: f64,
-    pub longitude: f64,
-}
+        return runs;
+    }
 
-pub struct Maps {
-    pub api_key: String,
-}
+    // chunks of a path within the snap limit, overlapping so that both sides of a cut are snapped
+    // with some context, as (start, end, first kept, end kept) input point indexes
+    fn snap_chunks(&self, length: usize) -> Vec<(usize, usize, usize, usize)> {
+        let size: usize = self.provider.max_snap_points().min(length).max(2);
+        let overlap: usize = SNAP_OVERLAP.min(size / 2);
+
+        let mut chunks: Vec<(usize, usize, usize, usize)> = Vec::new();
+        if length == 0 {
+            return chunks;
+        }
+
+        let mut start: usize = 0;
+        let mut lower: usize = 0;
+        loop {
+            let end: usize = (start + size).min(length);
+            if end == length {
+                chunks.push((start, end, lower, length));
+                break;
+            }
+
+            let next_start: usize = end - overlap;
+            let boundary: usize = next_start + overlap / 2;
+            chunks.push((start, end, lower, boundary));
+            start = next_start;
+            lower = boundary;
+        }
+
+        return chunks;
+    }
+
+    // requests needed to snap a path, without sending them
+    pub fn count_snap_requests(&self, length: usize) -> usize {
+        return self.snap_chunks(length).len();
+    }
+
+    // requests needed to route a direction, without sending them
+    pub async fn count_direction_requests(
+        &self,
+        mode: TransportMode,
+        trip_stops: &Vec<&Stop>,
+    ) -> Result<usize, RoutingError> {
+        let routed: Vec<Option<RoutedLeg>> = self.cached_legs(mode, trip_stops).await?;
+
+        return Ok(self.missing_runs(&routed).len());
+    }
 
-impl Maps {
     pub async fn get_direction_sub_from_direction(
         &self,
         direction: &Direction,
+        mode: TransportMode,
         _trip_stops: &Vec<&Stop>,
         stops: &Vec<Stop>,
-        start_leg_id: i32,
-        strat_step_id: i32,
-    ) -> Result<(Vec<DirectionLeg>, Vec<LegStep>), Error> {
-        let mut trip_stops: Vec<&Stop> = _trip_stops.iter().map(|s| *s).collect::<Vec<&Stop>>();
-
-        let mut leg_id = start_leg_id;
-        let mut step_id = strat_step_id;
+        leg_ids: &mut StableIds,
+        step_ids: &mut StableIds,
+    ) -> Result<(Vec<DirectionLeg>, Vec<LegStep>), RoutingError> {
+        let trip_stops: Vec<&Stop> = _trip_stops.to_vec();
+        let provider: String = self.provider.name();
 
         let mut direction_legs: Vec<DirectionLeg> = Vec::new();
         let mut leg_steps: Vec<LegStep> = Vec::new();
 
-        let mut i: i16 = 1;
-        while trip_stops.len() > 0 {
-            let max = if trip_stops.len() > 25 {
-                25
-            } else {
-                trip_stops.len()
-            };
-            let waypoints: Vec<Location> = (&trip_stops)[0..max]
+        let mut routed: Vec<Option<RoutedLeg>> = self.cached_legs(mode, &trip_stops).await?;
+
+        for (start, k) in self.missing_runs(&routed) {
+            let waypoints: Vec<Location> = (&trip_stops)[start..=k]
                 .iter()
                 .map(|trip_stop| {
                     let stop = stops.iter().find(|s| s.id == trip_stop.id).unwrap();
@@ -116,64 +152,62 @@ impl Maps {
                 })
                 .collect::<Vec<Location>>();
 
-            let res: Response = reqwest::get(format!(
-                "https://maps.googleapis.com/maps/api/directions/json?key={}&origin={}&destination={}&waypoints={}",
-                self.api_key,
-                self.format_position(waypoints[0].latitude, waypoints[0].longitude),
-                self.format_position(waypoints.last().unwrap().latitude, waypoints.last().unwrap().longitude),
-                if max > 1 { waypoints[1..max-1].iter().map(|waypoint| self.format_position(waypoint.latitude, waypoint.longitude)).collect::<Vec<String>>().join("|") } else { "".to_string() }
-            ))
-            .await?;
-
-            if res.status().is_success() {
-                let direction_response = res.json::<DirectionResponse>().await?;
-                let route_response = direction_response.routes.first().unwrap();
-
-                let mut a: i32 = 0;
-                for leg_response in &route_response.legs {
-                    let leg = DirectionLeg {
-                        id: leg_id,
-                        direction_id: direction.id,
-                        distance: leg_response.distance.value,
-                        duration: leg_response.duration.value,
-                        origin_id: trip_stops[a as usize].id,
-                        destination_id: trip_stops[a as usize + 1].id,
-                        sequence: i,
-                    };
-
-                    direction_legs.push(leg);
-
-                    let mut j: i16 = 1;
-                    for step in &leg_response.steps {
-                        let leg_step = LegStep { // TO THINK: call snap to road with the steps ? negative point: no distance/duration
-                            id: step_id,
-                            distance: step.distance.value,
-                            duration: step.duration.value,
-                            leg_id,
-                            sequence: j,
-                            start_lat: step.start_location.lat,
-                            start_lng: step.start_location.lng,
-                            end_lat: step.end_location.lat,
-                            end_lng: step.end_location.lng,
-                        };
-
-                        leg_steps.push(leg_step);
-
-                        j += 1;
-                        step_id += 1;
-                    }
+            let routed_legs: Vec<RoutedLeg> = self
+                .quota
+                .run(|| self.provider.route(mode, &waypoints))
+                .await?;
+            if routed_legs.len() != k - start {
+                return Err(RoutingError::NoRoute);
+            }
 
-                    i += 1;
-                    a += 1;
-                    leg_id += 1;
+            for (a, leg) in routed_legs.into_iter().enumerate() {
+                if let Some(cache) = &self.cache {
+                    cache
+                        .put(&provider, mode, trip_stops[start + a].id, trip_stops[start + a + 1].id, &leg)
+                        .await?;
                 }
+                routed[start + a] = Some(leg);
             }
+        }
+
+        let mut i: i16 = 1;
+        for (a, leg_response) in routed.iter().flatten().enumerate() {
+            let leg_id: i64 = leg_ids.get(&format!("{}:{}", direction.id, i));
+            let leg = DirectionLeg {
+                id: leg_id,
+                direction_id: direction.id,
+                distance: leg_response.distance,
+                duration: leg_response.duration,
+                origin_id: trip_stops[a].id,
+                destination_id: trip_stops[a + 1].id,
+                sequence: i,
+                running_times: None,
+                dwell_times: None,
+            };
+
+            direction_legs.push(leg);
+
+            let mut j: i16 = 1;
+            for step in &leg_response.steps {
+                let leg_step = LegStep { // TO THINK: call snap to road with the steps ? negative point: no distance/duration
+                    id: step_ids.get(&format!("{}:{}", leg_id, j)),
+                    distance: step.distance,
+                    duration: step.duration,
+                    leg_id,
+                    sequence: j,
+                    start_lat: step.start.latitude,
+                    start_lng: step.start.longitude,
+                    end_lat: step.end.latitude,
+                    end_lng: step.end.longitude,
+                    polyline: encode_polyline(&step.points),
+                };
+
+                leg_steps.push(leg_step);
 
-            if trip_stops.len() > max {
-                trip_stops = (&trip_stops)[max - 1..].to_vec();
-            } else {
-                trip_stops = Vec::new();
+                j += 1;
             }
+
+            i += 1;
         }
 
         return Ok((direction_legs, leg_steps));
@@ -181,60 +215,119 @@ impl Maps {
 
     pub async fn get_shape_points_from_shape_stops(
         &self,
-        shape_id: i32,
+        shape_id: i64,
+        mode: TransportMode,
         shape_stops: &Vec<ShapeStop>,
         stops: &Vec<Stop>,
-        start_shape_point_id: i32,
-    ) -> Vec<ShapePoint> {
+        shape_point_ids: &mut StableIds,
+    ) -> Result<Vec<ShapePoint>, RoutingError> {
         let mut shape_points: Vec<ShapePoint> = Vec::new();
 
-        let mut shape_point_id = start_shape_point_id;
-
         let path = shape_stops
             .iter()
             .map(|shape_stop| {
                 let stop = stops.iter().find(|s| s.id == shape_stop.stop_id).unwrap();
-                self.format_position(stop.latitude, stop.longitude)
+                Location {
+                    latitude: stop.latitude,
+                    longitude: stop.longitude,
+                }
             })
-            .collect::<Vec<String>>()
-            .join("|");
+            .collect::<Vec<Location>>();
+
+        let mut snapped_points: Vec<SnappedLocation> = Vec::new();
+        for (start, end, lower, upper) in self.snap_chunks(path.len()) {
+            let chunk: Vec<SnappedLocation> = self
+                .quota
+                .run(|| self.provider.snap(mode, &path[start..end]))
+                .await?;
+
+            // points are attributed to the last input point before them, interpolated ones included,
+            // each chunk keeps the points of its half of the overlaps
+            let mut current: usize = start;
+            for snapped_point in chunk {
+                if let Some(original_index) = snapped_point.original_index {
+                    current = start + original_index;
+                }
+                if current >= lower && current < upper {
+                    snapped_points.push(SnappedLocation {
+                        location: snapped_point.location,
+                        original_index: snapped_point.original_index.map(|i| start + i),
+                    });
+                }
+            }
+        }
 
-        let res: Response = reqwest::get(format!(
-            "https://roads.googleapis.com/v1/snapToRoads?interpolate=true&key={}&path={}",
-            "***REMOVED***", path
-        ))
-        .await
-        .unwrap();
+        let mut j: i16 = 1;
+        for snapped_point in snapped_points {
+            let shape_point: ShapePoint = ShapePoint {
+                id: shape_point_ids.get(&format!("{}:{}", shape_id, j)),
+                shape_id,
+                sequence: j,
+                latitude: snapped_point.location.latitude,
+                longitude: snapped_point.location.longitude,
+                shape_stop_id: if snapped_point.original_index.is_some() {
+                    Some(shape_stops[snapped_point.original_index.unwrap()].id)
+                } else {
+                    None
+                },
+            };
 
-        if res.status().is_success() {
-            let snapped_points: Vec<SnappedPoint> =
-                res.json::<RoadResponse>().await.unwrap().snappedPoints;
+            shape_points.push(shape_point);
+            j += 1;
+        }
 
-            let mut j: i16 = 1;
-            for snapped_point in snapped_points {
-                let shape_point: ShapePoint = ShapePoint {
-                    id: shape_point_id,
-                    shape_id,
-                    sequence: j,
-                    latitude: snapped_point.location.latitude,
-                    longitude: snapped_point.location.longitude,
-                    shape_stop_id: if snapped_point.originalIndex.is_some() {
-                        Some(shape_stops[snapped_point.originalIndex.unwrap() as usize].id)
-                    } else {
-                        None
-                    },
-                };
+        return Ok(shape_points);
+    }
+}
 
-                shape_points.push(shape_point);
-                j += 1;
-                shape_point_id += 1;
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::repository::routing::GoogleProvider;
+
+    // google snaps up to 100 points a request
+    fn maps() -> Maps {
+        Maps {
+            provider: Box::new(GoogleProvider { api_key: String::new() }),
+            cache: None,
+            quota: RequestQuota::new(None, 0, None),
         }
+    }
+
+    #[test]
+    fn empty_paths_need_no_chunk() {
+        assert!(maps().snap_chunks(0).is_empty());
+        assert_eq!(maps().count_snap_requests(0), 0);
+    }
+
+    #[test]
+    fn paths_within_the_limit_take_one_chunk() {
+        assert_eq!(maps().snap_chunks(1), vec![(0, 1, 0, 1)]);
+        assert_eq!(maps().snap_chunks(100), vec![(0, 100, 0, 100)]);
+    }
 
-        return shape_points;
+    #[test]
+    fn one_point_over_the_limit_cuts_in_the_middle_of_the_overlap() {
+        assert_eq!(maps().snap_chunks(101), vec![(0, 100, 0, 95), (90, 101, 95, 101)]);
     }
 
-    fn format_position(&self, lat: f64, lng: f64) -> String {
-        return format!("{},{}", lat, lng);
+    #[test]
+    fn kept_points_cover_the_path_once() {
+        for length in [150, 181, 999, 1000] {
+            let chunks: Vec<(usize, usize, usize, usize)> = maps().snap_chunks(length);
+
+            assert_eq!(chunks.first().unwrap().2, 0);
+            assert_eq!(chunks.last().unwrap().3, length);
+            for (start, end, lower, upper) in &chunks {
+                assert!(end - start <= 100);
+                assert!(start <= lower && lower < upper && upper <= end);
+            }
+            for pair in chunks.windows(2) {
+                assert_eq!(pair[0].3, pair[1].2);
+                // both sides of a cut are snapped with points beyond it
+                assert!(pair[1].0 < pair[0].3 && pair[0].3 < pair[0].1);
+            }
+        }
     }
 }
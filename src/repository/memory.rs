@@ -0,0 +1,420 @@
+use std::{collections::HashMap, io::Error, sync::Arc};
+
+use async_trait::async_trait;
+use chrono::{NaiveDate, NaiveTime};
+use log::info;
+
+use crate::model::{
+    bitfield::Bitfield, direction::Direction, direction_leg::DirectionLeg,
+    information::Information, leg_step::LegStep, line::Line, shape::Shape,
+    shape_point::ShapePoint, shape_stop::ShapeStop, stop::Stop, trip::Trip, trip_stop::TripStop,
+};
+
+use super::{
+    database::RepositoryError,
+    hrdf::{CornerDates, Fahrplan, HRDF},
+    storage::{
+        Bounds, BoardFilter, DirectionRepository, LineRepository, ShapeRepository, StopRepository,
+        TripFilter, TripRepository,
+    },
+};
+
+// days of a bitfield packed in words, bit i is the day at index i of the string
+struct Bitset {
+    words: Vec<u64>,
+}
+
+impl Bitset {
+    fn from_days(days: &str) -> Self {
+        let mut words: Vec<u64> = vec![0; days.len().div_ceil(64)];
+        for (i, day) in days.bytes().enumerate() {
+            if day == b'1' {
+                words[i / 64] |= 1 << (i % 64);
+            }
+        }
+        Bitset { words }
+    }
+
+    fn contains(&self, i: usize) -> bool {
+        return self
+            .words
+            .get(i / 64)
+            .map(|word| word & (1 << (i % 64)) != 0)
+            .unwrap_or(false);
+    }
+}
+
+// squared equirectangular distance, enough to rank the stops of a network
+fn distance_key(latitude: f64, longitude: f64, stop: &Stop) -> f64 {
+    let x: f64 = (stop.longitude - longitude) * latitude.to_radians().cos();
+    let y: f64 = stop.latitude - latitude;
+    x * x + y * y
+}
+
+#[derive(Default)]
+struct Timetable {
+    lines: Vec<Line>,
+    line_index: HashMap<i32, usize>,
+    stops: Vec<Stop>,
+    stop_index: HashMap<i32, usize>,
+    information: Vec<Information>,
+    // keyed by (information_id, bitfield_id), the bitset answers the day tests
+    bitfields: HashMap<(i32, i32), (Bitfield, Bitset)>,
+    // trips of every timetable period, sorted by departure time
+    trips: HashMap<i32, Vec<Trip>>,
+    trip_index: HashMap<i64, (i32, usize)>,
+    trip_stops: HashMap<i64, Vec<TripStop>>,
+    // every stop but the terminus of the trips, sorted by departure time
+    departures: HashMap<i32, Vec<TripStop>>,
+    // every stop but the origin of the trips, sorted by arrival time
+    arrivals: HashMap<i32, Vec<TripStop>>,
+    directions: HashMap<i64, Direction>,
+}
+
+// whole timetable held in ram, read from the hrdf directories at startup
+#[derive(Clone)]
+pub struct MemoryDatabase {
+    timetable: Arc<Timetable>,
+}
+
+impl MemoryDatabase {
+    pub fn load(hrdfs: &[HRDF]) -> Result<MemoryDatabase, Error> {
+        let mut timetable: Timetable = Timetable::default();
+
+        for hrdf in hrdfs {
+            let corner_dates: CornerDates = hrdf.get_corner_dates()?;
+            timetable.information.push(Information {
+                id: hrdf.information_id,
+                start_date: corner_dates.start_date,
+                end_date: corner_dates.end_date,
+            });
+
+            // lines and stops are shared by the periods, the first one read is kept
+            for line in hrdf.get_lines()? {
+                if !timetable.line_index.contains_key(&line.id) {
+                    timetable.line_index.insert(line.id, timetable.lines.len());
+                    timetable.lines.push(line);
+                }
+            }
+
+            let fahrplans: Vec<Fahrplan> = hrdf.get_fahrplans()?;
+
+            let bitfields: Vec<Bitfield> =
+                hrdf.retrieve_bitfields(hrdf.extract_bitfield_ids(&fahrplans))?;
+            for bitfield in bitfields {
+                let bitset: Bitset = Bitset::from_days(&bitfield.days);
+                timetable
+                    .bitfields
+                    .insert((bitfield.information_id, bitfield.id), (bitfield, bitset));
+            }
+
+            for stop in hrdf.retrieve_stops(hrdf.extract_stop_ids(&fahrplans))? {
+                if !timetable.stop_index.contains_key(&stop.id) {
+                    timetable.stop_index.insert(stop.id, timetable.stops.len());
+                    timetable.stops.push(stop);
+                }
+            }
+
+            let (mut trips, directions) = hrdf.to_trips_and_directions(&fahrplans);
+            for direction in directions {
+                timetable.directions.insert(direction.id, direction);
+            }
+
+            trips.sort_by_key(|trip| trip.departure_time);
+            for (i, trip) in trips.iter().enumerate() {
+                timetable.trip_index.insert(trip.id, (hrdf.information_id, i));
+            }
+            timetable.trips.insert(hrdf.information_id, trips);
+
+            let platforms = hrdf.get_platforms()?;
+            for trip_stop in hrdf.to_trip_stops(&fahrplans, &platforms) {
+                timetable
+                    .trip_stops
+                    .entry(trip_stop.trip_id)
+                    .or_default()
+                    .push(trip_stop);
+            }
+
+            info!("Loaded period {} in memory", hrdf.information_id);
+        }
+
+        for trip_stops in timetable.trip_stops.values() {
+            for trip_stop in &trip_stops[..trip_stops.len().saturating_sub(1)] {
+                if trip_stop.departure_time.is_some() {
+                    timetable
+                        .departures
+                        .entry(trip_stop.stop_id)
+                        .or_default()
+                        .push(trip_stop.clone());
+                }
+            }
+            for trip_stop in trip_stops.iter().skip(1) {
+                if trip_stop.arrival_time.is_some() {
+                    timetable
+                        .arrivals
+                        .entry(trip_stop.stop_id)
+                        .or_default()
+                        .push(trip_stop.clone());
+                }
+            }
+        }
+        for departures in timetable.departures.values_mut() {
+            departures.sort_by_key(|trip_stop| trip_stop.departure_time);
+        }
+        for arrivals in timetable.arrivals.values_mut() {
+            arrivals.sort_by_key(|trip_stop| trip_stop.arrival_time);
+        }
+
+        Ok(MemoryDatabase {
+            timetable: Arc::new(timetable),
+        })
+    }
+
+    // first stops of the board whose trips run on the day of the filter, before or past midnight as asked
+    fn runs_on_day(
+        &self,
+        board: &[TripStop],
+        filter: &BoardFilter,
+        time: fn(&TripStop) -> Option<NaiveTime>,
+    ) -> Vec<TripStop> {
+        return board
+            .iter()
+            .filter(|trip_stop| {
+                self.timetable
+                    .trip_index
+                    .get(&trip_stop.trip_id)
+                    .filter(|(information_id, _)| *information_id == filter.information_id)
+                    .map(|(information_id, i)| &self.timetable.trips[information_id][*i])
+                    .filter(|trip| {
+                        time(trip_stop).is_some_and(|time| (time < trip.departure_time) == filter.past_midnight)
+                    })
+                    .and_then(|trip| self.timetable.bitfields.get(&(trip.information_id, trip.bitfield_id)))
+                    .map(|(_, bitset)| bitset.contains(filter.day_index as usize))
+                    .unwrap_or(false)
+            })
+            .take(filter.limit as usize)
+            .cloned()
+            .collect();
+    }
+}
+
+#[async_trait]
+impl LineRepository for MemoryDatabase {
+    async fn get_lines(&self) -> Result<Vec<Line>, RepositoryError> {
+        Ok(self.timetable.lines.clone())
+    }
+
+    async fn get_line(&self, id: i32) -> Result<Line, RepositoryError> {
+        self.timetable
+            .line_index
+            .get(&id)
+            .map(|i| self.timetable.lines[*i].clone())
+            .ok_or(RepositoryError::NotFound)
+    }
+}
+
+#[async_trait]
+impl StopRepository for MemoryDatabase {
+    async fn get_stops(&self) -> Result<Vec<Stop>, RepositoryError> {
+        Ok(self.timetable.stops.clone())
+    }
+
+    async fn get_stop(&self, id: i32) -> Result<Stop, RepositoryError> {
+        self.timetable
+            .stop_index
+            .get(&id)
+            .map(|i| self.timetable.stops[*i].clone())
+            .ok_or(RepositoryError::NotFound)
+    }
+
+    async fn get_stops_within(&self, bounds: Bounds) -> Result<Vec<Stop>, RepositoryError> {
+        Ok(self
+            .timetable
+            .stops
+            .iter()
+            .filter(|stop| {
+                stop.longitude >= bounds.min_longitude
+                    && stop.longitude <= bounds.max_longitude
+                    && stop.latitude >= bounds.min_latitude
+                    && stop.latitude <= bounds.max_latitude
+            })
+            .cloned()
+            .collect())
+    }
+
+    async fn get_nearest_stops(
+        &self,
+        latitude: f64,
+        longitude: f64,
+        limit: i64,
+    ) -> Result<Vec<Stop>, RepositoryError> {
+        let mut stops: Vec<(f64, &Stop)> = self
+            .timetable
+            .stops
+            .iter()
+            .map(|stop| (distance_key(latitude, longitude, stop), stop))
+            .collect();
+        stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        Ok(stops
+            .into_iter()
+            .take(limit as usize)
+            .map(|(_, stop)| stop.clone())
+            .collect())
+    }
+}
+
+#[async_trait]
+impl TripRepository for MemoryDatabase {
+    async fn get_information(&self, date: NaiveDate) -> Result<Information, RepositoryError> {
+        self.timetable
+            .information
+            .iter()
+            .filter(|information| information.start_date <= date && information.end_date >= date)
+            .max_by_key(|information| information.start_date)
+            .cloned()
+            .ok_or(RepositoryError::NotFound)
+    }
+
+    async fn get_information_by_id(&self, id: i32) -> Result<Information, RepositoryError> {
+        self.timetable
+            .information
+            .iter()
+            .find(|information| information.id == id)
+            .cloned()
+            .ok_or(RepositoryError::NotFound)
+    }
+
+    async fn get_bitfield(&self, information_id: i32, id: i32) -> Result<Bitfield, RepositoryError> {
+        self.timetable
+            .bitfields
+            .get(&(information_id, id))
+            .map(|(bitfield, _)| bitfield.clone())
+            .ok_or(RepositoryError::NotFound)
+    }
+
+    async fn get_trips(&self, filter: TripFilter) -> Result<Vec<Trip>, RepositoryError> {
+        let trips: &[Trip] = match self.timetable.trips.get(&filter.information_id) {
+            Some(trips) => trips,
+            None => return Ok(Vec::new()),
+        };
+
+        let start: usize = trips.partition_point(|trip| trip.departure_time < filter.departure_from);
+        let end: usize = trips.partition_point(|trip| trip.departure_time <= filter.departure_until);
+
+        Ok(trips[start..end.max(start)]
+            .iter()
+            .filter(|trip| {
+                let past_midnight: bool = trip.arrival_time < trip.departure_time;
+                (past_midnight == filter.past_midnight && trip.arrival_time >= filter.arrival_from)
+                    || (!filter.past_midnight && past_midnight)
+            })
+            .filter(|trip| {
+                self.timetable
+                    .bitfields
+                    .get(&(trip.information_id, trip.bitfield_id))
+                    .map(|(_, bitset)| bitset.contains(filter.day_index as usize))
+                    .unwrap_or(false)
+            })
+            .cloned()
+            .collect())
+    }
+
+    async fn get_trip(&self, id: i64) -> Result<Trip, RepositoryError> {
+        self.timetable
+            .trip_index
+            .get(&id)
+            .map(|(information_id, i)| self.timetable.trips[information_id][*i].clone())
+            .ok_or(RepositoryError::NotFound)
+    }
+
+    async fn get_trip_stops(&self, trip_id: i64) -> Result<Vec<TripStop>, RepositoryError> {
+        Ok(self
+            .timetable
+            .trip_stops
+            .get(&trip_id)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn get_filtered_trip_stops(&self, filter: TripFilter) -> Result<Vec<TripStop>, RepositoryError> {
+        let trips: Vec<Trip> = self.get_trips(filter).await?;
+
+        Ok(trips
+            .iter()
+            .filter_map(|trip| self.timetable.trip_stops.get(&trip.id))
+            .flatten()
+            .cloned()
+            .collect())
+    }
+
+    async fn get_departures(&self, filter: BoardFilter) -> Result<Vec<TripStop>, RepositoryError> {
+        let departures: &[TripStop] = match self.timetable.departures.get(&filter.stop_id) {
+            Some(departures) => departures,
+            None => return Ok(Vec::new()),
+        };
+
+        let start: usize = departures.partition_point(|trip_stop| trip_stop.departure_time < Some(filter.from));
+
+        Ok(self.runs_on_day(&departures[start..], &filter, |trip_stop| trip_stop.departure_time))
+    }
+
+    async fn get_arrivals(&self, filter: BoardFilter) -> Result<Vec<TripStop>, RepositoryError> {
+        let arrivals: &[TripStop] = match self.timetable.arrivals.get(&filter.stop_id) {
+            Some(arrivals) => arrivals,
+            None => return Ok(Vec::new()),
+        };
+
+        let start: usize = arrivals.partition_point(|trip_stop| trip_stop.arrival_time < Some(filter.from));
+
+        Ok(self.runs_on_day(&arrivals[start..], &filter, |trip_stop| trip_stop.arrival_time))
+    }
+}
+
+// legs and steps come from the maps api, they are not computed when serving from memory
+#[async_trait]
+impl DirectionRepository for MemoryDatabase {
+    async fn get_direction(&self, id: i64) -> Result<Direction, RepositoryError> {
+        self.timetable
+            .directions
+            .get(&id)
+            .cloned()
+            .ok_or(RepositoryError::NotFound)
+    }
+
+    async fn get_direction_legs(&self, _direction_id: i64) -> Result<Vec<DirectionLeg>, RepositoryError> {
+        Ok(Vec::new())
+    }
+
+    async fn get_leg(&self, _id: i64) -> Result<DirectionLeg, RepositoryError> {
+        Err(RepositoryError::NotFound)
+    }
+
+    async fn get_leg_steps(&self, _leg_id: i64) -> Result<Vec<LegStep>, RepositoryError> {
+        Ok(Vec::new())
+    }
+
+    async fn get_directions_legs(&self, _direction_ids: &[i64]) -> Result<Vec<DirectionLeg>, RepositoryError> {
+        Ok(Vec::new())
+    }
+
+    async fn get_legs_steps(&self, _leg_ids: &[i64]) -> Result<Vec<LegStep>, RepositoryError> {
+        Ok(Vec::new())
+    }
+}
+
+// shapes are still unstable and never loaded in memory
+#[async_trait]
+impl ShapeRepository for MemoryDatabase {
+    async fn get_shape(&self, _id: i64) -> Result<Shape, RepositoryError> {
+        Err(RepositoryError::NotFound)
+    }
+
+    async fn get_shape_points(&self, _shape_id: i64) -> Result<Vec<ShapePoint>, RepositoryError> {
+        Ok(Vec::new())
+    }
+
+    async fn get_shape_stops(&self, _shape_id: i64) -> Result<Vec<ShapeStop>, RepositoryError> {
+        Ok(Vec::new())
+    }
+}
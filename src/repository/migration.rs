@@ -0,0 +1,905 @@
+use derive_more::Display;
+use log::info;
+use sqlx::{postgres::PgRow, Postgres, Row, Sqlite, Transaction};
+
+use super::{database::Database, sqlite::SqliteDatabase};
+
+// qualified, connections look up the active dataset schema first
+const MIGRATIONS_TABLE_NAME: &str = "public.schema_migrations";
+
+// arbitrary key for pg_advisory_xact_lock, serializes concurrent migrations
+const MIGRATIONS_LOCK_KEY: i64 = 7_382_019;
+
+#[derive(PartialEq)]
+pub enum MigrationScope {
+    // shared tables, only created in the public schema
+    Global,
+    // timetable tables, created in the public schema and in every dataset schema
+    Timetable,
+}
+
+pub struct Migration {
+    pub version: i32,
+    pub description: &'static str,
+    pub scope: MigrationScope,
+    pub statements: &'static [&'static str],
+    // same change for the sqlite backend, times and dates are stored as iso text
+    pub sqlite_statements: &'static [&'static str],
+}
+
+#[derive(Debug, Display)]
+pub enum MigrationError {
+    #[display(fmt = "database error: {}", _0)]
+    Database(sqlx::Error),
+    #[display(fmt = "migration {} is applied but unknown to this build", _0)]
+    UnknownVersion(i32),
+    #[display(fmt = "migration {} was applied as \"{}\" but is \"{}\" in this build", _0, _1, _2)]
+    DescriptionMismatch(i32, String, String),
+    #[display(fmt = "migration {} is not applied", _0)]
+    Pending(i32),
+}
+
+impl From<sqlx::Error> for MigrationError {
+    fn from(error: sqlx::Error) -> Self {
+        MigrationError::Database(error)
+    }
+}
+
+// ordered list of the schema changes, never edit an applied migration: append a new one
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "initial schema",
+        scope: MigrationScope::Timetable,
+        statements: &[
+            "CREATE TABLE IF NOT EXISTS bitfields (
+                id INTEGER PRIMARY KEY,
+                days VARCHAR(400) NOT NULL
+            )",
+            "CREATE TABLE IF NOT EXISTS lines (
+                id INTEGER PRIMARY KEY,
+                name VARCHAR(10) NOT NULL,
+                color_type VARCHAR(5),
+                color VARCHAR(11)
+            )",
+            "CREATE TABLE IF NOT EXISTS stops (
+                id INTEGER PRIMARY KEY,
+                latitude DOUBLE PRECISION NOT NULL,
+                longitude DOUBLE PRECISION NOT NULL,
+                name VARCHAR(60) NOT NULL
+            )",
+            "CREATE TABLE IF NOT EXISTS shapes (
+                id INTEGER PRIMARY KEY,
+                identifier VARCHAR(1024) NOT NULL
+            )",
+            "CREATE TABLE IF NOT EXISTS trips (
+                id INTEGER PRIMARY KEY,
+                journey_number INTEGER NOT NULL,
+                option_count SMALLINT NOT NULL,
+                shape_id INTEGER,
+                direction_id INTEGER,
+                transport_mode VARCHAR(12) NOT NULL,
+                origin_id INTEGER NOT NULL,
+                destination_id INTEGER NOT NULL,
+                bitfield_id INTEGER NOT NULL,
+                line_id INTEGER NOT NULL,
+                direction VARCHAR(7) NOT NULL,
+                departure_time TIME NOT NULL,
+                arrival_time TIME NOT NULL,
+                CONSTRAINT fk_origin
+                    FOREIGN KEY(origin_id)
+                        REFERENCES stops(id),
+                CONSTRAINT fk_destination
+                    FOREIGN KEY(destination_id)
+                        REFERENCES stops(id),
+                CONSTRAINT fk_bitfield
+                    FOREIGN KEY(bitfield_id)
+                        REFERENCES bitfields(id),
+                CONSTRAINT fk_line
+                    FOREIGN KEY(line_id)
+                        REFERENCES lines(id)
+            )",
+            "CREATE TABLE IF NOT EXISTS trip_stops (
+                id INTEGER PRIMARY KEY,
+                stop_id INTEGER NOT NULL,
+                trip_id INTEGER NOT NULL,
+                sequence SMALLINT NOT NULL,
+                arrival_time TIME,
+                departure_time TIME,
+                CONSTRAINT fk_trip
+                    FOREIGN KEY(trip_id)
+                        REFERENCES trips(id)
+            )",
+            "CREATE TABLE IF NOT EXISTS information (
+                id SERIAL PRIMARY KEY,
+                start_date DATE NOT NULL,
+                end_date DATE NOT NULL
+            )",
+            "CREATE TABLE IF NOT EXISTS shape_stops (
+                id INTEGER PRIMARY KEY,
+                shape_id INTEGER NOT NULL,
+                stop_id INTEGER NOT NULL,
+                sequence SMALLINT NOT NULL,
+                CONSTRAINT fk_stop
+                    FOREIGN KEY(stop_id)
+                        REFERENCES stops(id),
+                CONSTRAINT fk_shape
+                    FOREIGN KEY(shape_id)
+                        REFERENCES shapes(id)
+            )",
+            "CREATE TABLE IF NOT EXISTS shape_points (
+                id INTEGER PRIMARY KEY,
+                shape_id INTEGER NOT NULL,
+                sequence SMALLINT NOT NULL,
+                latitude DOUBLE PRECISION NOT NULL,
+                longitude DOUBLE PRECISION NOT NULL,
+                shape_stop_id INTEGER,
+                CONSTRAINT fk_shape
+                    FOREIGN KEY(shape_id)
+                        REFERENCES shapes(id)
+            )",
+            "CREATE TABLE IF NOT EXISTS directions (
+                id INTEGER PRIMARY KEY,
+                identifier VARCHAR(1024) NOT NULL,
+                origin_id INTEGER NOT NULL,
+                destination_id INTEGER NOT NULL,
+                CONSTRAINT fk_origin
+                    FOREIGN KEY(origin_id)
+                        REFERENCES stops(id),
+                CONSTRAINT fk_destination
+                    FOREIGN KEY(destination_id)
+                        REFERENCES stops(id)
+            )",
+            "CREATE TABLE IF NOT EXISTS direction_legs (
+                id INTEGER PRIMARY KEY,
+                direction_id INTEGER NOT NULL,
+                distance INTEGER NOT NULL,
+                duration INTEGER NOT NULL,
+                sequence SMALLINT NOT NULL,
+                origin_id INTEGER NOT NULL,
+                destination_id INTEGER NOT NULL,
+                CONSTRAINT fk_origin
+                    FOREIGN KEY(origin_id)
+                        REFERENCES stops(id),
+                CONSTRAINT fk_destination
+                    FOREIGN KEY(destination_id)
+                        REFERENCES stops(id),
+                CONSTRAINT fk_direction
+                    FOREIGN KEY(direction_id)
+                        REFERENCES directions(id)
+            )",
+            "CREATE TABLE IF NOT EXISTS leg_steps (
+                id INTEGER PRIMARY KEY,
+                leg_id INTEGER NOT NULL,
+                distance INTEGER NOT NULL,
+                duration INTEGER NOT NULL,
+                sequence SMALLINT NOT NULL,
+                start_lat DOUBLE PRECISION NOT NULL,
+                start_lng DOUBLE PRECISION NOT NULL,
+                end_lat DOUBLE PRECISION NOT NULL,
+                end_lng DOUBLE PRECISION NOT NULL,
+                CONSTRAINT fk_leg
+                    FOREIGN KEY(leg_id)
+                        REFERENCES direction_legs(id)
+            )",
+        ],
+        sqlite_statements: &[],
+    },
+    Migration {
+        version: 2,
+        description: "datasets",
+        scope: MigrationScope::Global,
+        statements: &[
+            "CREATE TABLE IF NOT EXISTS datasets (
+                id SERIAL PRIMARY KEY,
+                status VARCHAR(8) NOT NULL,
+                created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                activated_at TIMESTAMPTZ
+            )",
+        ],
+        sqlite_statements: &[],
+    },
+    Migration {
+        version: 3,
+        description: "timetable periods",
+        scope: MigrationScope::Timetable,
+        statements: &[
+            "ALTER TABLE trips DROP CONSTRAINT IF EXISTS fk_bitfield",
+            "ALTER TABLE bitfields ADD COLUMN information_id INTEGER NOT NULL DEFAULT 1",
+            "ALTER TABLE bitfields ALTER COLUMN information_id DROP DEFAULT",
+            "ALTER TABLE bitfields DROP CONSTRAINT bitfields_pkey",
+            "ALTER TABLE bitfields ADD PRIMARY KEY (information_id, id)",
+            "ALTER TABLE trips ADD COLUMN information_id INTEGER NOT NULL DEFAULT 1",
+            "ALTER TABLE trips ALTER COLUMN information_id DROP DEFAULT",
+            "ALTER TABLE trips ADD CONSTRAINT fk_bitfield
+                FOREIGN KEY(information_id, bitfield_id)
+                    REFERENCES bitfields(information_id, id)",
+            "CREATE INDEX trips_information_departure ON trips (information_id, departure_time)",
+        ],
+        sqlite_statements: &[],
+    },
+    Migration {
+        version: 4,
+        description: "stable ids",
+        scope: MigrationScope::Timetable,
+        statements: &[
+            "ALTER TABLE shapes ALTER COLUMN id TYPE BIGINT",
+            "ALTER TABLE directions ALTER COLUMN id TYPE BIGINT",
+            "ALTER TABLE trips
+                ALTER COLUMN id TYPE BIGINT,
+                ALTER COLUMN shape_id TYPE BIGINT,
+                ALTER COLUMN direction_id TYPE BIGINT",
+            "ALTER TABLE trip_stops
+                ALTER COLUMN id TYPE BIGINT,
+                ALTER COLUMN trip_id TYPE BIGINT",
+            "ALTER TABLE shape_stops
+                ALTER COLUMN id TYPE BIGINT,
+                ALTER COLUMN shape_id TYPE BIGINT",
+            "ALTER TABLE shape_points
+                ALTER COLUMN id TYPE BIGINT,
+                ALTER COLUMN shape_id TYPE BIGINT,
+                ALTER COLUMN shape_stop_id TYPE BIGINT",
+            "ALTER TABLE direction_legs
+                ALTER COLUMN id TYPE BIGINT,
+                ALTER COLUMN direction_id TYPE BIGINT",
+            "ALTER TABLE leg_steps
+                ALTER COLUMN id TYPE BIGINT,
+                ALTER COLUMN leg_id TYPE BIGINT",
+        ],
+        // the sqlite backend starts with the timetable tables as they are at this version
+        sqlite_statements: &[
+            "CREATE TABLE IF NOT EXISTS bitfields (
+                id INTEGER NOT NULL,
+                information_id INTEGER NOT NULL,
+                days TEXT NOT NULL,
+                PRIMARY KEY (information_id, id)
+            )",
+            "CREATE TABLE IF NOT EXISTS lines (
+                id INTEGER PRIMARY KEY,
+                name TEXT NOT NULL,
+                color_type TEXT,
+                color TEXT
+            )",
+            "CREATE TABLE IF NOT EXISTS stops (
+                id INTEGER PRIMARY KEY,
+                latitude REAL NOT NULL,
+                longitude REAL NOT NULL,
+                name TEXT NOT NULL
+            )",
+            "CREATE TABLE IF NOT EXISTS shapes (
+                id INTEGER PRIMARY KEY,
+                identifier TEXT NOT NULL
+            )",
+            "CREATE TABLE IF NOT EXISTS trips (
+                id INTEGER PRIMARY KEY,
+                journey_number INTEGER NOT NULL,
+                option_count INTEGER NOT NULL,
+                shape_id INTEGER,
+                direction_id INTEGER,
+                transport_mode TEXT NOT NULL,
+                origin_id INTEGER NOT NULL REFERENCES stops(id),
+                destination_id INTEGER NOT NULL REFERENCES stops(id),
+                information_id INTEGER NOT NULL,
+                bitfield_id INTEGER NOT NULL,
+                line_id INTEGER NOT NULL REFERENCES lines(id),
+                direction TEXT NOT NULL,
+                departure_time TEXT NOT NULL,
+                arrival_time TEXT NOT NULL,
+                FOREIGN KEY(information_id, bitfield_id) REFERENCES bitfields(information_id, id)
+            )",
+            "CREATE INDEX IF NOT EXISTS trips_information_departure ON trips (information_id, departure_time)",
+            "CREATE TABLE IF NOT EXISTS trip_stops (
+                id INTEGER PRIMARY KEY,
+                stop_id INTEGER NOT NULL,
+                trip_id INTEGER NOT NULL REFERENCES trips(id),
+                sequence INTEGER NOT NULL,
+                arrival_time TEXT,
+                departure_time TEXT
+            )",
+            "CREATE TABLE IF NOT EXISTS information (
+                id INTEGER PRIMARY KEY,
+                start_date TEXT NOT NULL,
+                end_date TEXT NOT NULL
+            )",
+            "CREATE TABLE IF NOT EXISTS shape_stops (
+                id INTEGER PRIMARY KEY,
+                shape_id INTEGER NOT NULL REFERENCES shapes(id),
+                stop_id INTEGER NOT NULL REFERENCES stops(id),
+                sequence INTEGER NOT NULL
+            )",
+            "CREATE TABLE IF NOT EXISTS shape_points (
+                id INTEGER PRIMARY KEY,
+                shape_id INTEGER NOT NULL REFERENCES shapes(id),
+                sequence INTEGER NOT NULL,
+                latitude REAL NOT NULL,
+                longitude REAL NOT NULL,
+                shape_stop_id INTEGER
+            )",
+            "CREATE TABLE IF NOT EXISTS directions (
+                id INTEGER PRIMARY KEY,
+                identifier TEXT NOT NULL,
+                origin_id INTEGER NOT NULL REFERENCES stops(id),
+                destination_id INTEGER NOT NULL REFERENCES stops(id)
+            )",
+            "CREATE TABLE IF NOT EXISTS direction_legs (
+                id INTEGER PRIMARY KEY,
+                direction_id INTEGER NOT NULL REFERENCES directions(id),
+                distance INTEGER NOT NULL,
+                duration INTEGER NOT NULL,
+                sequence INTEGER NOT NULL,
+                origin_id INTEGER NOT NULL REFERENCES stops(id),
+                destination_id INTEGER NOT NULL REFERENCES stops(id)
+            )",
+            "CREATE TABLE IF NOT EXISTS leg_steps (
+                id INTEGER PRIMARY KEY,
+                leg_id INTEGER NOT NULL REFERENCES direction_legs(id),
+                distance INTEGER NOT NULL,
+                duration INTEGER NOT NULL,
+                sequence INTEGER NOT NULL,
+                start_lat REAL NOT NULL,
+                start_lng REAL NOT NULL,
+                end_lat REAL NOT NULL,
+                end_lng REAL NOT NULL
+            )",
+        ],
+    },
+    Migration {
+        version: 5,
+        description: "import reports",
+        scope: MigrationScope::Global,
+        statements: &[
+            "CREATE TABLE IF NOT EXISTS import_reports (
+                id SERIAL PRIMARY KEY,
+                created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                table_name VARCHAR(32) NOT NULL,
+                added INTEGER NOT NULL,
+                changed INTEGER NOT NULL,
+                removed INTEGER NOT NULL
+            )",
+        ],
+        sqlite_statements: &[
+            "CREATE TABLE IF NOT EXISTS import_reports (
+                id INTEGER PRIMARY KEY,
+                created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                table_name TEXT NOT NULL,
+                added INTEGER NOT NULL,
+                changed INTEGER NOT NULL,
+                removed INTEGER NOT NULL
+            )",
+        ],
+    },
+    Migration {
+        version: 6,
+        description: "bit string bitfields",
+        scope: MigrationScope::Timetable,
+        statements: &[
+            "ALTER TABLE bitfields ALTER COLUMN days TYPE BIT VARYING(400) USING days::BIT VARYING(400)",
+            "CREATE INDEX trips_information_bitfield ON trips (information_id, bitfield_id)",
+        ],
+        sqlite_statements: &["CREATE INDEX IF NOT EXISTS trips_information_bitfield ON trips (information_id, bitfield_id)"],
+    },
+    Migration {
+        version: 7,
+        description: "step polylines",
+        scope: MigrationScope::Timetable,
+        statements: &[
+            "ALTER TABLE leg_steps ADD COLUMN polyline TEXT NOT NULL DEFAULT ''",
+            // the geometry is generated again from the polyline when postgis is enabled
+            "ALTER TABLE leg_steps DROP COLUMN IF EXISTS geom",
+        ],
+        sqlite_statements: &["ALTER TABLE leg_steps ADD COLUMN polyline TEXT NOT NULL DEFAULT ''"],
+    },
+    Migration {
+        version: 8,
+        description: "leg calibration",
+        scope: MigrationScope::Timetable,
+        statements: &[
+            "ALTER TABLE direction_legs
+                ADD COLUMN running_times TEXT NOT NULL DEFAULT '',
+                ADD COLUMN dwell_times TEXT NOT NULL DEFAULT ''",
+        ],
+        sqlite_statements: &[
+            "ALTER TABLE direction_legs ADD COLUMN running_times TEXT NOT NULL DEFAULT ''",
+            "ALTER TABLE direction_legs ADD COLUMN dwell_times TEXT NOT NULL DEFAULT ''",
+        ],
+    },
+    Migration {
+        version: 9,
+        description: "stop departures",
+        scope: MigrationScope::Timetable,
+        statements: &[
+            "ALTER TABLE trip_stops ADD COLUMN platform TEXT",
+            "CREATE INDEX trip_stops_stop_departure ON trip_stops (stop_id, departure_time)",
+            "CREATE INDEX trip_stops_trip_sequence ON trip_stops (trip_id, sequence)",
+        ],
+        sqlite_statements: &[
+            "ALTER TABLE trip_stops ADD COLUMN platform TEXT",
+            "CREATE INDEX IF NOT EXISTS trip_stops_stop_departure ON trip_stops (stop_id, departure_time)",
+            "CREATE INDEX IF NOT EXISTS trip_stops_trip_sequence ON trip_stops (trip_id, sequence)",
+        ],
+    },
+    Migration {
+        version: 10,
+        description: "typed band times",
+        scope: MigrationScope::Timetable,
+        statements: &[
+            "ALTER TABLE direction_legs
+                ALTER COLUMN running_times DROP DEFAULT,
+                ALTER COLUMN running_times DROP NOT NULL,
+                ALTER COLUMN running_times TYPE INTEGER[]
+                    USING string_to_array(NULLIF(running_times, ''), ',')::INTEGER[],
+                ALTER COLUMN dwell_times DROP DEFAULT,
+                ALTER COLUMN dwell_times DROP NOT NULL,
+                ALTER COLUMN dwell_times TYPE INTEGER[]
+                    USING string_to_array(NULLIF(dwell_times, ''), ',')::INTEGER[]",
+        ],
+        // columns cannot change type in sqlite, they are replaced by json ones
+        sqlite_statements: &[
+            "ALTER TABLE direction_legs ADD COLUMN running_times_json TEXT",
+            "UPDATE direction_legs SET running_times_json = '[' || running_times || ']' WHERE running_times <> ''",
+            "ALTER TABLE direction_legs DROP COLUMN running_times",
+            "ALTER TABLE direction_legs RENAME COLUMN running_times_json TO running_times",
+            "ALTER TABLE direction_legs ADD COLUMN dwell_times_json TEXT",
+            "UPDATE direction_legs SET dwell_times_json = '[' || dwell_times || ']' WHERE dwell_times <> ''",
+            "ALTER TABLE direction_legs DROP COLUMN dwell_times",
+            "ALTER TABLE direction_legs RENAME COLUMN dwell_times_json TO dwell_times",
+        ],
+    },
+];
+
+// foreign key of a timetable table, the columns referencing the parent's
+pub struct ForeignKey {
+    pub columns: &'static str,
+    pub parent: &'static str,
+    pub parent_columns: &'static str,
+}
+
+pub struct TimetableTable {
+    pub name: &'static str,
+    pub foreign_keys: &'static [ForeignKey],
+}
+
+// timetable tables as the migrations leave them, parents before children
+pub const TIMETABLE_TABLES: &[TimetableTable] = &[
+    TimetableTable {
+        name: "bitfields",
+        foreign_keys: &[],
+    },
+    TimetableTable {
+        name: "lines",
+        foreign_keys: &[],
+    },
+    TimetableTable {
+        name: "stops",
+        foreign_keys: &[],
+    },
+    TimetableTable {
+        name: "information",
+        foreign_keys: &[],
+    },
+    TimetableTable {
+        name: "shapes",
+        foreign_keys: &[],
+    },
+    TimetableTable {
+        name: "trips",
+        foreign_keys: &[
+            ForeignKey {
+                columns: "origin_id",
+                parent: "stops",
+                parent_columns: "id",
+            },
+            ForeignKey {
+                columns: "destination_id",
+                parent: "stops",
+                parent_columns: "id",
+            },
+            ForeignKey {
+                columns: "information_id,bitfield_id",
+                parent: "bitfields",
+                parent_columns: "information_id,id",
+            },
+            ForeignKey {
+                columns: "line_id",
+                parent: "lines",
+                parent_columns: "id",
+            },
+        ],
+    },
+    TimetableTable {
+        name: "trip_stops",
+        foreign_keys: &[ForeignKey {
+            columns: "trip_id",
+            parent: "trips",
+            parent_columns: "id",
+        }],
+    },
+    TimetableTable {
+        name: "shape_stops",
+        foreign_keys: &[
+            ForeignKey {
+                columns: "stop_id",
+                parent: "stops",
+                parent_columns: "id",
+            },
+            ForeignKey {
+                columns: "shape_id",
+                parent: "shapes",
+                parent_columns: "id",
+            },
+        ],
+    },
+    TimetableTable {
+        name: "shape_points",
+        foreign_keys: &[ForeignKey {
+            columns: "shape_id",
+            parent: "shapes",
+            parent_columns: "id",
+        }],
+    },
+    TimetableTable {
+        name: "directions",
+        foreign_keys: &[
+            ForeignKey {
+                columns: "origin_id",
+                parent: "stops",
+                parent_columns: "id",
+            },
+            ForeignKey {
+                columns: "destination_id",
+                parent: "stops",
+                parent_columns: "id",
+            },
+        ],
+    },
+    TimetableTable {
+        name: "direction_legs",
+        foreign_keys: &[
+            ForeignKey {
+                columns: "origin_id",
+                parent: "stops",
+                parent_columns: "id",
+            },
+            ForeignKey {
+                columns: "destination_id",
+                parent: "stops",
+                parent_columns: "id",
+            },
+            ForeignKey {
+                columns: "direction_id",
+                parent: "directions",
+                parent_columns: "id",
+            },
+        ],
+    },
+    TimetableTable {
+        name: "leg_steps",
+        foreign_keys: &[ForeignKey {
+            columns: "leg_id",
+            parent: "direction_legs",
+            parent_columns: "id",
+        }],
+    },
+];
+
+impl TimetableTable {
+    pub fn find(name: &str) -> Option<&'static TimetableTable> {
+        return TIMETABLE_TABLES.iter().find(|table| table.name == name);
+    }
+
+    // true when the table references one of the tables, directly or through its parents
+    pub fn depends_on(&self, names: &[&str]) -> bool {
+        return self.foreign_keys.iter().any(|foreign_key| {
+            names.contains(&foreign_key.parent)
+                || TimetableTable::find(foreign_key.parent)
+                    .is_some_and(|parent| parent.depends_on(names))
+        });
+    }
+}
+
+// optional postgis geometries, generated from the coordinates so imports fill them as they insert,
+// kept out of the versioned migrations so that databases without the extension still pass the check
+const GEOMETRY_STATEMENTS: &[&str] = &[
+    "ALTER TABLE stops ADD COLUMN IF NOT EXISTS geom public.geometry(Point, 4326)
+        GENERATED ALWAYS AS (public.ST_SetSRID(public.ST_MakePoint(longitude, latitude), 4326)) STORED",
+    "CREATE INDEX IF NOT EXISTS stops_geom ON stops USING GIST (geom)",
+    "ALTER TABLE shape_points ADD COLUMN IF NOT EXISTS geom public.geometry(Point, 4326)
+        GENERATED ALWAYS AS (public.ST_SetSRID(public.ST_MakePoint(longitude, latitude), 4326)) STORED",
+    "CREATE INDEX IF NOT EXISTS shape_points_geom ON shape_points USING GIST (geom)",
+    "ALTER TABLE leg_steps ADD COLUMN IF NOT EXISTS geom public.geometry(LineString, 4326)
+        GENERATED ALWAYS AS (public.ST_SetSRID(CASE WHEN polyline = '' THEN public.ST_MakeLine(
+            public.ST_MakePoint(start_lng, start_lat),
+            public.ST_MakePoint(end_lng, end_lat)
+        ) ELSE public.ST_LineFromEncodedPolyline(polyline, 5) END, 4326)) STORED",
+    "CREATE INDEX IF NOT EXISTS leg_steps_geom ON leg_steps USING GIST (geom)",
+];
+
+pub struct Migrator<'a> {
+    pub database: &'a Database,
+}
+
+impl Migrator<'_> {
+    async fn create_migrations_table(&self) -> Result<(), MigrationError> {
+        self.database
+            .query(
+                format!(
+                    "CREATE TABLE IF NOT EXISTS {} (
+            version INTEGER PRIMARY KEY,
+            description VARCHAR(255) NOT NULL,
+            applied_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+        )",
+                    MIGRATIONS_TABLE_NAME
+                )
+                .as_str(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn applied_migrations(&self) -> Result<Vec<(i32, String)>, MigrationError> {
+        let rows: Vec<PgRow> = sqlx::query(
+            format!(
+                "SELECT version, description FROM {} ORDER BY version",
+                MIGRATIONS_TABLE_NAME
+            )
+            .as_str(),
+        )
+        .fetch_all(self.database.pool())
+        .await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| (row.get::<i32, _>("version"), row.get::<String, _>("description")))
+            .collect())
+    }
+
+    async fn apply(
+        transaction: &mut Transaction<'_, Postgres>,
+        statements: &[&str],
+        schema: &str,
+    ) -> Result<(), MigrationError> {
+        sqlx::query(format!("SET LOCAL search_path TO {}", schema).as_str())
+            .execute(&mut **transaction)
+            .await?;
+
+        for statement in statements {
+            sqlx::query(statement).execute(&mut **transaction).await?;
+        }
+
+        Ok(())
+    }
+
+    // schemas of the datasets that are kept, empty until the datasets table exists
+    async fn dataset_schemas(
+        transaction: &mut Transaction<'_, Postgres>,
+    ) -> Result<Vec<String>, MigrationError> {
+        let exists: bool = sqlx::query_scalar("SELECT to_regclass('public.datasets') IS NOT NULL")
+            .fetch_one(&mut **transaction)
+            .await?;
+        if !exists {
+            return Ok(Vec::new());
+        }
+
+        let schemas: Vec<String> = sqlx::query_scalar(
+            "SELECT 'dataset_' || id FROM public.datasets WHERE status IN ('Loading','Ready','Active') ORDER BY id",
+        )
+        .fetch_all(&mut **transaction)
+        .await?;
+
+        Ok(schemas)
+    }
+
+    // geometries are enabled once the public timetable has them
+    async fn geometries_enabled(
+        transaction: &mut Transaction<'_, Postgres>,
+    ) -> Result<bool, MigrationError> {
+        let enabled: bool = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM information_schema.columns WHERE table_schema='public' AND table_name='stops' AND column_name='geom')",
+        )
+        .fetch_one(&mut **transaction)
+        .await?;
+
+        Ok(enabled)
+    }
+
+    // install postgis and add the geometry columns to every timetable schema, idempotent
+    pub async fn enable_geometries(&self) -> Result<(), MigrationError> {
+        let mut transaction: Transaction<'_, Postgres> = self.database.pool().begin().await?;
+
+        sqlx::query("SELECT pg_advisory_xact_lock($1)")
+            .bind(MIGRATIONS_LOCK_KEY)
+            .execute(&mut *transaction)
+            .await?;
+        sqlx::query("CREATE EXTENSION IF NOT EXISTS postgis SCHEMA public")
+            .execute(&mut *transaction)
+            .await?;
+
+        let mut schemas: Vec<String> = vec!["public".to_string()];
+        schemas.extend(Self::dataset_schemas(&mut transaction).await?);
+        for schema in schemas {
+            Self::apply(&mut transaction, GEOMETRY_STATEMENTS, &schema).await?;
+        }
+
+        transaction.commit().await?;
+        info!("Enabled postgis geometries");
+
+        Ok(())
+    }
+
+    // create a schema holding the current timetable tables, used by dataset imports
+    pub async fn create_timetable_schema(&self, schema: &str) -> Result<(), MigrationError> {
+        let mut transaction: Transaction<'_, Postgres> = self.database.pool().begin().await?;
+
+        sqlx::query(format!("CREATE SCHEMA {}", schema).as_str())
+            .execute(&mut *transaction)
+            .await?;
+
+        for migration in MIGRATIONS
+            .iter()
+            .filter(|m| m.scope == MigrationScope::Timetable)
+        {
+            Self::apply(&mut transaction, migration.statements, schema).await?;
+        }
+        if Self::geometries_enabled(&mut transaction).await? {
+            Self::apply(&mut transaction, GEOMETRY_STATEMENTS, schema).await?;
+        }
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    // apply every pending migration in its own transaction, returns the applied versions
+    pub async fn migrate(&self) -> Result<Vec<i32>, MigrationError> {
+        self.create_migrations_table().await?;
+
+        let mut applied_versions: Vec<i32> = Vec::new();
+
+        for migration in MIGRATIONS {
+            let mut transaction: Transaction<'_, Postgres> = self.database.pool().begin().await?;
+
+            sqlx::query("SELECT pg_advisory_xact_lock($1)")
+                .bind(MIGRATIONS_LOCK_KEY)
+                .execute(&mut *transaction)
+                .await?;
+
+            let applied: Option<PgRow> = sqlx::query(
+                format!("SELECT version FROM {} WHERE version=$1", MIGRATIONS_TABLE_NAME).as_str(),
+            )
+            .bind(migration.version)
+            .fetch_optional(&mut *transaction)
+            .await?;
+
+            if applied.is_some() {
+                transaction.rollback().await?;
+                continue;
+            }
+
+            info!(
+                "Applying migration {}: {}",
+                migration.version, migration.description
+            );
+            let schemas: Vec<String> = match migration.scope {
+                MigrationScope::Global => vec!["public".to_string()],
+                MigrationScope::Timetable => {
+                    let mut schemas: Vec<String> = vec!["public".to_string()];
+                    schemas.extend(Self::dataset_schemas(&mut transaction).await?);
+                    schemas
+                }
+            };
+            for schema in schemas {
+                Self::apply(&mut transaction, migration.statements, &schema).await?;
+            }
+            sqlx::query("SET LOCAL search_path TO public")
+                .execute(&mut *transaction)
+                .await?;
+
+            sqlx::query(
+                format!(
+                    "INSERT INTO {} (version,description) VALUES ($1,$2)",
+                    MIGRATIONS_TABLE_NAME
+                )
+                .as_str(),
+            )
+            .bind(migration.version)
+            .bind(migration.description)
+            .execute(&mut *transaction)
+            .await?;
+
+            transaction.commit().await?;
+            applied_versions.push(migration.version);
+        }
+
+        Ok(applied_versions)
+    }
+
+    // verify that the database schema is exactly the one this build expects
+    pub async fn check(&self) -> Result<(), MigrationError> {
+        self.create_migrations_table().await?;
+
+        let applied: Vec<(i32, String)> = self.applied_migrations().await?;
+
+        for (version, description) in &applied {
+            let migration: Option<&Migration> = MIGRATIONS.iter().find(|m| m.version == *version);
+            match migration {
+                None => return Err(MigrationError::UnknownVersion(*version)),
+                Some(migration) if migration.description != description => {
+                    return Err(MigrationError::DescriptionMismatch(
+                        *version,
+                        description.to_string(),
+                        migration.description.to_string(),
+                    ))
+                }
+                _ => {}
+            }
+        }
+
+        for migration in MIGRATIONS {
+            if !applied.iter().any(|(version, _)| *version == migration.version) {
+                return Err(MigrationError::Pending(migration.version));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// versions a sqlite file with its user_version, sqlite files have no datasets
+pub struct SqliteMigrator<'a> {
+    pub database: &'a SqliteDatabase,
+}
+
+impl SqliteMigrator<'_> {
+    async fn version(&self) -> Result<i32, MigrationError> {
+        let version: i32 = sqlx::query_scalar("PRAGMA user_version")
+            .fetch_one(self.database.pool())
+            .await?;
+
+        Ok(version)
+    }
+
+    async fn set_version(
+        transaction: &mut Transaction<'_, Sqlite>,
+        version: i32,
+    ) -> Result<(), MigrationError> {
+        // pragmas take no bound parameters
+        sqlx::query(format!("PRAGMA user_version = {}", version).as_str())
+            .execute(&mut **transaction)
+            .await?;
+
+        Ok(())
+    }
+
+    // apply every pending migration in its own transaction, returns the applied versions
+    pub async fn migrate(&self) -> Result<Vec<i32>, MigrationError> {
+        let version: i32 = self.version().await?;
+        if let Some(latest) = MIGRATIONS.last() {
+            if version > latest.version {
+                return Err(MigrationError::UnknownVersion(version));
+            }
+        }
+
+        let mut applied_versions: Vec<i32> = Vec::new();
+
+        for migration in MIGRATIONS.iter().filter(|m| m.version > version) {
+            let mut transaction: Transaction<'_, Sqlite> = self.database.pool().begin().await?;
+
+            for statement in migration.sqlite_statements {
+                sqlx::query(statement).execute(&mut *transaction).await?;
+            }
+            Self::set_version(&mut transaction, migration.version).await?;
+
+            transaction.commit().await?;
+            if !migration.sqlite_statements.is_empty() {
+                info!(
+                    "Applied sqlite migration {}: {}",
+                    migration.version, migration.description
+                );
+                applied_versions.push(migration.version);
+            }
+        }
+
+        Ok(applied_versions)
+    }
+}
@@ -1,4 +1,18 @@
+pub mod calibration;
 pub mod database;
+pub mod dataset;
 pub mod gtfs;
 pub mod hrdf;
-pub mod maps;
\ No newline at end of file
+pub mod identifier;
+pub mod import;
+pub mod maps;
+pub mod memory;
+pub mod migration;
+pub mod osm;
+pub mod planner;
+pub mod postgres;
+pub mod quota;
+pub mod routing;
+pub mod routing_cache;
+pub mod sqlite;
+pub mod storage;
\ No newline at end of file
@@ -0,0 +1,904 @@
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap, HashSet},
+    fs::File,
+    io::{BufReader, Error, ErrorKind, Read},
+    path::PathBuf,
+    sync::OnceLock,
+};
+
+use async_trait::async_trait;
+use flate2::read::ZlibDecoder;
+use log::info;
+
+use crate::model::line::TransportMode;
+
+use super::routing::{
+    haversine, Location, RoutedLeg, RoutedStep, RoutingError, RoutingProvider, SnappedLocation,
+    StraightLineProvider,
+};
+
+// size of the cells of the stop snapping index, in degrees
+const CELL_SIZE: f64 = 0.005;
+
+// stops further than this from any way of their network are not routed, in meters
+const MAX_SNAP_DISTANCE: f64 = 500.0;
+
+/*
+osm pbf files
+file: repeated (4 bytes big endian length, BlobHeader, Blob)
+BlobHeader: 1 type ("OSMHeader"|"OSMData") 3 datasize
+Blob: 1 raw 3 zlib_data
+PrimitiveBlock: 1 stringtable 2 primitivegroup 17 granularity 19 lat_offset 20 lon_offset
+PrimitiveGroup: 1 nodes 2 dense 3 ways
+DenseNodes: 1 id 8 lat 9 lon (packed, delta coded)
+Way: 1 id 2 keys 3 vals 8 refs (delta coded)
+*/
+
+// protobuf wire format, only what the osm blocks use
+struct Message<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+enum Field<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+}
+
+impl<'a> Message<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Message { buf, pos: 0 }
+    }
+
+    fn varint(&mut self) -> Result<u64, Error> {
+        let mut value: u64 = 0;
+        let mut shift: u32 = 0;
+        loop {
+            let byte: u8 = *self
+                .buf
+                .get(self.pos)
+                .ok_or(Error::new(ErrorKind::InvalidData, "truncated varint"))?;
+            self.pos += 1;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+            shift += 7;
+            if shift >= 64 {
+                return Err(Error::new(ErrorKind::InvalidData, "varint too long"));
+            }
+        }
+    }
+
+    fn next_field(&mut self) -> Result<Option<(u64, Field<'a>)>, Error> {
+        if self.pos >= self.buf.len() {
+            return Ok(None);
+        }
+
+        let key: u64 = self.varint()?;
+        let field: Field = match key & 0x7 {
+            0 => Field::Varint(self.varint()?),
+            1 => {
+                self.pos += 8;
+                Field::Varint(0)
+            }
+            2 => {
+                let length: usize = self.varint()? as usize;
+                let bytes: &[u8] = self
+                    .buf
+                    .get(self.pos..self.pos + length)
+                    .ok_or(Error::new(ErrorKind::InvalidData, "truncated field"))?;
+                self.pos += length;
+                Field::Bytes(bytes)
+            }
+            5 => {
+                self.pos += 4;
+                Field::Varint(0)
+            }
+            wire_type => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("unsupported wire type {}", wire_type),
+                ))
+            }
+        };
+
+        Ok(Some((key >> 3, field)))
+    }
+}
+
+fn zigzag(value: u64) -> i64 {
+    (value >> 1) as i64 ^ -((value & 1) as i64)
+}
+
+// repeated integer field, packed or not
+fn push_varints(field: Field, values: &mut Vec<u64>) -> Result<(), Error> {
+    match field {
+        Field::Varint(value) => values.push(value),
+        Field::Bytes(bytes) => {
+            let mut packed: Message = Message::new(bytes);
+            while packed.pos < bytes.len() {
+                values.push(packed.varint()?);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn delta_decode(values: &[u64]) -> Vec<i64> {
+    let mut current: i64 = 0;
+    values
+        .iter()
+        .map(|value| {
+            current += zigzag(*value);
+            current
+        })
+        .collect()
+}
+
+fn bytes<'a>(field: Field<'a>) -> &'a [u8] {
+    match field {
+        Field::Bytes(bytes) => bytes,
+        Field::Varint(_) => &[],
+    }
+}
+
+fn varint(field: Field) -> u64 {
+    match field {
+        Field::Varint(value) => value,
+        Field::Bytes(_) => 0,
+    }
+}
+
+// decompressed OSMData blocks of the file, one at a time
+struct BlockReader {
+    reader: BufReader<File>,
+}
+
+impl BlockReader {
+    fn open(path: &PathBuf) -> Result<Self, Error> {
+        Ok(BlockReader {
+            reader: BufReader::new(File::open(path)?),
+        })
+    }
+
+    fn next_block(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        loop {
+            let mut length: [u8; 4] = [0; 4];
+            match self.reader.read_exact(&mut length) {
+                Ok(()) => {}
+                Err(error) if error.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+                Err(error) => return Err(error),
+            }
+
+            let mut header: Vec<u8> = vec![0; u32::from_be_bytes(length) as usize];
+            self.reader.read_exact(&mut header)?;
+
+            let mut block_type: String = String::new();
+            let mut data_size: usize = 0;
+            let mut message: Message = Message::new(&header);
+            while let Some((number, field)) = message.next_field()? {
+                match number {
+                    1 => block_type = String::from_utf8_lossy(bytes(field)).to_string(),
+                    3 => data_size = varint(field) as usize,
+                    _ => {}
+                }
+            }
+
+            let mut blob: Vec<u8> = vec![0; data_size];
+            self.reader.read_exact(&mut blob)?;
+            if block_type != "OSMData" {
+                continue;
+            }
+
+            let mut message: Message = Message::new(&blob);
+            while let Some((number, field)) = message.next_field()? {
+                match number {
+                    1 => return Ok(Some(bytes(field).to_vec())),
+                    3 => {
+                        let mut data: Vec<u8> = Vec::new();
+                        ZlibDecoder::new(bytes(field)).read_to_end(&mut data)?;
+                        return Ok(Some(data));
+                    }
+                    4..=7 => {
+                        return Err(Error::new(
+                            ErrorKind::Unsupported,
+                            "only raw and zlib compressed blobs are supported",
+                        ))
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+struct Block<'a> {
+    strings: Vec<&'a [u8]>,
+    groups: Vec<&'a [u8]>,
+    granularity: i64,
+    lat_offset: i64,
+    lon_offset: i64,
+}
+
+impl<'a> Block<'a> {
+    fn parse(data: &'a [u8]) -> Result<Self, Error> {
+        let mut block: Block = Block {
+            strings: Vec::new(),
+            groups: Vec::new(),
+            granularity: 100,
+            lat_offset: 0,
+            lon_offset: 0,
+        };
+
+        let mut message: Message = Message::new(data);
+        while let Some((number, field)) = message.next_field()? {
+            match number {
+                1 => {
+                    let mut table: Message = Message::new(bytes(field));
+                    while let Some((_, string)) = table.next_field()? {
+                        block.strings.push(bytes(string));
+                    }
+                }
+                2 => block.groups.push(bytes(field)),
+                17 => block.granularity = varint(field) as i64,
+                19 => block.lat_offset = varint(field) as i64,
+                20 => block.lon_offset = varint(field) as i64,
+                _ => {}
+            }
+        }
+
+        Ok(block)
+    }
+
+    fn string(&self, i: u64) -> &str {
+        self.strings
+            .get(i as usize)
+            .and_then(|string| std::str::from_utf8(string).ok())
+            .unwrap_or("")
+    }
+
+    fn location(&self, lat: i64, lon: i64) -> Location {
+        Location {
+            latitude: 1e-9 * (self.lat_offset + self.granularity * lat) as f64,
+            longitude: 1e-9 * (self.lon_offset + self.granularity * lon) as f64,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+enum Network {
+    Road,
+    Tram,
+    Rail,
+}
+
+impl Network {
+    // modes without a network of their own (ships, cableways) fall back to straight lines
+    fn from_mode(mode: TransportMode) -> Option<Network> {
+        match mode {
+            TransportMode::Bus => Some(Network::Road),
+            TransportMode::Tramway => Some(Network::Tram),
+            TransportMode::Rail
+            | TransportMode::RackRailroad
+            | TransportMode::Underground
+            | TransportMode::Funicular => Some(Network::Rail),
+            _ => None,
+        }
+    }
+
+    // default speed on a way without maxspeed, in km/h, None when the way is not part of the network
+    fn speed(&self, tags: &HashMap<&str, &str>) -> Option<f64> {
+        match self {
+            Network::Road => match tags.get("highway").copied()? {
+                "motorway" | "motorway_link" => Some(100.0),
+                "trunk" | "trunk_link" => Some(80.0),
+                "primary" | "primary_link" | "secondary" | "secondary_link" | "tertiary"
+                | "tertiary_link" | "unclassified" | "busway" | "bus_guideway" | "road" => Some(50.0),
+                "residential" => Some(30.0),
+                "service" => Some(20.0),
+                "living_street" => Some(10.0),
+                _ => None,
+            },
+            Network::Tram => match tags.get("railway").copied()? {
+                "tram" | "light_rail" => Some(30.0),
+                _ => None,
+            },
+            Network::Rail => match tags.get("railway").copied()? {
+                "rail" | "narrow_gauge" | "light_rail" | "subway" => Some(80.0),
+                "funicular" => Some(15.0),
+                _ => None,
+            },
+        }
+    }
+
+    // 1 forward only, -1 backward only, 0 both ways
+    fn oneway(&self, tags: &HashMap<&str, &str>) -> i8 {
+        if *self != Network::Road
+            || matches!(tags.get("oneway:bus"), Some(&"no"))
+            || matches!(tags.get("oneway:psv"), Some(&"no"))
+            || matches!(tags.get("busway"), Some(&"opposite_lane"))
+        {
+            return 0;
+        }
+        match tags.get("oneway") {
+            Some(&"yes") | Some(&"1") | Some(&"true") => 1,
+            Some(&"-1") => -1,
+            _ if matches!(tags.get("junction"), Some(&"roundabout")) => 1,
+            _ => 0,
+        }
+    }
+}
+
+struct Way {
+    id: i64,
+    network: Network,
+    oneway: i8,
+    speed: f64,
+    refs: Vec<i64>,
+}
+
+struct Edge {
+    target: u32,
+    distance: f32,
+    duration: f32,
+    way_id: i64,
+}
+
+// directed graph of the ways of a network, nodes are the way nodes
+#[derive(Default)]
+struct Graph {
+    locations: Vec<Location>,
+    edges: Vec<Vec<Edge>>,
+    cells: HashMap<(i32, i32), Vec<u32>>,
+}
+
+#[derive(PartialEq)]
+struct Candidate {
+    duration: f32,
+    node: u32,
+}
+
+impl Eq for Candidate {}
+
+impl Ord for Candidate {
+    // reversed for a min-heap
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.duration.total_cmp(&self.duration)
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+fn cell(location: &Location) -> (i32, i32) {
+    (
+        (location.latitude / CELL_SIZE).floor() as i32,
+        (location.longitude / CELL_SIZE).floor() as i32,
+    )
+}
+
+impl Graph {
+    fn build(ways: &[&Way], locations: &HashMap<i64, Location>) -> Self {
+        let mut graph: Graph = Graph::default();
+        let mut index: HashMap<i64, u32> = HashMap::new();
+
+        for way in ways {
+            let nodes: Vec<u32> = way
+                .refs
+                .iter()
+                .filter_map(|node_id| {
+                    let location: &Location = locations.get(node_id)?;
+                    Some(*index.entry(*node_id).or_insert_with(|| {
+                        graph.locations.push(*location);
+                        graph.edges.push(Vec::new());
+                        graph.locations.len() as u32 - 1
+                    }))
+                })
+                .collect();
+
+            for pair in nodes.windows(2) {
+                let distance: f64 = haversine(
+                    &graph.locations[pair[0] as usize],
+                    &graph.locations[pair[1] as usize],
+                );
+                let duration: f64 = distance / (way.speed / 3.6);
+                if way.oneway >= 0 {
+                    graph.edges[pair[0] as usize].push(Edge {
+                        target: pair[1],
+                        distance: distance as f32,
+                        duration: duration as f32,
+                        way_id: way.id,
+                    });
+                }
+                if way.oneway <= 0 {
+                    graph.edges[pair[1] as usize].push(Edge {
+                        target: pair[0],
+                        distance: distance as f32,
+                        duration: duration as f32,
+                        way_id: way.id,
+                    });
+                }
+            }
+        }
+
+        for (i, location) in graph.locations.iter().enumerate() {
+            graph.cells.entry(cell(location)).or_default().push(i as u32);
+        }
+
+        graph
+    }
+
+    // closest node, looking in the cells around the location
+    fn nearest(&self, location: &Location) -> Option<u32> {
+        let (row, column) = cell(location);
+        let rings: i32 = (MAX_SNAP_DISTANCE / 111_000.0 / CELL_SIZE).ceil() as i32 + 1;
+
+        (row - rings..=row + rings)
+            .flat_map(|r| (column - rings..=column + rings).map(move |c| (r, c)))
+            .filter_map(|key| self.cells.get(&key))
+            .flatten()
+            .map(|node| (haversine(location, &self.locations[*node as usize]), *node))
+            .filter(|(distance, _)| *distance <= MAX_SNAP_DISTANCE)
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|(_, node)| node)
+    }
+
+    // fastest path as the edges taken, dijkstra stopped at the target
+    fn shortest_path(&self, source: u32, target: u32) -> Option<Vec<(u32, &Edge)>> {
+        let mut durations: HashMap<u32, f32> = HashMap::new();
+        let mut previous: HashMap<u32, (u32, &Edge)> = HashMap::new();
+        let mut heap: BinaryHeap<Candidate> = BinaryHeap::new();
+
+        durations.insert(source, 0.0);
+        heap.push(Candidate {
+            duration: 0.0,
+            node: source,
+        });
+
+        while let Some(Candidate { duration, node }) = heap.pop() {
+            if node == target {
+                break;
+            }
+            if duration > *durations.get(&node).unwrap_or(&f32::INFINITY) {
+                continue;
+            }
+            for edge in &self.edges[node as usize] {
+                let next: f32 = duration + edge.duration;
+                if next < *durations.get(&edge.target).unwrap_or(&f32::INFINITY) {
+                    durations.insert(edge.target, next);
+                    previous.insert(edge.target, (node, edge));
+                    heap.push(Candidate {
+                        duration: next,
+                        node: edge.target,
+                    });
+                }
+            }
+        }
+
+        if source != target && !previous.contains_key(&target) {
+            return None;
+        }
+
+        let mut path: Vec<(u32, &Edge)> = Vec::new();
+        let mut node: u32 = target;
+        while let Some((from, edge)) = previous.get(&node) {
+            path.push((*from, *edge));
+            node = *from;
+        }
+        path.reverse();
+
+        Some(path)
+    }
+}
+
+struct Graphs {
+    graphs: HashMap<Network, Graph>,
+}
+
+impl Graphs {
+    fn load(path: &PathBuf) -> Result<Self, Error> {
+        let networks: [Network; 3] = [Network::Road, Network::Tram, Network::Rail];
+
+        // ways first, only the nodes they use are kept
+        let mut ways: Vec<Way> = Vec::new();
+        let mut blocks: BlockReader = BlockReader::open(path)?;
+        while let Some(data) = blocks.next_block()? {
+            let block: Block = Block::parse(&data)?;
+            for group in &block.groups {
+                let mut message: Message = Message::new(group);
+                while let Some((number, field)) = message.next_field()? {
+                    if number != 3 {
+                        continue;
+                    }
+
+                    let mut id: i64 = 0;
+                    let mut keys: Vec<u64> = Vec::new();
+                    let mut values: Vec<u64> = Vec::new();
+                    let mut refs: Vec<u64> = Vec::new();
+                    let mut way: Message = Message::new(bytes(field));
+                    while let Some((number, field)) = way.next_field()? {
+                        match number {
+                            1 => id = varint(field) as i64,
+                            2 => push_varints(field, &mut keys)?,
+                            3 => push_varints(field, &mut values)?,
+                            8 => push_varints(field, &mut refs)?,
+                            _ => {}
+                        }
+                    }
+
+                    let tags: HashMap<&str, &str> = keys
+                        .iter()
+                        .zip(&values)
+                        .map(|(key, value)| (block.string(*key), block.string(*value)))
+                        .collect();
+                    for network in networks {
+                        if let Some(default_speed) = network.speed(&tags) {
+                            ways.push(Way {
+                                id,
+                                network,
+                                oneway: network.oneway(&tags),
+                                speed: tags
+                                    .get("maxspeed")
+                                    .and_then(|speed| speed.parse::<f64>().ok())
+                                    .unwrap_or(default_speed),
+                                refs: delta_decode(&refs),
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        let needed: HashSet<i64> = ways.iter().flat_map(|way| way.refs.iter().copied()).collect();
+        let mut locations: HashMap<i64, Location> = HashMap::with_capacity(needed.len());
+
+        let mut blocks: BlockReader = BlockReader::open(path)?;
+        while let Some(data) = blocks.next_block()? {
+            let block: Block = Block::parse(&data)?;
+            for group in &block.groups {
+                let mut message: Message = Message::new(group);
+                while let Some((number, field)) = message.next_field()? {
+                    let mut ids: Vec<u64> = Vec::new();
+                    let mut lats: Vec<u64> = Vec::new();
+                    let mut lons: Vec<u64> = Vec::new();
+                    let mut node: Message = Message::new(bytes(field));
+                    match number {
+                        // plain nodes, values are not delta coded
+                        1 => {
+                            while let Some((number, field)) = node.next_field()? {
+                                match number {
+                                    1 => ids.push(varint(field)),
+                                    8 => lats.push(varint(field)),
+                                    9 => lons.push(varint(field)),
+                                    _ => {}
+                                }
+                            }
+                            if let (Some(id), Some(lat), Some(lon)) = (ids.first(), lats.first(), lons.first()) {
+                                if needed.contains(&zigzag(*id)) {
+                                    locations.insert(zigzag(*id), block.location(zigzag(*lat), zigzag(*lon)));
+                                }
+                            }
+                        }
+                        2 => {
+                            while let Some((number, field)) = node.next_field()? {
+                                match number {
+                                    1 => push_varints(field, &mut ids)?,
+                                    8 => push_varints(field, &mut lats)?,
+                                    9 => push_varints(field, &mut lons)?,
+                                    _ => {}
+                                }
+                            }
+                            let ids: Vec<i64> = delta_decode(&ids);
+                            let lats: Vec<i64> = delta_decode(&lats);
+                            let lons: Vec<i64> = delta_decode(&lons);
+                            for ((id, lat), lon) in ids.iter().zip(&lats).zip(&lons) {
+                                if needed.contains(id) {
+                                    locations.insert(*id, block.location(*lat, *lon));
+                                }
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        let mut graphs: HashMap<Network, Graph> = HashMap::new();
+        for network in networks {
+            let network_ways: Vec<&Way> = ways.iter().filter(|way| way.network == network).collect();
+            let graph: Graph = Graph::build(&network_ways, &locations);
+            info!(
+                "Built {:?} graph: {} ways, {} nodes",
+                network,
+                network_ways.len(),
+                graph.locations.len()
+            );
+            graphs.insert(network, graph);
+        }
+
+        Ok(Graphs { graphs })
+    }
+}
+
+// routes on the ways of a local .osm.pbf extract, read on first use
+pub struct OsmProvider {
+    pub path: PathBuf,
+    graphs: OnceLock<Result<Graphs, String>>,
+    fallback: StraightLineProvider,
+}
+
+impl OsmProvider {
+    pub fn new(path: PathBuf) -> Self {
+        OsmProvider {
+            path,
+            graphs: OnceLock::new(),
+            fallback: StraightLineProvider::default(),
+        }
+    }
+
+    fn graph(&self, mode: TransportMode) -> Result<Option<&Graph>, RoutingError> {
+        let graphs: &Graphs = self
+            .graphs
+            .get_or_init(|| {
+                info!("Loading {}...", self.path.display());
+                Graphs::load(&self.path).map_err(|error| error.to_string())
+            })
+            .as_ref()
+            .map_err(|error| RoutingError::Network(error.clone()))?;
+
+        Ok(Network::from_mode(mode).and_then(|network| graphs.graphs.get(&network)))
+    }
+
+    // fastest path between the nodes closest to the locations
+    fn path<'g>(graph: &'g Graph, from: &Location, to: &Location) -> Result<Vec<(u32, &'g Edge)>, RoutingError> {
+        let source: u32 = graph.nearest(from).ok_or(RoutingError::NoRoute)?;
+        let target: u32 = graph.nearest(to).ok_or(RoutingError::NoRoute)?;
+
+        graph.shortest_path(source, target).ok_or(RoutingError::NoRoute)
+    }
+}
+
+#[async_trait]
+impl RoutingProvider for OsmProvider {
+    fn name(&self) -> String {
+        "osm".to_string()
+    }
+
+    fn max_waypoints(&self) -> usize {
+        usize::MAX
+    }
+
+    fn max_snap_points(&self) -> usize {
+        usize::MAX
+    }
+
+    async fn route(&self, mode: TransportMode, waypoints: &[Location]) -> Result<Vec<RoutedLeg>, RoutingError> {
+        let graph: &Graph = match self.graph(mode)? {
+            Some(graph) => graph,
+            None => return self.fallback.route(mode, waypoints).await,
+        };
+
+        let mut legs: Vec<RoutedLeg> = Vec::new();
+        for pair in waypoints.windows(2) {
+            // one step per way taken, edges of curved ways are a few meters long and are only rounded
+            // once summed
+            let mut steps: Vec<RoutedStep> = Vec::new();
+            let mut totals: Vec<(f32, f32)> = Vec::new();
+            let mut current_way: Option<i64> = None;
+            for (from, edge) in OsmProvider::path(graph, &pair[0], &pair[1])? {
+                let end: Location = graph.locations[edge.target as usize];
+                match (steps.last_mut(), totals.last_mut()) {
+                    (Some(step), Some(total)) if current_way == Some(edge.way_id) => {
+                        total.0 += edge.distance;
+                        total.1 += edge.duration;
+                        step.end = end;
+                        step.points.push(end);
+                    }
+                    _ => {
+                        steps.push(RoutedStep {
+                            distance: 0,
+                            duration: 0,
+                            start: graph.locations[from as usize],
+                            end,
+                            points: vec![graph.locations[from as usize], end],
+                        });
+                        totals.push((edge.distance, edge.duration));
+                    }
+                }
+                current_way = Some(edge.way_id);
+            }
+            for (step, (distance, duration)) in steps.iter_mut().zip(&totals) {
+                step.distance = distance.round() as i32;
+                step.duration = duration.round() as i32;
+            }
+
+            legs.push(RoutedLeg {
+                distance: totals.iter().map(|total| total.0).sum::<f32>().round() as i32,
+                duration: totals.iter().map(|total| total.1).sum::<f32>().round() as i32,
+                steps,
+            });
+        }
+
+        Ok(legs)
+    }
+
+    async fn snap(&self, mode: TransportMode, path: &[Location]) -> Result<Vec<SnappedLocation>, RoutingError> {
+        let graph: &Graph = match self.graph(mode)? {
+            Some(graph) => graph,
+            None => return self.fallback.snap(mode, path).await,
+        };
+
+        let mut snapped: Vec<SnappedLocation> = Vec::new();
+        for (i, pair) in path.windows(2).enumerate() {
+            let edges: Vec<(u32, &Edge)> = OsmProvider::path(graph, &pair[0], &pair[1])?;
+            snapped.push(SnappedLocation {
+                location: graph.locations[graph.nearest(&pair[0]).unwrap() as usize],
+                original_index: Some(i),
+            });
+            // the node of the next stop is added by the next pair
+            for (_, edge) in edges.iter().take(edges.len().saturating_sub(1)) {
+                snapped.push(SnappedLocation {
+                    location: graph.locations[edge.target as usize],
+                    original_index: None,
+                });
+            }
+        }
+        if let Some(last) = path.last() {
+            let node: u32 = graph.nearest(last).ok_or(RoutingError::NoRoute)?;
+            snapped.push(SnappedLocation {
+                location: graph.locations[node as usize],
+                original_index: Some(path.len() - 1),
+            });
+        }
+
+        Ok(snapped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags<'a>(pairs: &[(&'a str, &'a str)]) -> HashMap<&'a str, &'a str> {
+        return pairs.iter().copied().collect();
+    }
+
+    fn location(latitude: f64, longitude: f64) -> Location {
+        Location { latitude, longitude }
+    }
+
+    fn way(id: i64, oneway: i8, speed: f64, refs: &[i64]) -> Way {
+        Way { id, network: Network::Road, oneway, speed, refs: refs.to_vec() }
+    }
+
+    // 1 - 2 - 3 both ways at 36 km/h, 3 -> 4 one way, and 1 - 3 straight but at walking pace
+    fn graph() -> Graph {
+        let locations: HashMap<i64, Location> = HashMap::from([
+            (1, location(46.0, 6.0)),
+            (2, location(46.0, 6.001)),
+            (3, location(46.0, 6.002)),
+            (4, location(46.001, 6.002)),
+        ]);
+        let ways: Vec<Way> = vec![way(10, 0, 36.0, &[1, 2, 3]), way(11, 1, 36.0, &[3, 4]), way(12, 0, 3.6, &[1, 3])];
+
+        return Graph::build(&ways.iter().collect::<Vec<&Way>>(), &locations);
+    }
+
+    #[test]
+    fn varints_are_read_seven_bits_at_a_time() {
+        assert_eq!(Message::new(&[0x96, 0x01]).varint().unwrap(), 150);
+        assert!(Message::new(&[0x96]).varint().is_err());
+        assert!(Message::new(&[0xff; 11]).varint().is_err());
+    }
+
+    #[test]
+    fn fields_carry_their_number() {
+        let data: [u8; 7] = [0x08, 0x96, 0x01, 0x12, 0x02, b'h', b'i'];
+        let mut message: Message = Message::new(&data);
+
+        assert!(matches!(message.next_field().unwrap(), Some((1, Field::Varint(150)))));
+        assert!(matches!(message.next_field().unwrap(), Some((2, Field::Bytes(b"hi")))));
+        assert!(message.next_field().unwrap().is_none());
+        assert!(Message::new(&[0x12, 0x05, b'h']).next_field().is_err());
+    }
+
+    #[test]
+    fn zigzag_alternates_the_signs() {
+        assert_eq!(zigzag(0), 0);
+        assert_eq!(zigzag(1), -1);
+        assert_eq!(zigzag(2), 1);
+        assert_eq!(zigzag(3), -2);
+        assert_eq!(zigzag(4_294_967_294), 2_147_483_647);
+    }
+
+    #[test]
+    fn packed_fields_hold_several_varints() {
+        let mut values: Vec<u64> = Vec::new();
+        push_varints(Field::Bytes(&[0x03, 0x8e, 0x02]), &mut values).unwrap();
+        push_varints(Field::Varint(7), &mut values).unwrap();
+
+        assert_eq!(values, vec![3, 270, 7]);
+    }
+
+    #[test]
+    fn deltas_add_up() {
+        assert_eq!(delta_decode(&[2, 2, 3]), vec![1, 2, 0]);
+        assert!(delta_decode(&[]).is_empty());
+    }
+
+    #[test]
+    fn networks_take_their_own_ways() {
+        assert_eq!(Network::Road.speed(&tags(&[("highway", "residential")])), Some(30.0));
+        assert_eq!(Network::Road.speed(&tags(&[("highway", "footway")])), None);
+        assert_eq!(Network::Road.speed(&tags(&[("railway", "tram")])), None);
+        assert_eq!(Network::Tram.speed(&tags(&[("railway", "tram")])), Some(30.0));
+        assert_eq!(Network::Rail.speed(&tags(&[("railway", "funicular")])), Some(15.0));
+        assert_eq!(Network::from_mode(TransportMode::Ship), None);
+    }
+
+    #[test]
+    fn buses_follow_the_one_way_streets_they_are_bound_to() {
+        assert_eq!(Network::Road.oneway(&tags(&[("oneway", "yes")])), 1);
+        assert_eq!(Network::Road.oneway(&tags(&[("oneway", "-1")])), -1);
+        assert_eq!(Network::Road.oneway(&tags(&[("junction", "roundabout")])), 1);
+        assert_eq!(Network::Road.oneway(&tags(&[("oneway", "yes"), ("oneway:bus", "no")])), 0);
+        assert_eq!(Network::Road.oneway(&tags(&[])), 0);
+        assert_eq!(Network::Tram.oneway(&tags(&[("oneway", "yes")])), 0);
+    }
+
+    #[test]
+    fn shortest_paths_are_the_fastest() {
+        let graph: Graph = graph();
+
+        let path: Vec<(u32, &Edge)> = graph.shortest_path(0, 3).unwrap();
+        let way_ids: Vec<i64> = path.iter().map(|(_, edge)| edge.way_id).collect();
+        assert_eq!(way_ids, vec![10, 10, 11]);
+        assert_eq!(path.iter().map(|(from, _)| *from).collect::<Vec<u32>>(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn shortest_paths_keep_to_one_way_streets() {
+        let graph: Graph = graph();
+
+        assert!(graph.shortest_path(3, 0).is_none());
+        assert!(graph.shortest_path(2, 2).unwrap().is_empty());
+    }
+
+    #[test]
+    fn nearest_nodes_are_within_the_snap_distance() {
+        let graph: Graph = graph();
+
+        assert_eq!(graph.nearest(&location(46.0001, 6.0011)), Some(1));
+        assert_eq!(graph.nearest(&location(46.0009, 6.0021)), Some(3));
+        assert_eq!(graph.nearest(&location(46.1, 6.0)), None);
+    }
+
+    #[actix_web::test]
+    async fn steps_are_rounded_once_summed() {
+        // a hundred edges of less than a meter, each one a tenth of a second at 36 km/h
+        let refs: Vec<i64> = (0..=100).collect();
+        let locations: HashMap<i64, Location> =
+            refs.iter().map(|id| (*id, location(46.0, 6.0 + *id as f64 * 0.00001))).collect();
+        let ways: Vec<Way> = vec![way(10, 0, 36.0, &refs)];
+        let graph: Graph = Graph::build(&ways.iter().collect::<Vec<&Way>>(), &locations);
+        let provider: OsmProvider = OsmProvider {
+            path: PathBuf::new(),
+            graphs: OnceLock::from(Ok(Graphs { graphs: HashMap::from([(Network::Road, graph)]) })),
+            fallback: StraightLineProvider::default(),
+        };
+
+        let legs: Vec<RoutedLeg> =
+            provider.route(TransportMode::Bus, &[location(46.0, 6.0), location(46.0, 6.001)]).await.unwrap();
+        let distance: f64 = haversine(&location(46.0, 6.0), &location(46.0, 6.001));
+
+        assert_eq!(legs[0].steps.len(), 1);
+        assert_eq!(legs[0].distance, distance.round() as i32);
+        assert_eq!(legs[0].duration, (distance / 10.0).round() as i32);
+        assert_eq!(legs[0].steps[0].duration, legs[0].duration);
+    }
+}
@@ -0,0 +1,526 @@
+use std::collections::{hash_map::Entry, HashMap, HashSet};
+
+use chrono::{NaiveTime, Timelike};
+
+use crate::model::{
+    journey::{Journey, JourneyLeg},
+    stop::Stop,
+    trip::Trip,
+    trip_stop::TripStop,
+};
+
+use super::{
+    calibration::{seconds_to_time, DAY},
+    routing::{haversine, Location},
+};
+
+// seconds to change vehicles at the same stop, walks between stops take at least as long
+pub const MIN_TRANSFER_TIME: i32 = 120;
+// meters
+pub const MAX_WALK_DISTANCE: f64 = 400.0;
+// meters per second
+pub const WALK_SPEED: f64 = 1.2;
+
+// trip running from a stop to the next one, times in seconds since midnight
+struct Connection {
+    trip_id: i64,
+    // the trips of the day before run as well, their times a day earlier
+    day: i32,
+    line_id: i32,
+    departure_stop: i32,
+    arrival_stop: i32,
+    departure: i32,
+    arrival: i32,
+}
+
+struct Footpath {
+    destination: i32,
+    distance: f64,
+    duration: i32,
+}
+
+// how the earliest arrival at a stop was reached
+#[derive(Clone, Copy)]
+enum Reached {
+    Origin,
+    Ride { board: usize, alight: usize },
+    Walk { origin: i32, departure: i32, distance: f64, duration: i32 },
+}
+
+// connection scan over the trips of a service day
+pub struct Planner {
+    // sorted by departure
+    connections: Vec<Connection>,
+    footpaths: HashMap<i32, Vec<Footpath>>,
+}
+
+fn seconds(time: NaiveTime) -> i32 {
+    return time.num_seconds_from_midnight() as i32;
+}
+
+impl Connection {
+    // the same trip runs on several days
+    fn run(&self) -> (i64, i32) {
+        return (self.trip_id, self.day);
+    }
+}
+
+impl Planner {
+    // trips of the day and of the day before, which still run past midnight
+    pub fn new(
+        trips: &Vec<Trip>,
+        trip_stops: &Vec<TripStop>,
+        previous_trip_stops: &Vec<TripStop>,
+        stops: &Vec<Stop>,
+    ) -> Planner {
+        let lines: HashMap<i64, i32> = trips.iter().map(|trip| (trip.id, trip.line_id)).collect();
+
+        let mut by_trip: HashMap<(i64, i32), Vec<&TripStop>> = HashMap::new();
+        for (day_stops, day) in [(trip_stops, 0), (previous_trip_stops, -1)] {
+            for trip_stop in day_stops {
+                by_trip.entry((trip_stop.trip_id, day)).or_default().push(trip_stop);
+            }
+        }
+
+        let mut connections: Vec<Connection> = Vec::new();
+        for ((trip_id, day), tstops) in by_trip.iter_mut() {
+            let line_id: i32 = match lines.get(trip_id) {
+                Some(line_id) => *line_id,
+                None => continue,
+            };
+            tstops.sort_by_key(|trip_stop| trip_stop.sequence);
+
+            // times are increasing along the trip, the ones after midnight go past a day
+            let mut offset: i32 = *day * DAY;
+            let mut previous: i32 = offset;
+            let mut unwrap = |time: NaiveTime| {
+                let mut time: i32 = seconds(time) + offset;
+                if time < previous {
+                    offset += DAY;
+                    time += DAY;
+                }
+                previous = time;
+                time
+            };
+
+            let mut last: Option<(i32, i32)> = None;
+            for trip_stop in tstops.iter() {
+                let arrival: Option<i32> = trip_stop.arrival_time.or(trip_stop.departure_time).map(&mut unwrap);
+                let departure: Option<i32> = trip_stop.departure_time.or(trip_stop.arrival_time).map(&mut unwrap);
+
+                if let (Some((stop_id, time)), Some(arrival)) = (last, arrival) {
+                    connections.push(Connection {
+                        trip_id: *trip_id,
+                        day: *day,
+                        line_id,
+                        departure_stop: stop_id,
+                        arrival_stop: trip_stop.stop_id,
+                        departure: time,
+                        arrival,
+                    });
+                }
+                last = departure.map(|departure| (trip_stop.stop_id, departure));
+            }
+        }
+        connections.sort_by_key(|connection| (connection.departure, connection.arrival));
+
+        // walks between the stops served on the day, swept by latitude
+        let served_ids: HashSet<i32> =
+            trip_stops.iter().chain(previous_trip_stops).map(|trip_stop| trip_stop.stop_id).collect();
+        let mut served: Vec<&Stop> = stops.iter().filter(|stop| served_ids.contains(&stop.id)).collect();
+        served.sort_by(|a, b| a.latitude.total_cmp(&b.latitude));
+
+        let latitude_span: f64 = MAX_WALK_DISTANCE / 111_320.0;
+        let mut footpaths: HashMap<i32, Vec<Footpath>> = HashMap::new();
+        for (k, origin) in served.iter().enumerate() {
+            for destination in &served[k + 1..] {
+                if destination.latitude - origin.latitude > latitude_span {
+                    break;
+                }
+
+                let distance: f64 = haversine(
+                    &Location { latitude: origin.latitude, longitude: origin.longitude },
+                    &Location { latitude: destination.latitude, longitude: destination.longitude },
+                );
+                if distance > MAX_WALK_DISTANCE {
+                    continue;
+                }
+
+                let duration: i32 = ((distance / WALK_SPEED).round() as i32).max(MIN_TRANSFER_TIME);
+                footpaths.entry(origin.id).or_default().push(Footpath { destination: destination.id, distance, duration });
+                footpaths.entry(destination.id).or_default().push(Footpath { destination: origin.id, distance, duration });
+            }
+        }
+
+        Planner { connections, footpaths }
+    }
+
+    // earliest arrivals from the origin and how they were reached, until the destination is reached
+    // or the deadline passed
+    fn scan(
+        &self,
+        origin: i32,
+        start: i32,
+        destination: Option<i32>,
+        deadline: i32,
+    ) -> (HashMap<i32, i32>, HashMap<i32, Reached>) {
+        let mut arrival: HashMap<i32, i32> = HashMap::new();
+        // arrival plus the time to change, for the stops reached on board
+        let mut ready: HashMap<i32, i32> = HashMap::new();
+        let mut reached: HashMap<i32, Reached> = HashMap::new();
+        let mut boarded: HashMap<(i64, i32), usize> = HashMap::new();
+
+        arrival.insert(origin, start);
+        ready.insert(origin, start);
+        reached.insert(origin, Reached::Origin);
+        for footpath in self.footpaths.get(&origin).into_iter().flatten() {
+            arrival.insert(footpath.destination, start + footpath.duration);
+            ready.insert(footpath.destination, start + footpath.duration);
+            reached.insert(
+                footpath.destination,
+                Reached::Walk { origin, departure: start, distance: footpath.distance, duration: footpath.duration },
+            );
+        }
+
+        let first: usize = self.connections.partition_point(|connection| connection.departure < start);
+        for (k, connection) in self.connections.iter().enumerate().skip(first) {
+            let until: i32 = destination
+                .and_then(|destination| arrival.get(&destination).copied())
+                .unwrap_or(i32::MAX)
+                .min(deadline);
+            if connection.departure >= until {
+                break;
+            }
+
+            if let Entry::Vacant(entry) = boarded.entry(connection.run()) {
+                if *ready.get(&connection.departure_stop).unwrap_or(&i32::MAX) > connection.departure {
+                    continue;
+                }
+                entry.insert(k);
+            }
+
+            if connection.arrival >= *arrival.get(&connection.arrival_stop).unwrap_or(&i32::MAX) {
+                continue;
+            }
+            arrival.insert(connection.arrival_stop, connection.arrival);
+            ready.insert(connection.arrival_stop, connection.arrival + MIN_TRANSFER_TIME);
+            reached.insert(
+                connection.arrival_stop,
+                Reached::Ride { board: boarded[&connection.run()], alight: k },
+            );
+
+            for footpath in self.footpaths.get(&connection.arrival_stop).into_iter().flatten() {
+                let time: i32 = connection.arrival + footpath.duration;
+                if time < *arrival.get(&footpath.destination).unwrap_or(&i32::MAX) {
+                    arrival.insert(footpath.destination, time);
+                    ready.insert(footpath.destination, time);
+                    reached.insert(
+                        footpath.destination,
+                        Reached::Walk {
+                            origin: connection.arrival_stop,
+                            departure: connection.arrival,
+                            distance: footpath.distance,
+                            duration: footpath.duration,
+                        },
+                    );
+                }
+            }
+        }
+
+        return (arrival, reached);
+    }
+
+    // journey arriving first, leaving as late as it can on its first trip, with its departure in seconds
+    fn earliest(&self, origin: i32, destination: i32, start: i32) -> Option<(Journey, i32)> {
+        let (arrival, reached) = self.scan(origin, start, Some(destination), i32::MAX);
+
+        // back from the destination to the origin, the walks and rides alternate
+        let mut legs: Vec<JourneyLeg> = Vec::new();
+        let mut times: Vec<(i32, i32)> = Vec::new();
+        let mut stop_id: i32 = destination;
+        while let Some(step) = reached.get(&stop_id) {
+            match *step {
+                Reached::Origin => break,
+                Reached::Ride { board, alight } => {
+                    let (boarding, alighting) = (&self.connections[board], &self.connections[alight]);
+                    let stop_count: usize = self.connections[board..=alight]
+                        .iter()
+                        .filter(|connection| connection.run() == boarding.run())
+                        .count();
+
+                    legs.push(JourneyLeg::Ride {
+                        trip_id: boarding.trip_id,
+                        line_id: boarding.line_id,
+                        origin_id: boarding.departure_stop,
+                        destination_id: alighting.arrival_stop,
+                        departure_time: seconds_to_time(boarding.departure),
+                        arrival_time: seconds_to_time(alighting.arrival),
+                        stop_count: stop_count as i32,
+                    });
+                    times.push((boarding.departure, alighting.arrival));
+                    stop_id = boarding.departure_stop;
+                }
+                Reached::Walk { origin, departure, distance, duration } => {
+                    legs.push(JourneyLeg::Walk {
+                        origin_id: origin,
+                        destination_id: stop_id,
+                        departure_time: seconds_to_time(departure),
+                        arrival_time: seconds_to_time(departure + duration),
+                        distance: distance.round() as i32,
+                    });
+                    times.push((departure, departure + duration));
+                    stop_id = origin;
+                }
+            }
+
+            // a broken chain of steps is no journey
+            if legs.len() > self.connections.len() + 1 {
+                return None;
+            }
+        }
+        if stop_id != origin || legs.is_empty() {
+            return None;
+        }
+        legs.reverse();
+        times.reverse();
+
+        // the first walk leaves just in time for the first trip
+        if let (Some(JourneyLeg::Walk { departure_time, arrival_time, .. }), Some((next, _))) = (legs.first_mut(), times.get(1)) {
+            let duration: i32 = times[0].1 - times[0].0;
+            times[0] = (next - duration, *next);
+            *departure_time = seconds_to_time(times[0].0);
+            *arrival_time = seconds_to_time(times[0].1);
+        }
+
+        let rides: i32 = legs.iter().filter(|leg| matches!(leg, JourneyLeg::Ride { .. })).count() as i32;
+        let departure: i32 = times[0].0;
+        let arrival: i32 = *arrival.get(&destination)?;
+
+        let journey: Journey = Journey {
+            departure_time: seconds_to_time(departure),
+            arrival_time: seconds_to_time(arrival),
+            duration: arrival - departure,
+            transfers: (rides - 1).max(0),
+            legs,
+        };
+        Some((journey, departure))
+    }
+
+    // journeys leaving after the time, each one leaving after the previous one, none arriving at the same
+    // time as a later one
+    pub fn plan(&self, origin: i32, destination: i32, time: NaiveTime, count: usize) -> Vec<Journey> {
+        let mut journeys: Vec<Journey> = Vec::new();
+        let mut start: i32 = seconds(time);
+
+        while journeys.len() < count {
+            let (journey, departure): (Journey, i32) = match self.earliest(origin, destination, start) {
+                Some(found) => found,
+                None => break,
+            };
+
+            let walk_only: bool = journey.legs.iter().all(|leg| matches!(leg, JourneyLeg::Walk { .. }));
+            if journeys.last().map(|last| last.arrival_time) == Some(journey.arrival_time) {
+                journeys.pop();
+            }
+            journeys.push(journey);
+
+            // walking takes as long whenever it starts
+            if walk_only {
+                break;
+            }
+            start = departure + 1;
+        }
+
+        return journeys;
+    }
+
+    // stops reached within the duration after the time, with the seconds it takes to reach them
+    pub fn reachable(&self, origin: i32, time: NaiveTime, duration: i32) -> HashMap<i32, i32> {
+        let start: i32 = seconds(time);
+        let (arrival, _) = self.scan(origin, start, None, start + duration);
+
+        return arrival
+            .into_iter()
+            .map(|(stop_id, arrival)| (stop_id, arrival - start))
+            .filter(|(_, duration_to)| *duration_to <= duration)
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::{line::TransportMode, types::Direction};
+
+    use super::*;
+
+    const A: i32 = 1;
+    const B: i32 = 2;
+    const C: i32 = 3;
+    const D: i32 = 4;
+    const E: i32 = 5;
+    // about 240 meters east of a
+    const W: i32 = 6;
+
+    fn time(hour: u32, minute: u32, second: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, second).unwrap()
+    }
+
+    fn stop(id: i32, latitude: f64, longitude: f64) -> Stop {
+        Stop { id, latitude, longitude, name: id.to_string() }
+    }
+
+    fn trip(id: i64, line_id: i32) -> Trip {
+        Trip {
+            id,
+            journey_number: id as i32,
+            option_count: 0,
+            shape_id: None,
+            direction_id: None,
+            transport_mode: TransportMode::Bus,
+            origin_id: 0,
+            destination_id: 0,
+            information_id: 1,
+            bitfield_id: 1,
+            line_id,
+            direction: Direction::Outward,
+            departure_time: NaiveTime::MIN,
+            arrival_time: NaiveTime::MIN,
+        }
+    }
+
+    // every stop of a trip is left when it is reached
+    fn trip_stops(trip_id: i64, stops: &[(i32, NaiveTime)]) -> Vec<TripStop> {
+        return stops
+            .iter()
+            .enumerate()
+            .map(|(k, (stop_id, time))| TripStop {
+                id: trip_id * 10 + k as i64,
+                stop_id: *stop_id,
+                trip_id,
+                sequence: k as i16 + 1,
+                arrival_time: Some(*time),
+                departure_time: Some(*time),
+                platform: None,
+            })
+            .collect();
+    }
+
+    fn planner() -> Planner {
+        let stops: Vec<Stop> = vec![
+            stop(A, 46.0, 6.0),
+            stop(B, 46.05, 6.0),
+            stop(C, 46.1, 6.0),
+            stop(D, 46.2, 6.0),
+            stop(E, 46.3, 6.5),
+            stop(W, 46.0, 6.0031),
+        ];
+        let trips: Vec<Trip> = (1..=6).map(|id| trip(id, id as i32)).collect();
+        let trip_stops: Vec<TripStop> = [
+            trip_stops(1, &[(A, time(8, 0, 0)), (B, time(8, 10, 0)), (C, time(8, 20, 0))]),
+            trip_stops(2, &[(A, time(8, 30, 0)), (B, time(8, 40, 0)), (C, time(8, 50, 0))]),
+            trip_stops(3, &[(W, time(8, 10, 0)), (D, time(8, 40, 0))]),
+            // one minute after the arrival of the first trip at b, too short to change
+            trip_stops(4, &[(B, time(8, 11, 0)), (E, time(8, 25, 0))]),
+            trip_stops(5, &[(B, time(8, 15, 0)), (E, time(8, 30, 0))]),
+            trip_stops(6, &[(C, time(23, 50, 0)), (D, time(0, 10, 0))]),
+        ]
+        .concat();
+
+        return Planner::new(&trips, &trip_stops, &Vec::new(), &stops);
+    }
+
+    fn rides(journey: &Journey) -> Vec<i64> {
+        return journey
+            .legs
+            .iter()
+            .filter_map(|leg| match leg {
+                JourneyLeg::Ride { trip_id, .. } => Some(*trip_id),
+                JourneyLeg::Walk { .. } => None,
+            })
+            .collect();
+    }
+
+    #[test]
+    fn journeys_leave_one_after_the_other() {
+        let journeys: Vec<Journey> = planner().plan(A, C, time(7, 55, 0), 3);
+
+        assert_eq!(journeys.len(), 2);
+        assert_eq!(rides(&journeys[0]), vec![1]);
+        assert_eq!((journeys[0].departure_time, journeys[0].arrival_time), (time(8, 0, 0), time(8, 20, 0)));
+        assert_eq!(rides(&journeys[1]), vec![2]);
+        assert_eq!(journeys[1].duration, 20 * 60);
+        assert!(matches!(journeys[0].legs[0], JourneyLeg::Ride { stop_count: 2, .. }));
+    }
+
+    #[test]
+    fn changes_leave_time_to_transfer() {
+        let journeys: Vec<Journey> = planner().plan(A, E, time(7, 55, 0), 1);
+
+        assert_eq!(rides(&journeys[0]), vec![1, 5]);
+        assert_eq!(journeys[0].transfers, 1);
+        assert_eq!(journeys[0].arrival_time, time(8, 30, 0));
+    }
+
+    #[test]
+    fn first_walks_leave_just_in_time_for_the_first_trip() {
+        let journeys: Vec<Journey> = planner().plan(A, D, time(7, 55, 0), 1);
+        let journey: &Journey = &journeys[0];
+
+        let (departure_time, arrival_time) = match journey.legs[0] {
+            JourneyLeg::Walk { departure_time, arrival_time, .. } => (departure_time, arrival_time),
+            JourneyLeg::Ride { .. } => panic!("the journey starts on board"),
+        };
+        let distance: f64 =
+            haversine(&Location { latitude: 46.0, longitude: 6.0 }, &Location { latitude: 46.0, longitude: 6.0031 });
+        let duration: i32 = ((distance / WALK_SPEED).round() as i32).max(MIN_TRANSFER_TIME);
+
+        assert_eq!(arrival_time, time(8, 10, 0));
+        assert_eq!(seconds(arrival_time) - seconds(departure_time), duration);
+        assert_eq!(journey.departure_time, departure_time);
+        assert_eq!(journey.duration, 30 * 60 + duration);
+        assert_eq!(rides(journey), vec![3]);
+        assert_eq!(journey.transfers, 0);
+    }
+
+    #[test]
+    fn walks_alone_are_planned_once() {
+        let journeys: Vec<Journey> = planner().plan(A, W, time(7, 55, 0), 3);
+
+        assert_eq!(journeys.len(), 1);
+        assert!(rides(&journeys[0]).is_empty());
+        assert_eq!(journeys[0].departure_time, time(7, 55, 0));
+    }
+
+    #[test]
+    fn trips_past_midnight_keep_counting() {
+        let journeys: Vec<Journey> = planner().plan(C, D, time(23, 45, 0), 1);
+
+        assert_eq!(rides(&journeys[0]), vec![6]);
+        assert_eq!(journeys[0].arrival_time, time(0, 10, 0));
+        assert_eq!(journeys[0].duration, 20 * 60);
+    }
+
+    #[test]
+    fn no_journey_once_the_last_trip_left() {
+        assert!(planner().plan(A, C, time(8, 31, 0), 3).is_empty());
+    }
+
+    #[test]
+    fn reachable_stops_are_within_the_duration() {
+        let reachable: HashMap<i32, i32> = planner().reachable(A, time(7, 55, 0), 30 * 60);
+
+        assert_eq!(reachable.get(&A), Some(&0));
+        assert_eq!(reachable.get(&B), Some(&(15 * 60)));
+        assert_eq!(reachable.get(&C), Some(&(25 * 60)));
+        assert!(reachable.get(&W).is_some_and(|duration| *duration < MIN_TRANSFER_TIME * 2));
+        assert!(!reachable.contains_key(&E));
+        assert!(!reachable.contains_key(&D));
+    }
+
+    #[test]
+    fn reachable_stops_include_the_trips_past_midnight() {
+        let reachable: HashMap<i32, i32> = planner().reachable(C, time(23, 45, 0), 30 * 60);
+
+        assert_eq!(reachable.get(&D), Some(&(25 * 60)));
+    }
+}
@@ -0,0 +1,411 @@
+use async_trait::async_trait;
+use chrono::NaiveDate;
+
+use crate::model::{
+    bitfield::Bitfield, direction::Direction, direction_leg::DirectionLeg, information::Information,
+    leg_step::LegStep, line::Line, shape::Shape, shape_point::ShapePoint, shape_stop::ShapeStop,
+    stop::Stop, trip::Trip, trip_stop::TripStop,
+};
+
+use super::{
+    database::{Database, RepositoryError, Table},
+    storage::{
+        Bounds, BoardFilter, DirectionRepository, LineRepository, ShapeRepository, StopRepository,
+        TripFilter, TripRepository,
+    },
+};
+
+#[async_trait]
+impl LineRepository for Database {
+    async fn get_lines(&self) -> Result<Vec<Line>, RepositoryError> {
+        self.get_all::<Line>(Line::TABLE_NAME).await
+    }
+
+    async fn get_line(&self, id: i32) -> Result<Line, RepositoryError> {
+        self.get_one::<Line>(
+            sqlx::query_as::<_, Line>(
+                format!("SELECT * FROM {} WHERE id=$1", Line::TABLE_NAME).as_str(),
+            )
+            .bind(id),
+        )
+        .await
+    }
+}
+
+#[async_trait]
+impl StopRepository for Database {
+    async fn get_stops(&self) -> Result<Vec<Stop>, RepositoryError> {
+        self.get_all::<Stop>(Stop::TABLE_NAME).await
+    }
+
+    async fn get_stop(&self, id: i32) -> Result<Stop, RepositoryError> {
+        self.get_one::<Stop>(
+            sqlx::query_as::<_, Stop>(
+                format!("SELECT * FROM {} WHERE id=$1", Stop::TABLE_NAME).as_str(),
+            )
+            .bind(id),
+        )
+        .await
+    }
+
+    async fn get_stops_within(&self, bounds: Bounds) -> Result<Vec<Stop>, RepositoryError> {
+        let condition: &str = if self.postgis() {
+            "geom && ST_MakeEnvelope($1, $2, $3, $4, 4326)"
+        } else {
+            "longitude BETWEEN $1 AND $3 AND latitude BETWEEN $2 AND $4"
+        };
+
+        self.get_many::<Stop>(
+            sqlx::query_as::<_, Stop>(
+                format!("SELECT * FROM {} WHERE {}", Stop::TABLE_NAME, condition).as_str(),
+            )
+            .bind(bounds.min_longitude)
+            .bind(bounds.min_latitude)
+            .bind(bounds.max_longitude)
+            .bind(bounds.max_latitude),
+        )
+        .await
+    }
+
+    async fn get_nearest_stops(
+        &self,
+        latitude: f64,
+        longitude: f64,
+        limit: i64,
+    ) -> Result<Vec<Stop>, RepositoryError> {
+        if self.postgis() {
+            // the gist index returns candidates by planar distance, they are ranked again in meters
+            return self
+                .get_many::<Stop>(
+                    sqlx::query_as::<_, Stop>(
+                        format!(
+                            "SELECT * FROM (SELECT * FROM {} ORDER BY geom <-> ST_SetSRID(ST_MakePoint($1, $2), 4326) LIMIT $3 * 4) AS candidates
+                            ORDER BY ST_Distance(geom::geography, ST_SetSRID(ST_MakePoint($1, $2), 4326)::geography) LIMIT $3",
+                            Stop::TABLE_NAME
+                        )
+                        .as_str(),
+                    )
+                    .bind(longitude)
+                    .bind(latitude)
+                    .bind(limit),
+                )
+                .await;
+        }
+
+        self.get_many::<Stop>(
+            sqlx::query_as::<_, Stop>(
+                format!(
+                    "SELECT * FROM {} ORDER BY POWER((longitude - $1) * $4, 2) + POWER(latitude - $2, 2) LIMIT $3",
+                    Stop::TABLE_NAME
+                )
+                .as_str(),
+            )
+            .bind(longitude)
+            .bind(latitude)
+            .bind(limit)
+            .bind(latitude.to_radians().cos()),
+        )
+        .await
+    }
+}
+
+impl Database {
+    // bitfields running on the day, a small lookup so that trips are then read from the
+    // (information_id, bitfield_id) index
+    async fn get_active_bitfield_ids(
+        &self,
+        information_id: i32,
+        day_index: i32,
+    ) -> Result<Vec<i32>, RepositoryError> {
+        let ids: Vec<i32> = sqlx::query_scalar(
+            format!(
+                "SELECT id FROM {} WHERE information_id = $1 AND SUBSTRING(days FROM $2 + 1 FOR 1) = B'1'",
+                Bitfield::TABLE_NAME
+            )
+            .as_str(),
+        )
+        .bind(information_id)
+        .bind(day_index)
+        .fetch_all(self.pool())
+        .await?;
+
+        Ok(ids)
+    }
+}
+
+#[async_trait]
+impl TripRepository for Database {
+    async fn get_information(&self, date: NaiveDate) -> Result<Information, RepositoryError> {
+        self.get_one::<Information>(
+            sqlx::query_as::<_, Information>(
+                format!(
+                    "SELECT * FROM {} WHERE start_date <= $1 AND end_date >= $1 ORDER BY start_date DESC LIMIT 1",
+                    Information::TABLE_NAME
+                )
+                .as_str(),
+            )
+            .bind(date),
+        )
+        .await
+    }
+
+    async fn get_information_by_id(&self, id: i32) -> Result<Information, RepositoryError> {
+        self.get_one::<Information>(
+            sqlx::query_as::<_, Information>(
+                format!("SELECT * FROM {} WHERE id=$1", Information::TABLE_NAME).as_str(),
+            )
+            .bind(id),
+        )
+        .await
+    }
+
+    async fn get_bitfield(&self, information_id: i32, id: i32) -> Result<Bitfield, RepositoryError> {
+        self.get_one::<Bitfield>(
+            sqlx::query_as::<_, Bitfield>(
+                format!(
+                    "SELECT id, information_id, days::TEXT AS days FROM {} WHERE information_id=$1 AND id=$2",
+                    Bitfield::TABLE_NAME
+                )
+                .as_str(),
+            )
+            .bind(information_id)
+            .bind(id),
+        )
+        .await
+    }
+
+    async fn get_trips(&self, filter: TripFilter) -> Result<Vec<Trip>, RepositoryError> {
+        let bitfield_ids: Vec<i32> = self
+            .get_active_bitfield_ids(filter.information_id, filter.day_index)
+            .await?;
+
+        self.get_many::<Trip>(
+            sqlx::query_as::<_, Trip>(
+                format!(
+                    "SELECT * FROM {} WHERE information_id = $4 AND departure_time <= $1 AND departure_time >= $3
+                    AND ((arrival_time < departure_time) = $6 AND arrival_time >= $2 OR NOT $6 AND arrival_time < departure_time)
+                    AND bitfield_id = ANY($5)",
+                    Trip::TABLE_NAME
+                )
+                .as_str(),
+            )
+            .bind(filter.departure_until)
+            .bind(filter.arrival_from)
+            .bind(filter.departure_from)
+            .bind(filter.information_id)
+            .bind(bitfield_ids)
+            .bind(filter.past_midnight),
+        )
+        .await
+    }
+
+    async fn get_trip(&self, id: i64) -> Result<Trip, RepositoryError> {
+        self.get_one::<Trip>(
+            sqlx::query_as::<_, Trip>(
+                format!("SELECT * FROM {} WHERE id=$1", Trip::TABLE_NAME).as_str(),
+            )
+            .bind(id),
+        )
+        .await
+    }
+
+    async fn get_trip_stops(&self, trip_id: i64) -> Result<Vec<TripStop>, RepositoryError> {
+        self.get_many::<TripStop>(
+            sqlx::query_as::<_, TripStop>(
+                format!("SELECT * FROM {} WHERE trip_id=$1", TripStop::TABLE_NAME).as_str(),
+            )
+            .bind(trip_id),
+        )
+        .await
+    }
+
+    async fn get_filtered_trip_stops(&self, filter: TripFilter) -> Result<Vec<TripStop>, RepositoryError> {
+        let bitfield_ids: Vec<i32> = self
+            .get_active_bitfield_ids(filter.information_id, filter.day_index)
+            .await?;
+
+        self.get_many::<TripStop>(
+            sqlx::query_as::<_, TripStop>(
+                format!(
+                    "SELECT * FROM {} WHERE trip_id IN (SELECT id FROM {} WHERE information_id = $4 AND departure_time <= $1 AND departure_time >= $3
+                    AND ((arrival_time < departure_time) = $6 AND arrival_time >= $2 OR NOT $6 AND arrival_time < departure_time)
+                    AND bitfield_id = ANY($5))",
+                    TripStop::TABLE_NAME,
+                    Trip::TABLE_NAME
+                )
+                .as_str(),
+            )
+            .bind(filter.departure_until)
+            .bind(filter.arrival_from)
+            .bind(filter.departure_from)
+            .bind(filter.information_id)
+            .bind(bitfield_ids)
+            .bind(filter.past_midnight),
+        )
+        .await
+    }
+
+    async fn get_departures(&self, filter: BoardFilter) -> Result<Vec<TripStop>, RepositoryError> {
+        let bitfield_ids: Vec<i32> = self
+            .get_active_bitfield_ids(filter.information_id, filter.day_index)
+            .await?;
+
+        self.get_many::<TripStop>(
+            sqlx::query_as::<_, TripStop>(
+                format!(
+                    "SELECT {0}.* FROM {0} JOIN {1} ON {1}.id = {0}.trip_id
+                    WHERE {0}.stop_id = $1 AND {0}.departure_time >= $2 AND {1}.information_id = $3
+                    AND {1}.bitfield_id = ANY($4)
+                    AND EXISTS (SELECT 1 FROM {0} AS next WHERE next.trip_id = {0}.trip_id AND next.sequence = {0}.sequence + 1)
+                    AND ({0}.departure_time < {1}.departure_time) = $6
+                    ORDER BY {0}.departure_time LIMIT $5",
+                    TripStop::TABLE_NAME,
+                    Trip::TABLE_NAME
+                )
+                .as_str(),
+            )
+            .bind(filter.stop_id)
+            .bind(filter.from)
+            .bind(filter.information_id)
+            .bind(&bitfield_ids)
+            .bind(filter.limit)
+            .bind(filter.past_midnight),
+        )
+        .await
+    }
+
+    async fn get_arrivals(&self, filter: BoardFilter) -> Result<Vec<TripStop>, RepositoryError> {
+        let bitfield_ids: Vec<i32> = self
+            .get_active_bitfield_ids(filter.information_id, filter.day_index)
+            .await?;
+
+        self.get_many::<TripStop>(
+            sqlx::query_as::<_, TripStop>(
+                format!(
+                    "SELECT {0}.* FROM {0} JOIN {1} ON {1}.id = {0}.trip_id
+                    WHERE {0}.stop_id = $1 AND {0}.arrival_time >= $2 AND {1}.information_id = $3
+                    AND {1}.bitfield_id = ANY($4)
+                    AND {0}.sequence > 1
+                    AND ({0}.arrival_time < {1}.departure_time) = $6
+                    ORDER BY {0}.arrival_time LIMIT $5",
+                    TripStop::TABLE_NAME,
+                    Trip::TABLE_NAME
+                )
+                .as_str(),
+            )
+            .bind(filter.stop_id)
+            .bind(filter.from)
+            .bind(filter.information_id)
+            .bind(&bitfield_ids)
+            .bind(filter.limit)
+            .bind(filter.past_midnight),
+        )
+        .await
+    }
+
+    // connections read the active dataset, see use_active_dataset
+    async fn get_dataset(&self) -> Result<Option<String>, RepositoryError> {
+        let schema: String = sqlx::query_scalar("SELECT current_schema()")
+            .fetch_one(self.pool())
+            .await
+            .map_err(RepositoryError::from)?;
+
+        Ok(Some(schema))
+    }
+}
+
+#[async_trait]
+impl DirectionRepository for Database {
+    async fn get_direction(&self, id: i64) -> Result<Direction, RepositoryError> {
+        self.get_one::<Direction>(
+            sqlx::query_as::<_, Direction>(
+                format!("SELECT * FROM {} WHERE id=$1", Direction::TABLE_NAME).as_str(),
+            )
+            .bind(id),
+        )
+        .await
+    }
+
+    async fn get_direction_legs(&self, direction_id: i64) -> Result<Vec<DirectionLeg>, RepositoryError> {
+        self.get_many::<DirectionLeg>(
+            sqlx::query_as::<_, DirectionLeg>(
+                format!("SELECT * FROM {} WHERE direction_id=$1", DirectionLeg::TABLE_NAME).as_str(),
+            )
+            .bind(direction_id),
+        )
+        .await
+    }
+
+    async fn get_leg(&self, id: i64) -> Result<DirectionLeg, RepositoryError> {
+        self.get_one::<DirectionLeg>(
+            sqlx::query_as::<_, DirectionLeg>(
+                format!("SELECT * FROM {} WHERE id=$1", DirectionLeg::TABLE_NAME).as_str(),
+            )
+            .bind(id),
+        )
+        .await
+    }
+
+    async fn get_leg_steps(&self, leg_id: i64) -> Result<Vec<LegStep>, RepositoryError> {
+        self.get_many::<LegStep>(
+            sqlx::query_as::<_, LegStep>(
+                format!("SELECT * FROM {} WHERE leg_id=$1", LegStep::TABLE_NAME).as_str(),
+            )
+            .bind(leg_id),
+        )
+        .await
+    }
+
+    async fn get_directions_legs(&self, direction_ids: &[i64]) -> Result<Vec<DirectionLeg>, RepositoryError> {
+        self.get_many::<DirectionLeg>(
+            sqlx::query_as::<_, DirectionLeg>(
+                format!("SELECT * FROM {} WHERE direction_id = ANY($1)", DirectionLeg::TABLE_NAME).as_str(),
+            )
+            .bind(direction_ids),
+        )
+        .await
+    }
+
+    async fn get_legs_steps(&self, leg_ids: &[i64]) -> Result<Vec<LegStep>, RepositoryError> {
+        self.get_many::<LegStep>(
+            sqlx::query_as::<_, LegStep>(
+                format!("SELECT * FROM {} WHERE leg_id = ANY($1)", LegStep::TABLE_NAME).as_str(),
+            )
+            .bind(leg_ids),
+        )
+        .await
+    }
+}
+
+#[async_trait]
+impl ShapeRepository for Database {
+    async fn get_shape(&self, id: i64) -> Result<Shape, RepositoryError> {
+        self.get_one::<Shape>(
+            sqlx::query_as::<_, Shape>(
+                format!("SELECT * FROM {} WHERE id=$1", Shape::TABLE_NAME).as_str(),
+            )
+            .bind(id),
+        )
+        .await
+    }
+
+    async fn get_shape_points(&self, shape_id: i64) -> Result<Vec<ShapePoint>, RepositoryError> {
+        self.get_many::<ShapePoint>(
+            sqlx::query_as::<_, ShapePoint>(
+                format!("SELECT * FROM {} WHERE shape_id=$1", ShapePoint::TABLE_NAME).as_str(),
+            )
+            .bind(shape_id),
+        )
+        .await
+    }
+
+    async fn get_shape_stops(&self, shape_id: i64) -> Result<Vec<ShapeStop>, RepositoryError> {
+        self.get_many::<ShapeStop>(
+            sqlx::query_as::<_, ShapeStop>(
+                format!("SELECT * FROM {} WHERE shape_id=$1", ShapeStop::TABLE_NAME).as_str(),
+            )
+            .bind(shape_id),
+        )
+        .await
+    }
+}
@@ -0,0 +1,129 @@
+use std::{
+    fmt,
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use actix_web::rt::time::sleep;
+use log::warn;
+
+use super::routing::RoutingError;
+
+// first retry delay, doubled on every further attempt
+const BASE_RETRY_DELAY: Duration = Duration::from_millis(500);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+pub struct QuotaStats {
+    pub requests: u64,
+    pub retries: u64,
+    pub budget: Option<u64>,
+}
+
+impl fmt::Display for QuotaStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} requests, {} retries", self.requests, self.retries)?;
+        match self.budget {
+            Some(budget) => write!(f, " (budget {})", budget),
+            None => Ok(()),
+        }
+    }
+}
+
+// spaces out the routing requests, retries the throttled and failed ones
+// and stops sending once the budget of the import is spent
+pub struct RequestQuota {
+    interval: Option<Duration>,
+    max_retries: u32,
+    budget: Option<u64>,
+    requests: AtomicU64,
+    retries: AtomicU64,
+    next_slot: Mutex<Instant>,
+}
+
+fn is_transient(error: &RoutingError) -> bool {
+    match error {
+        RoutingError::Status(status) => *status == 429 || (500..600).contains(status),
+        RoutingError::Overloaded(_) => true,
+        _ => false,
+    }
+}
+
+impl RequestQuota {
+    // rate in requests per second, none for no limit
+    pub fn new(rate: Option<f64>, max_retries: u32, budget: Option<u64>) -> RequestQuota {
+        RequestQuota {
+            interval: rate.map(|rate| Duration::from_secs_f64(1.0 / rate)),
+            max_retries,
+            budget,
+            requests: AtomicU64::new(0),
+            retries: AtomicU64::new(0),
+            next_slot: Mutex::new(Instant::now()),
+        }
+    }
+
+    pub fn budget(&self) -> Option<u64> {
+        self.budget
+    }
+
+    async fn wait_for_slot(&self) {
+        let interval: Duration = match self.interval {
+            Some(interval) => interval,
+            None => return,
+        };
+
+        let wait: Duration = {
+            let mut next_slot = self.next_slot.lock().unwrap();
+            let now: Instant = Instant::now();
+            let slot: Instant = (*next_slot).max(now);
+            *next_slot = slot + interval;
+            slot - now
+        };
+        if !wait.is_zero() {
+            sleep(wait).await;
+        }
+    }
+
+    pub async fn run<T, F, R>(&self, request: F) -> Result<T, RoutingError>
+    where
+        F: Fn() -> R,
+        R: Future<Output = Result<T, RoutingError>>,
+    {
+        let mut attempt: u32 = 0;
+        loop {
+            if let Some(budget) = self.budget {
+                // retries are billed too
+                if self.requests.fetch_add(1, Ordering::Relaxed) >= budget {
+                    self.requests.fetch_sub(1, Ordering::Relaxed);
+                    return Err(RoutingError::BudgetExceeded(budget));
+                }
+            } else {
+                self.requests.fetch_add(1, Ordering::Relaxed);
+            }
+
+            self.wait_for_slot().await;
+
+            match request().await {
+                Err(error) if is_transient(&error) && attempt < self.max_retries => {
+                    let delay: Duration = BASE_RETRY_DELAY.saturating_mul(2u32.saturating_pow(attempt)).min(MAX_RETRY_DELAY);
+                    warn!("Routing request failed ({}), retrying in {:?}", error, delay);
+                    self.retries.fetch_add(1, Ordering::Relaxed);
+                    sleep(delay).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    pub fn stats(&self) -> QuotaStats {
+        QuotaStats {
+            requests: self.requests.load(Ordering::Relaxed),
+            retries: self.retries.load(Ordering::Relaxed),
+            budget: self.budget,
+        }
+    }
+}
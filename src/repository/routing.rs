@@ -0,0 +1,625 @@
+use async_trait::async_trait;
+use derive_more::Display;
+use reqwest::Response;
+use serde::{Deserialize, Serialize};
+
+use crate::model::line::TransportMode;
+
+// mean radius of the earth, in meters
+const EARTH_RADIUS: f64 = 6_371_000.0;
+
+// encoded polylines keep 5 decimals, about a meter
+const POLYLINE_PRECISION: f64 = 1e5;
+
+#[derive(Debug, Display)]
+pub enum RoutingError {
+    #[display(fmt = "request error: {}", _0)]
+    Request(reqwest::Error),
+    #[display(fmt = "provider answered with status {}", _0)]
+    Status(u16),
+    #[display(fmt = "no route between the waypoints")]
+    NoRoute,
+    #[display(fmt = "network unavailable: {}", _0)]
+    Network(String),
+    #[display(fmt = "cache error: {}", _0)]
+    Cache(sqlx::Error),
+    #[display(fmt = "request budget of {} exhausted", _0)]
+    BudgetExceeded(u64),
+    #[display(fmt = "provider is overloaded: {}", _0)]
+    Overloaded(String),
+    #[display(fmt = "provider rejected the request: {}", _0)]
+    Rejected(String),
+}
+
+impl From<reqwest::Error> for RoutingError {
+    fn from(error: reqwest::Error) -> Self {
+        RoutingError::Request(error)
+    }
+}
+
+impl From<sqlx::Error> for RoutingError {
+    fn from(error: sqlx::Error) -> Self {
+        RoutingError::Cache(error)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy)]
+pub struct Location {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct RoutedStep {
+    pub distance: i32,
+    pub duration: i32,
+    pub start: Location,
+    pub end: Location,
+    // geometry of the step, start and end included
+    pub points: Vec<Location>,
+}
+
+// route between two consecutive waypoints, distance in meters and duration in seconds
+pub struct RoutedLeg {
+    pub distance: i32,
+    pub duration: i32,
+    pub steps: Vec<RoutedStep>,
+}
+
+// point of a path snapped to the network, original_index is set for the points of the input path
+pub struct SnappedLocation {
+    pub location: Location,
+    pub original_index: Option<usize>,
+}
+
+#[async_trait]
+pub trait RoutingProvider: Send + Sync {
+    // identifies the provider and its settings in the routing cache
+    fn name(&self) -> String;
+    // most waypoints accepted by a single route request, origin and destination included
+    fn max_waypoints(&self) -> usize;
+    // most points accepted by a single snap request
+    fn max_snap_points(&self) -> usize;
+    // one leg per pair of consecutive waypoints, providers without networks per mode ignore it
+    async fn route(&self, mode: TransportMode, waypoints: &[Location]) -> Result<Vec<RoutedLeg>, RoutingError>;
+    async fn snap(&self, mode: TransportMode, path: &[Location]) -> Result<Vec<SnappedLocation>, RoutingError>;
+}
+
+fn format_position(location: &Location) -> String {
+    return format!("{},{}", location.latitude, location.longitude);
+}
+
+// great-circle distance in meters
+pub fn haversine(from: &Location, to: &Location) -> f64 {
+    let d_latitude: f64 = (to.latitude - from.latitude).to_radians();
+    let d_longitude: f64 = (to.longitude - from.longitude).to_radians();
+    let a: f64 = (d_latitude / 2.0).sin().powi(2)
+        + from.latitude.to_radians().cos()
+            * to.latitude.to_radians().cos()
+            * (d_longitude / 2.0).sin().powi(2);
+
+    return 2.0 * EARTH_RADIUS * a.sqrt().asin();
+}
+
+// initial great-circle bearing in degrees, clockwise from north
+pub fn bearing(from: &Location, to: &Location) -> f64 {
+    let d_longitude: f64 = (to.longitude - from.longitude).to_radians();
+    let y: f64 = d_longitude.sin() * to.latitude.to_radians().cos();
+    let x: f64 = from.latitude.to_radians().cos() * to.latitude.to_radians().sin()
+        - from.latitude.to_radians().sin() * to.latitude.to_radians().cos() * d_longitude.cos();
+
+    return (y.atan2(x).to_degrees() + 360.0) % 360.0;
+}
+
+fn encode_polyline_value(value: i64, encoded: &mut String) {
+    let mut value: i64 = if value < 0 { !(value << 1) } else { value << 1 };
+    while value >= 0x20 {
+        encoded.push((((value & 0x1f) | 0x20) as u8 + 63) as char);
+        value >>= 5;
+    }
+    encoded.push((value as u8 + 63) as char);
+}
+
+// google encoded polyline algorithm format
+pub fn encode_polyline(points: &[Location]) -> String {
+    let mut encoded: String = String::new();
+    let mut previous: (i64, i64) = (0, 0);
+    for point in points {
+        let current: (i64, i64) = (
+            (point.latitude * POLYLINE_PRECISION).round() as i64,
+            (point.longitude * POLYLINE_PRECISION).round() as i64,
+        );
+        encode_polyline_value(current.0 - previous.0, &mut encoded);
+        encode_polyline_value(current.1 - previous.1, &mut encoded);
+        previous = current;
+    }
+
+    return encoded;
+}
+
+// a truncated polyline keeps the points decoded so far
+pub fn decode_polyline(encoded: &str) -> Vec<Location> {
+    let mut points: Vec<Location> = Vec::new();
+    let mut bytes = encoded.bytes();
+    let mut next_value = || -> Option<i64> {
+        let mut value: i64 = 0;
+        let mut shift: u32 = 0;
+        loop {
+            let byte: i64 = (bytes.next()? as i64) - 63;
+            value |= (byte & 0x1f) << shift;
+            shift += 5;
+            if byte < 0x20 || shift > 60 {
+                break;
+            }
+        }
+        Some(if value & 1 == 1 { !(value >> 1) } else { value >> 1 })
+    };
+
+    let mut current: (i64, i64) = (0, 0);
+    while let (Some(d_latitude), Some(d_longitude)) = (next_value(), next_value()) {
+        current = (current.0 + d_latitude, current.1 + d_longitude);
+        points.push(Location {
+            latitude: current.0 as f64 / POLYLINE_PRECISION,
+            longitude: current.1 as f64 / POLYLINE_PRECISION,
+        });
+    }
+
+    return points;
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct DirectionResponse {
+    status: String,
+    error_message: Option<String>,
+    routes: Vec<RouteResponse>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct RouteResponse {
+    bounds: Bounds,
+    legs: Vec<LegResponse>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct LegResponse {
+    distance: Distance,
+    duration: Duration,
+    start_location: Position,
+    end_location: Position,
+    steps: Vec<StepResponse>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct StepResponse {
+    distance: Distance,
+    duration: Duration,
+    start_location: Position,
+    end_location: Position,
+    polyline: PolylineResponse,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct PolylineResponse {
+    points: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Distance {
+    text: String,
+    value: i32,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Duration {
+    text: String,
+    value: i32,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Bounds {
+    northeast: Position,
+    southwest: Position,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Position {
+    lat: f64,
+    lng: f64,
+}
+
+impl From<&Position> for Location {
+    fn from(position: &Position) -> Self {
+        Location {
+            latitude: position.lat,
+            longitude: position.lng,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RoadResponse {
+    pub snappedPoints: Vec<SnappedPoint>,
+    pub warningMessage: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SnappedPoint {
+    pub location: Location,
+    pub originalIndex: Option<i32>,
+    pub placeId: String,
+}
+
+// google directions and roads apis
+pub struct GoogleProvider {
+    pub api_key: String,
+}
+
+#[async_trait]
+impl RoutingProvider for GoogleProvider {
+    fn name(&self) -> String {
+        "google".to_string()
+    }
+
+    fn max_waypoints(&self) -> usize {
+        25
+    }
+
+    fn max_snap_points(&self) -> usize {
+        100
+    }
+
+    async fn route(&self, _mode: TransportMode, waypoints: &[Location]) -> Result<Vec<RoutedLeg>, RoutingError> {
+        let max: usize = waypoints.len();
+        let res: Response = reqwest::get(format!(
+            "https://maps.googleapis.com/maps/api/directions/json?key={}&origin={}&destination={}&waypoints={}",
+            self.api_key,
+            format_position(&waypoints[0]),
+            format_position(waypoints.last().unwrap()),
+            if max > 1 { waypoints[1..max-1].iter().map(format_position).collect::<Vec<String>>().join("|") } else { "".to_string() }
+        ))
+        .await?;
+
+        if !res.status().is_success() {
+            return Err(RoutingError::Status(res.status().as_u16()));
+        }
+
+        // errors come back as 200 with a status in the body
+        let direction_response: DirectionResponse = res.json::<DirectionResponse>().await?;
+        match direction_response.status.as_str() {
+            "OK" => {}
+            "ZERO_RESULTS" | "NOT_FOUND" => return Err(RoutingError::NoRoute),
+            "OVER_QUERY_LIMIT" | "UNKNOWN_ERROR" => {
+                return Err(RoutingError::Overloaded(direction_response.status))
+            }
+            _ => {
+                return Err(RoutingError::Rejected(match direction_response.error_message {
+                    Some(message) => format!("{}: {}", direction_response.status, message),
+                    None => direction_response.status,
+                }))
+            }
+        }
+        let route_response: &RouteResponse = direction_response
+            .routes
+            .first()
+            .ok_or(RoutingError::NoRoute)?;
+
+        Ok(route_response
+            .legs
+            .iter()
+            .map(|leg| RoutedLeg {
+                distance: leg.distance.value,
+                duration: leg.duration.value,
+                steps: leg
+                    .steps
+                    .iter()
+                    .map(|step| RoutedStep {
+                        distance: step.distance.value,
+                        duration: step.duration.value,
+                        start: Location::from(&step.start_location),
+                        end: Location::from(&step.end_location),
+                        points: decode_polyline(&step.polyline.points),
+                    })
+                    .collect(),
+            })
+            .collect())
+    }
+
+    async fn snap(&self, _mode: TransportMode, path: &[Location]) -> Result<Vec<SnappedLocation>, RoutingError> {
+        let res: Response = reqwest::get(format!(
+            "https://roads.googleapis.com/v1/snapToRoads?interpolate=true&key={}&path={}",
+            self.api_key,
+            path.iter().map(format_position).collect::<Vec<String>>().join("|")
+        ))
+        .await?;
+
+        if !res.status().is_success() {
+            return Err(RoutingError::Status(res.status().as_u16()));
+        }
+
+        Ok(res
+            .json::<RoadResponse>()
+            .await?
+            .snappedPoints
+            .into_iter()
+            .map(|point| SnappedLocation {
+                location: point.location,
+                original_index: point.originalIndex.map(|i| i as usize),
+            })
+            .collect())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct OsrmRouteResponse {
+    code: String,
+    routes: Option<Vec<OsrmRoute>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OsrmRoute {
+    legs: Vec<OsrmLeg>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OsrmLeg {
+    distance: f64,
+    duration: f64,
+    steps: Vec<OsrmStep>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OsrmStep {
+    distance: f64,
+    duration: f64,
+    geometry: OsrmGeometry,
+}
+
+// geojson line string, [longitude, latitude] pairs
+#[derive(Debug, Deserialize)]
+struct OsrmGeometry {
+    coordinates: Vec<[f64; 2]>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OsrmMatchResponse {
+    code: String,
+    tracepoints: Option<Vec<Option<OsrmTracepoint>>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OsrmTracepoint {
+    location: [f64; 2],
+}
+
+fn osrm_location(coordinates: &[f64; 2]) -> Location {
+    Location {
+        latitude: coordinates[1],
+        longitude: coordinates[0],
+    }
+}
+
+// self-hosted osrm-routed http endpoint, profile is the one the server was built with
+pub struct OsrmProvider {
+    pub url: String,
+    pub profile: String,
+}
+
+impl OsrmProvider {
+    fn coordinates(path: &[Location]) -> String {
+        path.iter()
+            .map(|location| format!("{},{}", location.longitude, location.latitude))
+            .collect::<Vec<String>>()
+            .join(";")
+    }
+}
+
+#[async_trait]
+impl RoutingProvider for OsrmProvider {
+    fn name(&self) -> String {
+        format!("osrm:{}", self.profile)
+    }
+
+    // default --max-viaroute-size of osrm-routed
+    fn max_waypoints(&self) -> usize {
+        500
+    }
+
+    // default --max-matching-size of osrm-routed
+    fn max_snap_points(&self) -> usize {
+        100
+    }
+
+    async fn route(&self, _mode: TransportMode, waypoints: &[Location]) -> Result<Vec<RoutedLeg>, RoutingError> {
+        let res: Response = reqwest::get(format!(
+            "{}/route/v1/{}/{}?steps=true&overview=false&geometries=geojson",
+            self.url.trim_end_matches('/'),
+            self.profile,
+            OsrmProvider::coordinates(waypoints)
+        ))
+        .await?;
+
+        if !res.status().is_success() {
+            return Err(RoutingError::Status(res.status().as_u16()));
+        }
+
+        let route_response: OsrmRouteResponse = res.json::<OsrmRouteResponse>().await?;
+        if route_response.code != "Ok" {
+            return Err(RoutingError::NoRoute);
+        }
+        let route: OsrmRoute = route_response
+            .routes
+            .and_then(|routes| routes.into_iter().next())
+            .ok_or(RoutingError::NoRoute)?;
+
+        Ok(route
+            .legs
+            .iter()
+            .map(|leg| RoutedLeg {
+                distance: leg.distance.round() as i32,
+                duration: leg.duration.round() as i32,
+                steps: leg
+                    .steps
+                    .iter()
+                    // the arrival step has no length
+                    .filter(|step| step.geometry.coordinates.len() > 1)
+                    .map(|step| RoutedStep {
+                        distance: step.distance.round() as i32,
+                        duration: step.duration.round() as i32,
+                        start: osrm_location(step.geometry.coordinates.first().unwrap()),
+                        end: osrm_location(step.geometry.coordinates.last().unwrap()),
+                        points: step.geometry.coordinates.iter().map(osrm_location).collect(),
+                    })
+                    .collect(),
+            })
+            .collect())
+    }
+
+    async fn snap(&self, _mode: TransportMode, path: &[Location]) -> Result<Vec<SnappedLocation>, RoutingError> {
+        let res: Response = reqwest::get(format!(
+            "{}/match/v1/{}/{}?overview=false",
+            self.url.trim_end_matches('/'),
+            self.profile,
+            OsrmProvider::coordinates(path)
+        ))
+        .await?;
+
+        if !res.status().is_success() {
+            return Err(RoutingError::Status(res.status().as_u16()));
+        }
+
+        let match_response: OsrmMatchResponse = res.json::<OsrmMatchResponse>().await?;
+        if match_response.code != "Ok" {
+            return Err(RoutingError::NoRoute);
+        }
+
+        // points that could not be matched are left out
+        Ok(match_response
+            .tracepoints
+            .unwrap_or_default()
+            .iter()
+            .enumerate()
+            .filter_map(|(i, tracepoint)| {
+                tracepoint.as_ref().map(|tracepoint| SnappedLocation {
+                    location: osrm_location(&tracepoint.location),
+                    original_index: Some(i),
+                })
+            })
+            .collect())
+    }
+}
+
+// no network at all, legs follow the great circle between the waypoints
+pub struct StraightLineProvider {
+    // in km/h
+    pub speed: f64,
+    // ratio between the road distance and the great-circle distance
+    pub detour_factor: f64,
+}
+
+impl Default for StraightLineProvider {
+    fn default() -> Self {
+        StraightLineProvider {
+            speed: 20.0,
+            detour_factor: 1.3,
+        }
+    }
+}
+
+#[async_trait]
+impl RoutingProvider for StraightLineProvider {
+    fn name(&self) -> String {
+        "straight".to_string()
+    }
+
+    fn max_waypoints(&self) -> usize {
+        usize::MAX
+    }
+
+    fn max_snap_points(&self) -> usize {
+        usize::MAX
+    }
+
+    async fn route(&self, _mode: TransportMode, waypoints: &[Location]) -> Result<Vec<RoutedLeg>, RoutingError> {
+        Ok(waypoints
+            .windows(2)
+            .map(|pair| {
+                let distance: f64 = haversine(&pair[0], &pair[1]) * self.detour_factor;
+                let duration: f64 = distance / (self.speed / 3.6);
+                RoutedLeg {
+                    distance: distance.round() as i32,
+                    duration: duration.round() as i32,
+                    steps: vec![RoutedStep {
+                        distance: distance.round() as i32,
+                        duration: duration.round() as i32,
+                        start: pair[0],
+                        end: pair[1],
+                        points: pair.to_vec(),
+                    }],
+                }
+            })
+            .collect())
+    }
+
+    async fn snap(&self, _mode: TransportMode, path: &[Location]) -> Result<Vec<SnappedLocation>, RoutingError> {
+        Ok(path
+            .iter()
+            .enumerate()
+            .map(|(i, location)| SnappedLocation {
+                location: *location,
+                original_index: Some(i),
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // example of the google polyline algorithm documentation
+    const REFERENCE: &str = "_p~iF~ps|U_ulLnnqC_mqNvxq`@";
+
+    fn reference_points() -> Vec<Location> {
+        vec![
+            Location { latitude: 38.5, longitude: -120.2 },
+            Location { latitude: 40.7, longitude: -120.95 },
+            Location { latitude: 43.252, longitude: -126.453 },
+        ]
+    }
+
+    #[test]
+    fn encodes_the_reference_polyline() {
+        assert_eq!(encode_polyline(&reference_points()), REFERENCE);
+    }
+
+    #[test]
+    fn decodes_the_reference_polyline() {
+        let points: Vec<Location> = decode_polyline(REFERENCE);
+
+        assert_eq!(points.len(), 3);
+        for (point, expected) in points.iter().zip(reference_points()) {
+            assert!((point.latitude - expected.latitude).abs() < 1e-9);
+            assert!((point.longitude - expected.longitude).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn truncated_polylines_keep_the_decoded_points() {
+        assert_eq!(decode_polyline(&REFERENCE[..REFERENCE.len() - 2]).len(), 2);
+        assert!(decode_polyline("").is_empty());
+    }
+
+    #[test]
+    fn round_trips_at_five_decimals() {
+        let points: Vec<Location> = vec![
+            Location { latitude: 46.209735, longitude: 6.142438 },
+            Location { latitude: 46.200789, longitude: 6.129694 },
+        ];
+
+        let decoded: Vec<Location> = decode_polyline(&encode_polyline(&points));
+        for (point, expected) in decoded.iter().zip(&points) {
+            assert!((point.latitude - expected.latitude).abs() <= 0.5e-5);
+            assert!((point.longitude - expected.longitude).abs() <= 0.5e-5);
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use std::{
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use log::info;
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions, SqliteRow},
+    Error, Row,
+};
+
+use crate::model::line::TransportMode;
+
+use super::routing::{RoutedLeg, RoutedStep};
+
+const CACHE_SCHEMA: &str = "CREATE TABLE IF NOT EXISTS routed_legs (
+    provider TEXT NOT NULL,
+    mode TEXT NOT NULL,
+    origin_id INTEGER NOT NULL,
+    destination_id INTEGER NOT NULL,
+    distance INTEGER NOT NULL,
+    duration INTEGER NOT NULL,
+    steps TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (provider, mode, origin_id, destination_id)
+)";
+
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lookups: u64 = self.hits + self.misses;
+        let rate: f64 = if lookups == 0 {
+            0.0
+        } else {
+            100.0 * self.hits as f64 / lookups as f64
+        };
+        write!(f, "{} hits, {} misses ({:.1}% hit rate)", self.hits, self.misses, rate)
+    }
+}
+
+// routed legs between two stops, kept in a local sqlite file across directions and imports
+pub struct RoutingCache {
+    pool: SqlitePool,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl RoutingCache {
+    pub async fn open(path: &str) -> Result<RoutingCache, Error> {
+        let pool: SqlitePool = SqlitePoolOptions::new()
+            .connect_with(
+                SqliteConnectOptions::new()
+                    .filename(path)
+                    .create_if_missing(true),
+            )
+            .await?;
+        sqlx::query(CACHE_SCHEMA).execute(&pool).await?;
+        info!("Opened routing cache {}", path);
+
+        Ok(RoutingCache {
+            pool,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        })
+    }
+
+    pub async fn get(
+        &self,
+        provider: &str,
+        mode: TransportMode,
+        origin_id: i32,
+        destination_id: i32,
+    ) -> Result<Option<RoutedLeg>, Error> {
+        let row: Option<SqliteRow> = sqlx::query(
+            "SELECT distance, duration, steps FROM routed_legs WHERE provider=?1 AND mode=?2 AND origin_id=?3 AND destination_id=?4",
+        )
+        .bind(provider)
+        .bind(format!("{:?}", mode))
+        .bind(origin_id)
+        .bind(destination_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        // entries that no longer decode are routed again
+        let leg: Option<RoutedLeg> = row.and_then(|row| {
+            let steps: Vec<RoutedStep> = serde_json::from_str(row.get::<&str, _>("steps")).ok()?;
+            Some(RoutedLeg {
+                distance: row.get("distance"),
+                duration: row.get("duration"),
+                steps,
+            })
+        });
+
+        if leg.is_some() {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+        }
+
+        Ok(leg)
+    }
+
+    pub async fn put(
+        &self,
+        provider: &str,
+        mode: TransportMode,
+        origin_id: i32,
+        destination_id: i32,
+        leg: &RoutedLeg,
+    ) -> Result<(), Error> {
+        sqlx::query(
+            "INSERT OR REPLACE INTO routed_legs (provider,mode,origin_id,destination_id,distance,duration,steps) VALUES (?1,?2,?3,?4,?5,?6,?7)",
+        )
+        .bind(provider)
+        .bind(format!("{:?}", mode))
+        .bind(origin_id)
+        .bind(destination_id)
+        .bind(leg.distance)
+        .bind(leg.duration)
+        .bind(serde_json::to_string(&leg.steps).unwrap())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+}
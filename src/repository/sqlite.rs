@@ -0,0 +1,452 @@
+use std::cmp;
+
+use async_trait::async_trait;
+use chrono::NaiveDate;
+use log::info;
+use sqlx::query::QueryAs;
+use sqlx::sqlite::{SqliteArguments, SqliteConnectOptions, SqlitePool, SqlitePoolOptions, SqliteRow};
+use sqlx::{Error, QueryBuilder, Sqlite};
+
+use crate::model::{
+    bitfield::Bitfield, direction::Direction, direction_leg::DirectionLeg, information::Information,
+    leg_step::LegStep, line::Line, shape::Shape, shape_point::ShapePoint, shape_stop::ShapeStop,
+    stop::Stop, trip::Trip, trip_stop::TripStop,
+};
+
+use super::{
+    database::{RepositoryError, Table, Value},
+    storage::{
+        Bounds, BoardFilter, DirectionRepository, LineRepository, ShapeRepository, StopRepository,
+        TripFilter, TripRepository,
+    },
+};
+
+// SQLITE_MAX_VARIABLE_NUMBER of the bundled sqlite
+const MAX_BIND_PARAMETERS: usize = 32766;
+
+// embedded backend reading a single database file, versioned by the sqlite migrator, no datasets
+#[derive(Clone)]
+pub struct SqliteDatabase {
+    pool: SqlitePool,
+}
+
+impl SqliteDatabase {
+    pub async fn init(path: &str) -> Result<SqliteDatabase, Error> {
+        let pool: SqlitePool = SqlitePoolOptions::new()
+            .connect_with(
+                SqliteConnectOptions::new()
+                    .filename(path)
+                    .create_if_missing(true),
+            )
+            .await?;
+        info!("Opened sqlite database {}", path);
+
+        Ok(SqliteDatabase { pool })
+    }
+
+    pub fn pool(&self) -> &SqlitePool {
+        &self.pool
+    }
+
+    pub async fn get_many<'q, T>(
+        &self,
+        query: QueryAs<'q, Sqlite, T, SqliteArguments<'q>>,
+    ) -> Result<Vec<T>, RepositoryError>
+    where
+        T: for<'r> sqlx::FromRow<'r, SqliteRow> + Send + Unpin,
+    {
+        let res: Result<Vec<T>, Error> = query.fetch_all(&self.pool).await;
+
+        return res.map_err(RepositoryError::from);
+    }
+
+    pub async fn get_one<'q, T>(
+        &self,
+        query: QueryAs<'q, Sqlite, T, SqliteArguments<'q>>,
+    ) -> Result<T, RepositoryError>
+    where
+        T: for<'r> sqlx::FromRow<'r, SqliteRow> + Send + Unpin,
+    {
+        let res: Result<T, Error> = query.fetch_one(&self.pool).await;
+
+        return res.map_err(RepositoryError::from);
+    }
+
+    pub async fn get_all<T>(&self, table_name: &str) -> Result<Vec<T>, RepositoryError>
+    where
+        T: for<'r> sqlx::FromRow<'r, SqliteRow> + Send + Unpin,
+    {
+        let query = format!("SELECT * FROM {}", table_name);
+        let res: Result<Vec<T>, Error> = sqlx::query_as::<_, T>(&query).fetch_all(&self.pool).await;
+
+        return res.map_err(RepositoryError::from);
+    }
+
+    pub async fn insert_many<T>(&self, data: &Vec<T>) -> Result<u64, Error>
+    where
+        T: serde::Serialize + Table,
+    {
+        let mut rows_affected: u64 = 0;
+
+        for mut builder in insert_queries(T::TABLE_NAME, data) {
+            builder.push(" ON CONFLICT DO NOTHING");
+            rows_affected += builder.build().execute(&self.pool).await?.rows_affected();
+        }
+
+        Ok(rows_affected)
+    }
+}
+
+// multi-row inserts of data into table_name, split to respect the bind parameter limit
+pub fn insert_queries<T: Table>(table_name: &str, data: &[T]) -> Vec<QueryBuilder<'static, Sqlite>> {
+    if data.is_empty() {
+        return Vec::new();
+    }
+
+    let columns: usize = data[0].values().len();
+    let rows_per_query: usize = cmp::max(1, MAX_BIND_PARAMETERS / columns);
+
+    data.chunks(rows_per_query)
+        .map(|chunk| {
+            let mut builder: QueryBuilder<Sqlite> =
+                QueryBuilder::new(format!("INSERT INTO {} {} ", table_name, T::keys()));
+
+            builder.push_values(chunk, |mut row, d| {
+                for value in d.values() {
+                    match value {
+                        Value::Integer(v) => row.push_bind(v),
+                        Value::BigInt(v) => row.push_bind(v),
+                        Value::SmallInt(v) => row.push_bind(v),
+                        Value::Double(v) => row.push_bind(v),
+                        Value::Boolean(v) => row.push_bind(v),
+                        Value::Text(v) | Value::Bits(v) => row.push_bind(v),
+                        Value::Date(v) => row.push_bind(v),
+                        Value::Time(v) => row.push_bind(v),
+                        Value::Integers(v) => {
+                            row.push_bind(v.and_then(|v| serde_json::to_string(&v).ok()))
+                        }
+                    };
+                }
+            });
+
+            builder
+        })
+        .collect()
+}
+
+#[async_trait]
+impl LineRepository for SqliteDatabase {
+    async fn get_lines(&self) -> Result<Vec<Line>, RepositoryError> {
+        self.get_all::<Line>(Line::TABLE_NAME).await
+    }
+
+    async fn get_line(&self, id: i32) -> Result<Line, RepositoryError> {
+        self.get_one::<Line>(
+            sqlx::query_as::<_, Line>(
+                format!("SELECT * FROM {} WHERE id=?", Line::TABLE_NAME).as_str(),
+            )
+            .bind(id),
+        )
+        .await
+    }
+}
+
+#[async_trait]
+impl StopRepository for SqliteDatabase {
+    async fn get_stops(&self) -> Result<Vec<Stop>, RepositoryError> {
+        self.get_all::<Stop>(Stop::TABLE_NAME).await
+    }
+
+    async fn get_stop(&self, id: i32) -> Result<Stop, RepositoryError> {
+        self.get_one::<Stop>(
+            sqlx::query_as::<_, Stop>(
+                format!("SELECT * FROM {} WHERE id=?", Stop::TABLE_NAME).as_str(),
+            )
+            .bind(id),
+        )
+        .await
+    }
+
+    async fn get_stops_within(&self, bounds: Bounds) -> Result<Vec<Stop>, RepositoryError> {
+        self.get_many::<Stop>(
+            sqlx::query_as::<_, Stop>(
+                format!(
+                    "SELECT * FROM {} WHERE longitude BETWEEN ?1 AND ?3 AND latitude BETWEEN ?2 AND ?4",
+                    Stop::TABLE_NAME
+                )
+                .as_str(),
+            )
+            .bind(bounds.min_longitude)
+            .bind(bounds.min_latitude)
+            .bind(bounds.max_longitude)
+            .bind(bounds.max_latitude),
+        )
+        .await
+    }
+
+    async fn get_nearest_stops(
+        &self,
+        latitude: f64,
+        longitude: f64,
+        limit: i64,
+    ) -> Result<Vec<Stop>, RepositoryError> {
+        self.get_many::<Stop>(
+            sqlx::query_as::<_, Stop>(
+                format!(
+                    "SELECT * FROM {} ORDER BY ((longitude - ?1) * ?4) * ((longitude - ?1) * ?4) + (latitude - ?2) * (latitude - ?2) LIMIT ?3",
+                    Stop::TABLE_NAME
+                )
+                .as_str(),
+            )
+            .bind(longitude)
+            .bind(latitude)
+            .bind(limit)
+            .bind(latitude.to_radians().cos()),
+        )
+        .await
+    }
+}
+
+#[async_trait]
+impl TripRepository for SqliteDatabase {
+    async fn get_information(&self, date: NaiveDate) -> Result<Information, RepositoryError> {
+        self.get_one::<Information>(
+            sqlx::query_as::<_, Information>(
+                format!(
+                    "SELECT * FROM {} WHERE start_date <= ?1 AND end_date >= ?1 ORDER BY start_date DESC LIMIT 1",
+                    Information::TABLE_NAME
+                )
+                .as_str(),
+            )
+            .bind(date),
+        )
+        .await
+    }
+
+    async fn get_information_by_id(&self, id: i32) -> Result<Information, RepositoryError> {
+        self.get_one::<Information>(
+            sqlx::query_as::<_, Information>(
+                format!("SELECT * FROM {} WHERE id=?1", Information::TABLE_NAME).as_str(),
+            )
+            .bind(id),
+        )
+        .await
+    }
+
+    async fn get_bitfield(&self, information_id: i32, id: i32) -> Result<Bitfield, RepositoryError> {
+        self.get_one::<Bitfield>(
+            sqlx::query_as::<_, Bitfield>(
+                format!(
+                    "SELECT id, information_id, days FROM {} WHERE information_id=?1 AND id=?2",
+                    Bitfield::TABLE_NAME
+                )
+                .as_str(),
+            )
+            .bind(information_id)
+            .bind(id),
+        )
+        .await
+    }
+
+    async fn get_trips(&self, filter: TripFilter) -> Result<Vec<Trip>, RepositoryError> {
+        self.get_many::<Trip>(sqlx::query_as::<_, Trip>(format!("SELECT trips.id, trips.journey_number, trips.option_count, trips.shape_id, trips.direction_id, trips.transport_mode, trips.origin_id, trips.destination_id, trips.information_id, trips.bitfield_id, trips.line_id, trips.direction, trips.departure_time, trips.arrival_time FROM {} JOIN bitfields ON bitfield_id = bitfields.id AND trips.information_id = bitfields.information_id WHERE trips.information_id = ?5 AND departure_time <= ?1 AND departure_time >= ?4 AND ((arrival_time < departure_time) = ?6 AND arrival_time >= ?2 OR NOT ?6 AND arrival_time < departure_time) AND SUBSTR(days,?3 + 1,1) = '1'", Trip::TABLE_NAME).as_str()).bind(filter.departure_until).bind(filter.arrival_from).bind(filter.day_index).bind(filter.departure_from).bind(filter.information_id).bind(filter.past_midnight)).await
+    }
+
+    async fn get_trip(&self, id: i64) -> Result<Trip, RepositoryError> {
+        self.get_one::<Trip>(
+            sqlx::query_as::<_, Trip>(
+                format!("SELECT * FROM {} WHERE id=?", Trip::TABLE_NAME).as_str(),
+            )
+            .bind(id),
+        )
+        .await
+    }
+
+    async fn get_trip_stops(&self, trip_id: i64) -> Result<Vec<TripStop>, RepositoryError> {
+        self.get_many::<TripStop>(
+            sqlx::query_as::<_, TripStop>(
+                format!("SELECT * FROM {} WHERE trip_id=?", TripStop::TABLE_NAME).as_str(),
+            )
+            .bind(trip_id),
+        )
+        .await
+    }
+
+    async fn get_filtered_trip_stops(&self, filter: TripFilter) -> Result<Vec<TripStop>, RepositoryError> {
+        self.get_many::<TripStop>(
+            sqlx::query_as::<_, TripStop>(
+                format!(
+                    "SELECT {0}.* FROM {0} JOIN {1} ON {1}.id = {0}.trip_id
+                    JOIN {2} ON {2}.id = {1}.bitfield_id AND {2}.information_id = {1}.information_id
+                    WHERE {1}.information_id = ?5 AND {1}.departure_time <= ?1 AND {1}.departure_time >= ?4 AND SUBSTR(days, ?3 + 1, 1) = '1'
+                    AND (({1}.arrival_time < {1}.departure_time) = ?6 AND {1}.arrival_time >= ?2 OR NOT ?6 AND {1}.arrival_time < {1}.departure_time)",
+                    TripStop::TABLE_NAME,
+                    Trip::TABLE_NAME,
+                    Bitfield::TABLE_NAME
+                )
+                .as_str(),
+            )
+            .bind(filter.departure_until)
+            .bind(filter.arrival_from)
+            .bind(filter.day_index)
+            .bind(filter.departure_from)
+            .bind(filter.information_id)
+            .bind(filter.past_midnight),
+        )
+        .await
+    }
+
+    async fn get_departures(&self, filter: BoardFilter) -> Result<Vec<TripStop>, RepositoryError> {
+        self.get_many::<TripStop>(
+            sqlx::query_as::<_, TripStop>(
+                format!(
+                    "SELECT {0}.* FROM {0} JOIN {1} ON {1}.id = {0}.trip_id
+                    JOIN {2} ON {2}.id = {1}.bitfield_id AND {2}.information_id = {1}.information_id
+                    WHERE {0}.stop_id = ?1 AND {0}.departure_time >= ?2 AND {1}.information_id = ?3 AND SUBSTR(days, ?4 + 1, 1) = '1'
+                    AND EXISTS (SELECT 1 FROM {0} AS next WHERE next.trip_id = {0}.trip_id AND next.sequence = {0}.sequence + 1)
+                    AND ({0}.departure_time < {1}.departure_time) = ?6
+                    ORDER BY {0}.departure_time LIMIT ?5",
+                    TripStop::TABLE_NAME,
+                    Trip::TABLE_NAME,
+                    Bitfield::TABLE_NAME
+                )
+                .as_str(),
+            )
+            .bind(filter.stop_id)
+            .bind(filter.from)
+            .bind(filter.information_id)
+            .bind(filter.day_index)
+            .bind(filter.limit)
+            .bind(filter.past_midnight),
+        )
+        .await
+    }
+
+    async fn get_arrivals(&self, filter: BoardFilter) -> Result<Vec<TripStop>, RepositoryError> {
+        self.get_many::<TripStop>(
+            sqlx::query_as::<_, TripStop>(
+                format!(
+                    "SELECT {0}.* FROM {0} JOIN {1} ON {1}.id = {0}.trip_id
+                    JOIN {2} ON {2}.id = {1}.bitfield_id AND {2}.information_id = {1}.information_id
+                    WHERE {0}.stop_id = ?1 AND {0}.arrival_time >= ?2 AND {1}.information_id = ?3 AND SUBSTR(days, ?4 + 1, 1) = '1'
+                    AND {0}.sequence > 1
+                    AND ({0}.arrival_time < {1}.departure_time) = ?6
+                    ORDER BY {0}.arrival_time LIMIT ?5",
+                    TripStop::TABLE_NAME,
+                    Trip::TABLE_NAME,
+                    Bitfield::TABLE_NAME
+                )
+                .as_str(),
+            )
+            .bind(filter.stop_id)
+            .bind(filter.from)
+            .bind(filter.information_id)
+            .bind(filter.day_index)
+            .bind(filter.limit)
+            .bind(filter.past_midnight),
+        )
+        .await
+    }
+}
+
+#[async_trait]
+impl DirectionRepository for SqliteDatabase {
+    async fn get_direction(&self, id: i64) -> Result<Direction, RepositoryError> {
+        self.get_one::<Direction>(
+            sqlx::query_as::<_, Direction>(
+                format!("SELECT * FROM {} WHERE id=?", Direction::TABLE_NAME).as_str(),
+            )
+            .bind(id),
+        )
+        .await
+    }
+
+    async fn get_direction_legs(&self, direction_id: i64) -> Result<Vec<DirectionLeg>, RepositoryError> {
+        self.get_many::<DirectionLeg>(
+            sqlx::query_as::<_, DirectionLeg>(
+                format!("SELECT * FROM {} WHERE direction_id=?", DirectionLeg::TABLE_NAME).as_str(),
+            )
+            .bind(direction_id),
+        )
+        .await
+    }
+
+    async fn get_leg(&self, id: i64) -> Result<DirectionLeg, RepositoryError> {
+        self.get_one::<DirectionLeg>(
+            sqlx::query_as::<_, DirectionLeg>(
+                format!("SELECT * FROM {} WHERE id=?", DirectionLeg::TABLE_NAME).as_str(),
+            )
+            .bind(id),
+        )
+        .await
+    }
+
+    async fn get_leg_steps(&self, leg_id: i64) -> Result<Vec<LegStep>, RepositoryError> {
+        self.get_many::<LegStep>(
+            sqlx::query_as::<_, LegStep>(
+                format!("SELECT * FROM {} WHERE leg_id=?", LegStep::TABLE_NAME).as_str(),
+            )
+            .bind(leg_id),
+        )
+        .await
+    }
+
+    // sqlite binds no arrays, the ids go in as a json array
+    async fn get_directions_legs(&self, direction_ids: &[i64]) -> Result<Vec<DirectionLeg>, RepositoryError> {
+        self.get_many::<DirectionLeg>(
+            sqlx::query_as::<_, DirectionLeg>(
+                format!(
+                    "SELECT * FROM {} WHERE direction_id IN (SELECT value FROM json_each(?))",
+                    DirectionLeg::TABLE_NAME
+                )
+                .as_str(),
+            )
+            .bind(serde_json::to_string(direction_ids).unwrap()),
+        )
+        .await
+    }
+
+    async fn get_legs_steps(&self, leg_ids: &[i64]) -> Result<Vec<LegStep>, RepositoryError> {
+        self.get_many::<LegStep>(
+            sqlx::query_as::<_, LegStep>(
+                format!("SELECT * FROM {} WHERE leg_id IN (SELECT value FROM json_each(?))", LegStep::TABLE_NAME)
+                    .as_str(),
+            )
+            .bind(serde_json::to_string(leg_ids).unwrap()),
+        )
+        .await
+    }
+}
+
+#[async_trait]
+impl ShapeRepository for SqliteDatabase {
+    async fn get_shape(&self, id: i64) -> Result<Shape, RepositoryError> {
+        self.get_one::<Shape>(
+            sqlx::query_as::<_, Shape>(
+                format!("SELECT * FROM {} WHERE id=?", Shape::TABLE_NAME).as_str(),
+            )
+            .bind(id),
+        )
+        .await
+    }
+
+    async fn get_shape_points(&self, shape_id: i64) -> Result<Vec<ShapePoint>, RepositoryError> {
+        self.get_many::<ShapePoint>(
+            sqlx::query_as::<_, ShapePoint>(
+                format!("SELECT * FROM {} WHERE shape_id=?", ShapePoint::TABLE_NAME).as_str(),
+            )
+            .bind(shape_id),
+        )
+        .await
+    }
+
+    async fn get_shape_stops(&self, shape_id: i64) -> Result<Vec<ShapeStop>, RepositoryError> {
+        self.get_many::<ShapeStop>(
+            sqlx::query_as::<_, ShapeStop>(
+                format!("SELECT * FROM {} WHERE shape_id=?", ShapeStop::TABLE_NAME).as_str(),
+            )
+            .bind(shape_id),
+        )
+        .await
+    }
+}
@@ -0,0 +1,180 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::{NaiveDate, NaiveTime};
+
+use crate::model::{
+    bitfield::Bitfield, direction::Direction, direction_leg::DirectionLeg, information::Information,
+    leg_step::LegStep, line::Line, shape::Shape, shape_point::ShapePoint, shape_stop::ShapeStop,
+    stop::Stop, trip::Trip, trip_stop::TripStop,
+};
+
+use super::{
+    database::{Database, RepositoryError, Table},
+    memory::MemoryDatabase,
+    sqlite::SqliteDatabase,
+};
+
+#[async_trait]
+pub trait LineRepository: Send + Sync {
+    async fn get_lines(&self) -> Result<Vec<Line>, RepositoryError>;
+    async fn get_line(&self, id: i32) -> Result<Line, RepositoryError>;
+}
+
+// wgs84 bounding box, in degrees
+pub struct Bounds {
+    pub min_longitude: f64,
+    pub min_latitude: f64,
+    pub max_longitude: f64,
+    pub max_latitude: f64,
+}
+
+impl Bounds {
+    pub fn contains(&self, latitude: f64, longitude: f64) -> bool {
+        return (self.min_latitude..=self.max_latitude).contains(&latitude)
+            && (self.min_longitude..=self.max_longitude).contains(&longitude);
+    }
+}
+
+#[async_trait]
+pub trait StopRepository: Send + Sync {
+    async fn get_stops(&self) -> Result<Vec<Stop>, RepositoryError>;
+    async fn get_stop(&self, id: i32) -> Result<Stop, RepositoryError>;
+    async fn get_stops_within(&self, bounds: Bounds) -> Result<Vec<Stop>, RepositoryError>;
+    // closest stops first
+    async fn get_nearest_stops(
+        &self,
+        latitude: f64,
+        longitude: f64,
+        limit: i64,
+    ) -> Result<Vec<Stop>, RepositoryError>;
+}
+
+// trips running on the day at `day_index` of their bitfield, see Bitfield::day_index
+#[derive(Clone)]
+pub struct TripFilter {
+    pub information_id: i32,
+    pub day_index: i32,
+    pub departure_from: NaiveTime,
+    pub departure_until: NaiveTime,
+    pub arrival_from: NaiveTime,
+    // trips arriving past midnight only, their arrival is stored without its day and comes before they leave
+    pub past_midnight: bool,
+}
+
+// departures from or arrivals at a stop on the day at `day_index`, the terminus of a trip is no departure
+// and its origin no arrival
+pub struct BoardFilter {
+    pub information_id: i32,
+    pub day_index: i32,
+    pub stop_id: i32,
+    pub from: NaiveTime,
+    pub limit: i64,
+    // stops reached past midnight only, they are stored without their day and come before the trip leaves
+    pub past_midnight: bool,
+}
+
+#[async_trait]
+pub trait TripRepository: Send + Sync {
+    // timetable period covering the date, the most recent one if they overlap
+    async fn get_information(&self, date: NaiveDate) -> Result<Information, RepositoryError>;
+    async fn get_information_by_id(&self, id: i32) -> Result<Information, RepositoryError>;
+    async fn get_bitfield(&self, information_id: i32, id: i32) -> Result<Bitfield, RepositoryError>;
+    async fn get_trips(&self, filter: TripFilter) -> Result<Vec<Trip>, RepositoryError>;
+    async fn get_trip(&self, id: i64) -> Result<Trip, RepositoryError>;
+    async fn get_trip_stops(&self, trip_id: i64) -> Result<Vec<TripStop>, RepositoryError>;
+    // stops of every trip matched by the filter, in one go
+    async fn get_filtered_trip_stops(&self, filter: TripFilter) -> Result<Vec<TripStop>, RepositoryError>;
+    // earliest departures first
+    async fn get_departures(&self, filter: BoardFilter) -> Result<Vec<TripStop>, RepositoryError>;
+    // earliest arrivals first
+    async fn get_arrivals(&self, filter: BoardFilter) -> Result<Vec<TripStop>, RepositoryError>;
+    // dataset served, what is built on the timetable is stale once it changes, none when it cannot change
+    async fn get_dataset(&self) -> Result<Option<String>, RepositoryError> {
+        Ok(None)
+    }
+}
+
+#[async_trait]
+pub trait DirectionRepository: Send + Sync {
+    async fn get_direction(&self, id: i64) -> Result<Direction, RepositoryError>;
+    async fn get_direction_legs(&self, direction_id: i64) -> Result<Vec<DirectionLeg>, RepositoryError>;
+    async fn get_leg(&self, id: i64) -> Result<DirectionLeg, RepositoryError>;
+    async fn get_leg_steps(&self, leg_id: i64) -> Result<Vec<LegStep>, RepositoryError>;
+    // legs of all the directions at once
+    async fn get_directions_legs(&self, direction_ids: &[i64]) -> Result<Vec<DirectionLeg>, RepositoryError>;
+    // steps of all the legs at once
+    async fn get_legs_steps(&self, leg_ids: &[i64]) -> Result<Vec<LegStep>, RepositoryError>;
+}
+
+#[async_trait]
+pub trait ShapeRepository: Send + Sync {
+    async fn get_shape(&self, id: i64) -> Result<Shape, RepositoryError>;
+    async fn get_shape_points(&self, shape_id: i64) -> Result<Vec<ShapePoint>, RepositoryError>;
+    async fn get_shape_stops(&self, shape_id: i64) -> Result<Vec<ShapeStop>, RepositoryError>;
+}
+
+// backend selected by STORAGE_BACKEND, handlers only see the repository traits
+#[derive(Clone)]
+pub enum Storage {
+    Postgres(Database),
+    Sqlite(SqliteDatabase),
+    Memory(MemoryDatabase),
+}
+
+impl Storage {
+    pub async fn insert_many<T>(&self, data: &Vec<T>) -> Result<u64, sqlx::Error>
+    where
+        T: serde::Serialize + Table,
+    {
+        match self {
+            Storage::Postgres(database) => database
+                .insert_many(data)
+                .await
+                .map(|result| result.rows_affected()),
+            Storage::Sqlite(database) => database.insert_many(data).await,
+            // loaded from the hrdf directories at startup, nothing to import into
+            Storage::Memory(_) => Ok(0),
+        }
+    }
+
+    pub fn lines(&self) -> Arc<dyn LineRepository> {
+        match self {
+            Storage::Postgres(database) => Arc::new(database.clone()),
+            Storage::Sqlite(database) => Arc::new(database.clone()),
+            Storage::Memory(database) => Arc::new(database.clone()),
+        }
+    }
+
+    pub fn stops(&self) -> Arc<dyn StopRepository> {
+        match self {
+            Storage::Postgres(database) => Arc::new(database.clone()),
+            Storage::Sqlite(database) => Arc::new(database.clone()),
+            Storage::Memory(database) => Arc::new(database.clone()),
+        }
+    }
+
+    pub fn trips(&self) -> Arc<dyn TripRepository> {
+        match self {
+            Storage::Postgres(database) => Arc::new(database.clone()),
+            Storage::Sqlite(database) => Arc::new(database.clone()),
+            Storage::Memory(database) => Arc::new(database.clone()),
+        }
+    }
+
+    pub fn directions(&self) -> Arc<dyn DirectionRepository> {
+        match self {
+            Storage::Postgres(database) => Arc::new(database.clone()),
+            Storage::Sqlite(database) => Arc::new(database.clone()),
+            Storage::Memory(database) => Arc::new(database.clone()),
+        }
+    }
+
+    pub fn shapes(&self) -> Arc<dyn ShapeRepository> {
+        match self {
+            Storage::Postgres(database) => Arc::new(database.clone()),
+            Storage::Sqlite(database) => Arc::new(database.clone()),
+            Storage::Memory(database) => Arc::new(database.clone()),
+        }
+    }
+}